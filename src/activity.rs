@@ -0,0 +1,417 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Prefix nix puts in front of every structured line with `--log-format internal-json`
+const INTERNAL_JSON_PREFIX: &str = "@nix ";
+
+/// Maximum number of builds listed individually in the summary
+const MAX_SUMMARY_BUILDS: usize = 50;
+
+// Activity types from nix's logging.hh (ActivityType)
+const ACT_FILE_TRANSFER: u64 = 101;
+const ACT_COPY_PATHS: u64 = 103;
+const ACT_BUILDS: u64 = 104;
+const ACT_BUILD: u64 = 105;
+const ACT_SUBSTITUTE: u64 = 108;
+
+// Result types from nix's logging.hh (ResultType)
+const RES_BUILD_LOG_LINE: u64 = 101;
+const RES_SET_PHASE: u64 = 104;
+const RES_PROGRESS: u64 = 105;
+const RES_POST_BUILD_LOG_LINE: u64 = 107;
+
+/// A single build seen in the activity stream
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct BuildActivity {
+    pub drv_path: String,
+    /// Last phase reported by the builder (e.g. "buildPhase")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phase: Option<String>,
+    /// Whether nix reported the build activity as stopped
+    pub done: bool,
+}
+
+/// Summary of the activities nix reported while running a command
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct ActivitySummary {
+    /// Builds in the order they started (capped at 50 entries)
+    pub builds: Vec<BuildActivity>,
+    pub builds_started: usize,
+    pub builds_done: usize,
+    pub substitutions: usize,
+    pub downloads: usize,
+    /// Total builds nix expected to perform, if it reported one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_builds: Option<u64>,
+    /// Total paths nix expected to copy/substitute, if it reported one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_copies: Option<u64>,
+    /// Descriptions of activities that were still running when output ended
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub in_flight: Vec<String>,
+}
+
+/// A progress update derived from the activity stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressUpdate {
+    /// Monotonically increasing count of observed activity events
+    pub progress: u64,
+    pub message: String,
+}
+
+#[derive(Debug)]
+struct Activity {
+    activity_type: u64,
+    text: String,
+    drv_path: Option<String>,
+    phase: Option<String>,
+}
+
+/// Incremental parser for `nix --log-format internal-json` stderr.
+///
+/// Feeds one stderr line at a time, keeping track of running activities and
+/// reconstructing the human-readable log that nix would otherwise have printed.
+#[derive(Debug)]
+pub struct ActivityTracker {
+    render_build_logs: bool,
+    activities: HashMap<u64, Activity>,
+    summary: ActivitySummary,
+    build_index: HashMap<u64, usize>,
+    events: u64,
+    text: String,
+}
+
+impl ActivityTracker {
+    pub fn new(render_build_logs: bool) -> Self {
+        ActivityTracker {
+            render_build_logs,
+            activities: HashMap::new(),
+            summary: ActivitySummary::default(),
+            build_index: HashMap::new(),
+            events: 0,
+            text: String::new(),
+        }
+    }
+
+    /// Process one stderr line, returning a progress update if the line
+    /// changed the state of a build, substitution or download.
+    pub fn process_line(&mut self, line: &str) -> Option<ProgressUpdate> {
+        let Some(json) = line.strip_prefix(INTERNAL_JSON_PREFIX) else {
+            self.push_text(line);
+            return None;
+        };
+
+        let event: Value = match serde_json::from_str(json) {
+            Ok(v) => v,
+            Err(_) => {
+                self.push_text(line);
+                return None;
+            }
+        };
+
+        match event.get("action").and_then(|v| v.as_str()) {
+            Some("msg") => {
+                if let Some(msg) = event.get("msg").and_then(|v| v.as_str()) {
+                    self.push_text(msg);
+                }
+                None
+            }
+            Some("start") => self.handle_start(&event),
+            Some("stop") => self.handle_stop(&event),
+            Some("result") => self.handle_result(&event),
+            _ => None,
+        }
+    }
+
+    /// Text reconstructed so far
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Consume the tracker, returning the reconstructed stderr and the summary
+    pub fn finish(mut self) -> (String, ActivitySummary) {
+        let mut in_flight: Vec<(u64, String)> = self
+            .activities
+            .iter()
+            .filter(|(_, a)| is_tracked(a.activity_type) && !a.text.is_empty())
+            .map(|(id, a)| (*id, a.text.clone()))
+            .collect();
+        in_flight.sort_by_key(|(id, _)| *id);
+        self.summary.in_flight = in_flight.into_iter().map(|(_, t)| t).collect();
+        (self.text, self.summary)
+    }
+
+    fn handle_start(&mut self, event: &Value) -> Option<ProgressUpdate> {
+        let id = event.get("id").and_then(|v| v.as_u64())?;
+        let activity_type = event.get("type").and_then(|v| v.as_u64()).unwrap_or(0);
+        let text = event
+            .get("text")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let level = event.get("level").and_then(|v| v.as_u64()).unwrap_or(0);
+
+        // nix prints activity descriptions at info level and below
+        if !text.is_empty() && level <= 3 {
+            self.push_text(&text);
+        }
+
+        let drv_path = if activity_type == ACT_BUILD {
+            first_field_str(event)
+        } else {
+            None
+        };
+
+        match activity_type {
+            ACT_BUILD => {
+                self.summary.builds_started += 1;
+                if self.summary.builds.len() < MAX_SUMMARY_BUILDS {
+                    if let Some(ref path) = drv_path {
+                        self.build_index.insert(id, self.summary.builds.len());
+                        self.summary.builds.push(BuildActivity {
+                            drv_path: path.clone(),
+                            phase: None,
+                            done: false,
+                        });
+                    }
+                }
+            }
+            ACT_SUBSTITUTE => self.summary.substitutions += 1,
+            ACT_FILE_TRANSFER => self.summary.downloads += 1,
+            _ => {}
+        }
+
+        self.activities.insert(
+            id,
+            Activity {
+                activity_type,
+                text,
+                drv_path,
+                phase: None,
+            },
+        );
+
+        if is_tracked(activity_type) {
+            self.progress_update()
+        } else {
+            None
+        }
+    }
+
+    fn handle_stop(&mut self, event: &Value) -> Option<ProgressUpdate> {
+        let id = event.get("id").and_then(|v| v.as_u64())?;
+        let activity = self.activities.remove(&id)?;
+
+        if activity.activity_type == ACT_BUILD {
+            self.summary.builds_done += 1;
+            if let Some(&idx) = self.build_index.get(&id) {
+                self.summary.builds[idx].done = true;
+            }
+        }
+
+        if is_tracked(activity.activity_type) {
+            self.progress_update()
+        } else {
+            None
+        }
+    }
+
+    fn handle_result(&mut self, event: &Value) -> Option<ProgressUpdate> {
+        let id = event.get("id").and_then(|v| v.as_u64())?;
+        let result_type = event.get("type").and_then(|v| v.as_u64()).unwrap_or(0);
+
+        match result_type {
+            RES_BUILD_LOG_LINE | RES_POST_BUILD_LOG_LINE => {
+                if self.render_build_logs {
+                    if let Some(line) = first_field_str(event) {
+                        let prefix = self
+                            .activities
+                            .get(&id)
+                            .and_then(|a| a.drv_path.as_deref())
+                            .map(drv_name);
+                        match prefix {
+                            Some(name) => self.push_text(&format!("{}> {}", name, line)),
+                            None => self.push_text(&line),
+                        }
+                    }
+                }
+                None
+            }
+            RES_SET_PHASE => {
+                let phase = first_field_str(event)?;
+                if let Some(&idx) = self.build_index.get(&id) {
+                    self.summary.builds[idx].phase = Some(phase.clone());
+                }
+                let activity = self.activities.get_mut(&id)?;
+                activity.phase = Some(phase);
+                self.progress_update()
+            }
+            RES_PROGRESS => {
+                let activity_type = self.activities.get(&id)?.activity_type;
+                let expected = event
+                    .get("fields")
+                    .and_then(|f| f.get(1))
+                    .and_then(|v| v.as_u64());
+                match activity_type {
+                    ACT_BUILDS => self.summary.expected_builds = expected,
+                    ACT_COPY_PATHS => self.summary.expected_copies = expected,
+                    _ => {}
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn progress_update(&mut self) -> Option<ProgressUpdate> {
+        self.events += 1;
+
+        let mut message = match self.summary.expected_builds {
+            Some(expected) if expected > 0 => {
+                format!("[{}/{} built]", self.summary.builds_done, expected)
+            }
+            _ => format!("[{} built]", self.summary.builds_done),
+        };
+
+        // Describe the most recently started activity that is still running
+        let latest = self
+            .activities
+            .iter()
+            .filter(|(_, a)| is_tracked(a.activity_type))
+            .max_by_key(|(id, _)| **id)
+            .map(|(_, a)| a);
+
+        if let Some(activity) = latest {
+            let description = match (&activity.drv_path, &activity.phase) {
+                (Some(drv), Some(phase)) => format!("building {} ({})", drv_name(drv), phase),
+                (Some(drv), None) => format!("building {}", drv_name(drv)),
+                _ => activity.text.clone(),
+            };
+            if !description.is_empty() {
+                message.push(' ');
+                message.push_str(&description);
+            }
+        }
+
+        Some(ProgressUpdate {
+            progress: self.events,
+            message,
+        })
+    }
+
+    fn push_text(&mut self, line: &str) {
+        if !self.text.is_empty() {
+            self.text.push('\n');
+        }
+        self.text.push_str(line);
+    }
+}
+
+fn is_tracked(activity_type: u64) -> bool {
+    matches!(
+        activity_type,
+        ACT_BUILD | ACT_SUBSTITUTE | ACT_FILE_TRANSFER
+    )
+}
+
+fn first_field_str(event: &Value) -> Option<String> {
+    event
+        .get("fields")
+        .and_then(|f| f.get(0))
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+}
+
+/// Extract the derivation name from a store path, e.g.
+/// `/nix/store/<hash>-hello-2.12.drv` -> `hello-2.12`
+pub fn drv_name(drv_path: &str) -> &str {
+    let base = drv_path.rsplit('/').next().unwrap_or(drv_path);
+    let base = base.strip_suffix(".drv").unwrap_or(base);
+    match base.split_once('-') {
+        Some((hash, name)) if hash.len() == 32 => name,
+        _ => base,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRV: &str = "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.drv";
+
+    fn start_build(id: u64) -> String {
+        format!(
+            r#"@nix {{"action":"start","id":{},"level":3,"type":105,"text":"building '{}'","fields":["{}","",1,1],"parent":0}}"#,
+            id, DRV, DRV
+        )
+    }
+
+    #[test]
+    fn test_plain_lines_pass_through() {
+        let mut tracker = ActivityTracker::new(true);
+        assert!(tracker.process_line("warning: Git tree is dirty").is_none());
+        let (text, summary) = tracker.finish();
+        assert_eq!(text, "warning: Git tree is dirty");
+        assert_eq!(summary.builds_started, 0);
+    }
+
+    #[test]
+    fn test_build_lifecycle() {
+        let mut tracker = ActivityTracker::new(true);
+
+        let update = tracker.process_line(&start_build(7)).unwrap();
+        assert_eq!(update.progress, 1);
+        assert!(update.message.contains("building hello-2.12"));
+
+        let update = tracker
+            .process_line(r#"@nix {"action":"result","id":7,"type":104,"fields":["buildPhase"]}"#)
+            .unwrap();
+        assert!(update.message.contains("(buildPhase)"));
+
+        tracker.process_line(r#"@nix {"action":"result","id":7,"type":101,"fields":["make: ok"]}"#);
+        tracker.process_line(r#"@nix {"action":"stop","id":7}"#);
+
+        let (text, summary) = tracker.finish();
+        assert!(text.contains(&format!("building '{}'", DRV)));
+        assert!(text.contains("hello-2.12> make: ok"));
+        assert_eq!(summary.builds_started, 1);
+        assert_eq!(summary.builds_done, 1);
+        assert_eq!(summary.builds[0].phase.as_deref(), Some("buildPhase"));
+        assert!(summary.builds[0].done);
+        assert!(summary.in_flight.is_empty());
+    }
+
+    #[test]
+    fn test_build_logs_suppressed() {
+        let mut tracker = ActivityTracker::new(false);
+        tracker.process_line(&start_build(1));
+        tracker.process_line(r#"@nix {"action":"result","id":1,"type":101,"fields":["noisy"]}"#);
+        let (text, _) = tracker.finish();
+        assert!(!text.contains("noisy"));
+    }
+
+    #[test]
+    fn test_msg_and_expected_builds() {
+        let mut tracker = ActivityTracker::new(true);
+        tracker.process_line(
+            r#"@nix {"action":"start","id":2,"level":5,"type":104,"text":"","fields":[],"parent":0}"#,
+        );
+        tracker.process_line(r#"@nix {"action":"result","id":2,"type":105,"fields":[0,3,0,0]}"#);
+        let update = tracker.process_line(&start_build(3)).unwrap();
+        assert!(update.message.starts_with("[0/3 built]"));
+
+        tracker.process_line(r#"@nix {"action":"msg","level":0,"msg":"error: build failed"}"#);
+        let (text, summary) = tracker.finish();
+        assert!(text.ends_with("error: build failed"));
+        assert_eq!(summary.expected_builds, Some(3));
+        assert_eq!(summary.in_flight.len(), 1);
+    }
+
+    #[test]
+    fn test_drv_name() {
+        assert_eq!(drv_name(DRV), "hello-2.12");
+        assert_eq!(drv_name("hello"), "hello");
+    }
+}
@@ -0,0 +1,101 @@
+mod process;
+mod replay;
+
+pub use process::ProcessBackend;
+pub use replay::{RecordingBackend, ReplayBackend};
+
+use crate::context;
+use crate::nix_runner::NixError;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// A single external command to run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Extra environment variables. Never written to fixtures since they
+    /// carry auth tokens.
+    #[serde(skip)]
+    pub env: Vec<(String, String)>,
+    #[serde(skip)]
+    pub timeout_secs: u64,
+}
+
+impl Invocation {
+    pub fn new(program: &str, args: &[&str], timeout_secs: u64) -> Self {
+        Invocation {
+            program: program.to_string(),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            cwd: None,
+            env: Vec::new(),
+            timeout_secs,
+        }
+    }
+
+    pub fn command_line(&self) -> String {
+        std::iter::once(self.program.as_str())
+            .chain(self.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// What a command produced, apart from stderr
+#[derive(Debug, Clone)]
+pub struct RawOutput {
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+}
+
+impl RawOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Which output stream a line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// Receives a command's output one line at a time
+pub type OutputSink<'a> = dyn FnMut(Stream, &str) + Send + 'a;
+
+/// Runs the external commands (nix, fh, cachix) behind every tool.
+///
+/// Implementations feed each output line to `on_line` as it is produced so
+/// callers can report progress while the command runs. Stdout is also
+/// returned whole in [`RawOutput`]; stderr is only delivered line by line.
+#[async_trait]
+pub trait CommandBackend: Send + Sync + Debug {
+    async fn run(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut OutputSink<'_>,
+    ) -> Result<RawOutput, NixError>;
+}
+
+/// The backend for the current request, falling back to real processes
+pub fn current() -> Arc<dyn CommandBackend> {
+    context::current()
+        .and_then(|ctx| ctx.backend)
+        .unwrap_or_else(|| Arc::new(ProcessBackend))
+}
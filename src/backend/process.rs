@@ -0,0 +1,185 @@
+use super::{CommandBackend, Invocation, OutputSink, RawOutput, Stream};
+use crate::background;
+use crate::context;
+use crate::nix_runner::NixError;
+use async_trait::async_trait;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+/// Runs commands as real child processes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessBackend;
+
+#[async_trait]
+impl CommandBackend for ProcessBackend {
+    async fn run(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut OutputSink<'_>,
+    ) -> Result<RawOutput, NixError> {
+        let mut cmd = Command::new(&invocation.program);
+        cmd.args(&invocation.args);
+
+        if let Some(dir) = &invocation.cwd {
+            cmd.current_dir(dir);
+        }
+
+        for (key, value) in &invocation.env {
+            cmd.env(key, value);
+        }
+
+        run_process(cmd, invocation, on_line).await
+    }
+}
+
+/// Spawn `cmd`, feeding each output line to `on_line` as it arrives.
+///
+/// The child is killed if the timeout expires or the current request is
+/// cancelled by the client.
+async fn run_process(
+    mut cmd: Command,
+    invocation: &Invocation,
+    on_line: &mut OutputSink<'_>,
+) -> Result<RawOutput, NixError> {
+    cmd.kill_on_drop(true);
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let mut child = cmd.spawn()?;
+    let task_id = context::current().and_then(|ctx| ctx.task_id);
+    if let (Some(pid), Some(task_id)) = (child.id(), task_id) {
+        background::record_pid(&task_id, pid);
+    }
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    // Both readers report through the same sink, one at a time
+    let on_line = std::sync::Mutex::new(on_line);
+
+    let run = async {
+        let read_stdout = async {
+            let mut reader = BufReader::new(stdout);
+            let mut buf = Vec::new();
+            loop {
+                let start = buf.len();
+                if reader.read_until(b'\n', &mut buf).await? == 0 {
+                    break;
+                }
+                let text = String::from_utf8_lossy(&buf[start..]);
+                (on_line.lock().unwrap())(Stream::Stdout, text.trim_end_matches(['\n', '\r']));
+            }
+            Ok::<_, std::io::Error>(buf)
+        };
+
+        let read_stderr = async {
+            let mut reader = BufReader::new(stderr);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if reader.read_until(b'\n', &mut line).await? == 0 {
+                    break;
+                }
+                let text = String::from_utf8_lossy(&line);
+                (on_line.lock().unwrap())(Stream::Stderr, text.trim_end_matches(['\n', '\r']));
+            }
+            Ok::<(), std::io::Error>(())
+        };
+
+        let (stdout_buf, stderr_result) = tokio::join!(read_stdout, read_stderr);
+        stderr_result?;
+        let status = child.wait().await?;
+        Ok::<_, std::io::Error>((status, stdout_buf?))
+    };
+
+    let outcome = tokio::select! {
+        result = timeout(Duration::from_secs(invocation.timeout_secs), run) => Some(result),
+        _ = context::cancelled() => None,
+    };
+
+    match outcome {
+        Some(Ok(result)) => {
+            let (status, stdout_buf) = result?;
+            Ok(RawOutput {
+                exit_code: status.code(),
+                stdout: String::from_utf8_lossy(&stdout_buf).to_string(),
+            })
+        }
+        Some(Err(_)) => {
+            let _ = child.kill().await;
+            Err(NixError::Timeout {
+                secs: invocation.timeout_secs,
+                command: invocation.command_line(),
+                in_flight: Vec::new(),
+            })
+        }
+        None => {
+            let _ = child.kill().await;
+            Err(NixError::Cancelled)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{CancelToken, RequestContext};
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_streams_output_lines() {
+        let invocation = Invocation::new("sh", &["-c", "echo out; echo one >&2; echo two >&2"], 10);
+        let mut stdout_lines = Vec::new();
+        let mut stderr_lines = Vec::new();
+
+        let output = ProcessBackend
+            .run(&invocation, &mut |stream, line: &str| match stream {
+                Stream::Stdout => stdout_lines.push(line.to_string()),
+                Stream::Stderr => stderr_lines.push(line.to_string()),
+            })
+            .await
+            .unwrap();
+
+        assert!(output.success());
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(stdout_lines, vec!["out"]);
+        assert_eq!(stderr_lines, vec!["one", "two"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_kills_running_command() {
+        let token = CancelToken::new();
+        let ctx = RequestContext {
+            cancel: Some(token.clone()),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel();
+        });
+
+        let invocation = Invocation::new("sleep", &["30"], 60);
+        let result =
+            context::scope(ctx, ProcessBackend.run(&invocation, &mut |_, _: &str| {})).await;
+
+        assert!(matches!(result, Err(NixError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_names_command() {
+        let invocation = Invocation::new("sleep", &["30"], 1);
+        let err = ProcessBackend
+            .run(&invocation, &mut |_, _: &str| {})
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "`sleep 30` timed out after 1 seconds");
+    }
+}
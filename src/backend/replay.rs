@@ -0,0 +1,291 @@
+use super::{CommandBackend, Invocation, OutputSink, RawOutput, Stream};
+use crate::nix_runner::NixError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A recorded invocation and everything it produced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    #[serde(flatten)]
+    pub invocation: Invocation,
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub stdout: String,
+    /// Stderr, one entry per line
+    #[serde(default)]
+    pub stderr: Vec<String>,
+}
+
+impl Fixture {
+    fn matches(&self, invocation: &Invocation) -> bool {
+        // cwd is deliberately ignored so fixtures recorded in one checkout
+        // replay in another
+        self.invocation.program == invocation.program && self.invocation.args == invocation.args
+    }
+
+    /// File name for the `seq`th fixture, e.g. `003-nix-build.json`
+    fn file_name(&self, seq: usize) -> String {
+        let subcommand = self
+            .invocation
+            .args
+            .iter()
+            .find(|a| !a.starts_with('-') && !a.contains('/') && !a.contains('#'))
+            .map(String::as_str)
+            .unwrap_or("");
+
+        let slug: String = format!("{}-{}", self.invocation.program, subcommand)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+
+        format!("{:03}-{}.json", seq, slug.trim_end_matches('-'))
+    }
+}
+
+/// Wraps another backend and writes every invocation to `dir` as a fixture
+#[derive(Debug)]
+pub struct RecordingBackend {
+    inner: Arc<dyn CommandBackend>,
+    dir: PathBuf,
+    seq: AtomicUsize,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Arc<dyn CommandBackend>, dir: impl Into<PathBuf>) -> Self {
+        RecordingBackend {
+            inner,
+            dir: dir.into(),
+            seq: AtomicUsize::new(1),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandBackend for RecordingBackend {
+    async fn run(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut OutputSink<'_>,
+    ) -> Result<RawOutput, NixError> {
+        let mut stderr = Vec::new();
+        let output = self
+            .inner
+            .run(invocation, &mut |stream, line: &str| {
+                if stream == Stream::Stderr {
+                    stderr.push(line.to_string());
+                }
+                on_line(stream, line);
+            })
+            .await?;
+
+        let fixture = Fixture {
+            invocation: invocation.clone(),
+            exit_code: output.exit_code,
+            stdout: output.stdout.clone(),
+            stderr,
+        };
+
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        let path = self.dir.join(fixture.file_name(seq));
+        let write = fs::create_dir_all(&self.dir).and_then(|_| {
+            let json = serde_json::to_string_pretty(&fixture).map_err(std::io::Error::other)?;
+            fs::write(&path, json)
+        });
+        if let Err(e) = write {
+            eprintln!("Warning: failed to record fixture {:?}: {}", path, e);
+        }
+
+        Ok(output)
+    }
+}
+
+/// Answers invocations from fixtures instead of running anything.
+///
+/// Each fixture is used once, in order, so a tool that runs the same command
+/// twice can get different answers. Once all matching fixtures are used the
+/// last one is repeated.
+#[derive(Debug, Default)]
+pub struct ReplayBackend {
+    fixtures: Vec<Fixture>,
+    used: Mutex<Vec<bool>>,
+    calls: Mutex<Vec<Invocation>>,
+}
+
+impl ReplayBackend {
+    pub fn new(fixtures: Vec<Fixture>) -> Self {
+        let used = vec![false; fixtures.len()];
+        ReplayBackend {
+            fixtures,
+            used: Mutex::new(used),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Load every `*.json` fixture in `dir`, in file name order
+    pub fn load(dir: &Path) -> std::io::Result<Self> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        let mut fixtures = Vec::with_capacity(paths.len());
+        for path in paths {
+            let contents = fs::read_to_string(&path)?;
+            let fixture = serde_json::from_str(&contents).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })?;
+            fixtures.push(fixture);
+        }
+
+        Ok(Self::new(fixtures))
+    }
+
+    /// Every invocation seen so far, in order
+    #[cfg(test)]
+    pub fn calls(&self) -> Vec<Invocation> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn take(&self, invocation: &Invocation) -> Option<&Fixture> {
+        let mut used = self.used.lock().unwrap();
+        let mut last_match = None;
+
+        for (i, fixture) in self.fixtures.iter().enumerate() {
+            if !fixture.matches(invocation) {
+                continue;
+            }
+            if !used[i] {
+                used[i] = true;
+                return Some(fixture);
+            }
+            last_match = Some(fixture);
+        }
+
+        last_match
+    }
+}
+
+#[async_trait]
+impl CommandBackend for ReplayBackend {
+    async fn run(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut OutputSink<'_>,
+    ) -> Result<RawOutput, NixError> {
+        self.calls.lock().unwrap().push(invocation.clone());
+
+        let fixture = self.take(invocation).ok_or_else(|| {
+            NixError::CommandFailed(format!(
+                "no fixture recorded for `{}`",
+                invocation.command_line()
+            ))
+        })?;
+
+        for line in &fixture.stderr {
+            on_line(Stream::Stderr, line);
+        }
+        for line in fixture.stdout.lines() {
+            on_line(Stream::Stdout, line);
+        }
+
+        Ok(RawOutput {
+            exit_code: fixture.exit_code,
+            stdout: fixture.stdout.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(args: &[&str], stdout: &str) -> Fixture {
+        Fixture {
+            invocation: Invocation::new("nix", args, 0),
+            exit_code: Some(0),
+            stdout: stdout.to_string(),
+            stderr: vec!["warning: Git tree is dirty".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_uses_fixtures_in_order() {
+        let backend = ReplayBackend::new(vec![
+            fixture(&["eval", ".#x"], "1"),
+            fixture(&["eval", ".#x"], "2"),
+        ]);
+        let invocation = Invocation::new("nix", &["eval", ".#x"], 300);
+
+        let mut stderr = Vec::new();
+        let first = backend
+            .run(&invocation, &mut |stream, line: &str| {
+                if stream == Stream::Stderr {
+                    stderr.push(line.to_string());
+                }
+            })
+            .await
+            .unwrap();
+        let second = backend
+            .run(&invocation, &mut |_, _: &str| {})
+            .await
+            .unwrap();
+        let third = backend
+            .run(&invocation, &mut |_, _: &str| {})
+            .await
+            .unwrap();
+
+        assert_eq!(first.stdout, "1");
+        assert_eq!(second.stdout, "2");
+        assert_eq!(third.stdout, "2");
+        assert_eq!(stderr, vec!["warning: Git tree is dirty"]);
+        assert_eq!(backend.calls().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_replay_unknown_invocation_fails() {
+        let backend = ReplayBackend::new(vec![fixture(&["eval", ".#x"], "1")]);
+        let invocation = Invocation::new("nix", &["eval", ".#y"], 300);
+
+        let err = backend
+            .run(&invocation, &mut |_, _: &str| {})
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("nix eval .#y"));
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let dir = std::env::temp_dir().join(format!("chix-fixtures-{}", uuid::Uuid::new_v4()));
+        let inner = Arc::new(ReplayBackend::new(vec![fixture(
+            &["flake", "metadata", "--json"],
+            "{}",
+        )]));
+        let recorder = RecordingBackend::new(inner, &dir);
+
+        let mut invocation = Invocation::new("nix", &["flake", "metadata", "--json"], 300);
+        invocation
+            .env
+            .push(("CACHIX_AUTH_TOKEN".to_string(), "secret".to_string()));
+        recorder
+            .run(&invocation, &mut |_, _: &str| {})
+            .await
+            .unwrap();
+
+        let recorded = fs::read_to_string(dir.join("001-nix-flake.json")).unwrap();
+        assert!(!recorded.contains("secret"));
+
+        let replay = ReplayBackend::load(&dir).unwrap();
+        let output = replay.run(&invocation, &mut |_, _: &str| {}).await.unwrap();
+        assert_eq!(output.stdout, "{}");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
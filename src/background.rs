@@ -1,27 +1,214 @@
-use serde::Serialize;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
-use tokio::process::Child;
+use crate::backend::Stream;
+use crate::config::load_config;
+use crate::context::CancelToken;
+use crate::scheduler::queue_position;
+use crate::task_store::{process_alive, to_unix, StoredTask, TaskLog, TaskRecord, TaskStore};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, PartialEq)]
+/// Output kept in memory per task stream; older lines are only in the
+/// task store's log
+const MAX_BUFFERED_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
 pub enum TaskStatus {
     Running,
     Completed,
     Failed,
 }
 
+/// What a tool call with `background: true` returns straight away
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TaskStarted {
+    pub task_id: String,
+    pub status: TaskStatus,
+    /// The tool and arguments the task runs
+    pub command: String,
+    /// Subscribe to this resource to hear when the task changes
+    pub resource_uri: String,
+}
+
 #[derive(Debug)]
 pub struct BackgroundTaskHandle {
     pub id: String,
     pub command: String,
     pub status: TaskStatus,
-    pub started_at: Instant,
-    pub child: Option<Child>,
+    pub started_at: SystemTime,
+    pub finished_at: Option<SystemTime>,
+    /// Cancelling this kills whatever the task is running
+    pub cancel: CancelToken,
+    /// The chix process that ran the task, which may be an earlier instance
+    pub server_pid: u32,
+    /// The last command the task spawned
+    pub pid: Option<u32>,
     pub exit_code: Option<i32>,
-    pub stdout: String,
-    pub stderr: String,
+    pub stdout: OutputBuffer,
+    pub stderr: OutputBuffer,
+    /// The tool's structured result once it has finished
+    pub result: Option<Value>,
+    /// Why the tool failed, if it returned an error instead of a result
+    pub error: Option<String>,
+    /// Bumped whenever the task's output or status changes
+    pub updates: watch::Sender<u64>,
+    /// Where output goes on disk while the task runs
+    log: Option<Arc<TaskLog>>,
+}
+
+impl BackgroundTaskHandle {
+    fn info(&self) -> TaskInfo {
+        let finished = self.finished_at.unwrap_or_else(SystemTime::now);
+        TaskInfo {
+            id: self.id.clone(),
+            command: self.command.clone(),
+            status: self.status.clone(),
+            elapsed_secs: finished
+                .duration_since(self.started_at)
+                .unwrap_or_default()
+                .as_secs(),
+            exit_code: self.exit_code,
+            error: self.error.clone(),
+            queue_position: match self.status {
+                TaskStatus::Running => queue_position(&self.id),
+                _ => None,
+            },
+        }
+    }
+
+    fn record(&self) -> TaskRecord {
+        TaskRecord {
+            id: self.id.clone(),
+            command: self.command.clone(),
+            status: self.status.clone(),
+            started_at: to_unix(self.started_at),
+            finished_at: self.finished_at.map(to_unix),
+            exit_code: self.exit_code,
+            server_pid: self.server_pid,
+            pid: self.pid,
+            result: self.result.clone(),
+            error: self.error.clone(),
+        }
+    }
+
+    fn from_stored(stored: StoredTask) -> Self {
+        let record = stored.record;
+        let from_unix = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        BackgroundTaskHandle {
+            id: record.id,
+            command: record.command,
+            status: record.status,
+            started_at: from_unix(record.started_at),
+            finished_at: record.finished_at.map(from_unix),
+            cancel: CancelToken::new(),
+            server_pid: record.server_pid,
+            pid: record.pid,
+            exit_code: record.exit_code,
+            stdout: OutputBuffer::from_text(&stored.stdout),
+            stderr: OutputBuffer::from_text(&stored.stderr),
+            result: record.result,
+            error: record.error,
+            updates: watch::Sender::new(0),
+            log: None,
+        }
+    }
+
+    /// Mark a task whose server went away before it finished.
+    ///
+    /// Such a task can't be reattached to even if its command is still
+    /// running: the command's stdout and stderr were pipes into the old
+    /// server, the structured result was the old server's to build, and
+    /// after a restart the pid may already belong to an unrelated process,
+    /// so it isn't safe to signal. The task is reported as failed instead.
+    fn interrupt(&mut self) {
+        let detail = match self.pid {
+            Some(pid) if process_alive(pid) => {
+                format!("; its last command (pid {}) may still be running", pid)
+            }
+            _ => String::new(),
+        };
+        self.status = TaskStatus::Failed;
+        self.finished_at = Some(SystemTime::now());
+        self.error = Some(format!("interrupted by a server restart{}", detail));
+    }
+}
+
+/// The most recent lines of one task stream, capped at `MAX_BUFFERED_BYTES`
+#[derive(Debug, Default)]
+pub struct OutputBuffer {
+    /// Lines with their trailing newline
+    lines: VecDeque<String>,
+    bytes: usize,
+    /// Lines and bytes dropped from the front to stay under the cap
+    dropped_lines: usize,
+    dropped_bytes: usize,
+}
+
+impl OutputBuffer {
+    fn from_text(text: &str) -> Self {
+        let mut buffer = OutputBuffer::default();
+        text.lines().for_each(|line| buffer.push(line));
+        buffer
+    }
+
+    fn push(&mut self, line: &str) {
+        self.bytes += line.len() + 1;
+        self.lines.push_back(format!("{}\n", line));
+        while self.bytes > MAX_BUFFERED_BYTES && self.lines.len() > 1 {
+            let dropped = self.lines.pop_front().unwrap_or_default();
+            self.bytes -= dropped.len();
+            self.dropped_lines += 1;
+            self.dropped_bytes += dropped.len();
+        }
+    }
+
+    /// Lines written to the stream so far, including dropped ones
+    pub fn total_lines(&self) -> usize {
+        self.dropped_lines + self.lines.len()
+    }
+
+    /// Bytes written to the stream so far, including dropped ones
+    pub fn total_bytes(&self) -> usize {
+        self.dropped_bytes + self.bytes
+    }
+
+    /// Buffered output from byte `offset` of the stream on, and the offset
+    /// the text starts at
+    pub fn text_from(&self, offset: usize) -> (usize, String) {
+        let first = offset.max(self.dropped_bytes);
+        let mut text = String::new();
+        let mut position = self.dropped_bytes;
+        for line in &self.lines {
+            let end = position + line.len();
+            if end > first {
+                text.push_str(&line[ceil_char_boundary(line, first.saturating_sub(position))..]);
+            }
+            position = end;
+        }
+        (first.min(position), text)
+    }
+}
+
+/// The first char boundary of `s` at or after `index`
+fn ceil_char_boundary(s: &str, index: usize) -> usize {
+    (index..s.len())
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(s.len())
+}
+
+/// Output of a task stream from some byte offset on
+#[derive(Debug)]
+pub struct OutputPage {
+    pub status: TaskStatus,
+    /// Byte offset in the stream of the start of `text`
+    pub first_byte: usize,
+    pub total_bytes: usize,
+    pub total_lines: usize,
+    pub text: String,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -31,70 +218,317 @@ pub struct TaskInfo {
     pub status: TaskStatus,
     pub elapsed_secs: u64,
     pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Position in the build queue while waiting for a heavy job slot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
 }
 
+static TASK_STORE: LazyLock<Option<TaskStore>> = LazyLock::new(TaskStore::from_config);
+
 lazy_static::lazy_static! {
     static ref BACKGROUND_TASKS: Arc<Mutex<HashMap<String, BackgroundTaskHandle>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+        Arc::new(Mutex::new(restore_tasks()));
+}
+
+/// Load tasks left behind by earlier server instances and prune old ones
+pub fn init() {
+    lazy_static::initialize(&BACKGROUND_TASKS);
+}
+
+fn restore_tasks() -> HashMap<String, BackgroundTaskHandle> {
+    let Some(store) = TASK_STORE.as_ref() else {
+        return HashMap::new();
+    };
+    store.prune(load_config().tasks.retention());
+
+    let mut tasks = HashMap::new();
+    for stored in store.load_all() {
+        let record = &stored.record;
+        // Still being run by another chix instance sharing the state dir
+        if record.status == TaskStatus::Running
+            && record.server_pid != std::process::id()
+            && process_alive(record.server_pid)
+        {
+            continue;
+        }
+
+        let mut handle = BackgroundTaskHandle::from_stored(stored);
+        if handle.status == TaskStatus::Running {
+            handle.interrupt();
+            store.save(&handle.record());
+        }
+        tasks.insert(handle.id.clone(), handle);
+    }
+    tasks
+}
+
+fn persist(handle: &BackgroundTaskHandle) {
+    if let Some(store) = TASK_STORE.as_ref() {
+        store.save(&handle.record());
+    }
+}
+
+fn notify_update(handle: &BackgroundTaskHandle) {
+    handle.updates.send_modify(|version| *version += 1);
+}
+
+/// Watch a task for changes to its output or status.
+///
+/// The receiver closes once the task is removed from the registry.
+pub fn watch_task(id: &str) -> Option<watch::Receiver<u64>> {
+    let tasks = BACKGROUND_TASKS.lock().unwrap();
+    tasks.get(id).map(|handle| handle.updates.subscribe())
+}
+
+/// Drop finished tasks older than the configured retention
+fn expire_tasks(tasks: &mut HashMap<String, BackgroundTaskHandle>) {
+    let retention = load_config().tasks.retention();
+    let now = SystemTime::now();
+    tasks.retain(|_, handle| match handle.finished_at {
+        Some(finished) => now.duration_since(finished).unwrap_or_default() < retention,
+        None => true,
+    });
 }
 
 pub fn generate_task_id() -> String {
     Uuid::new_v4().to_string()
 }
 
-pub fn register_task(id: String, command: String, child: Child) {
+pub fn register_task(id: String, command: String, cancel: CancelToken) {
     let handle = BackgroundTaskHandle {
         id: id.clone(),
         command,
         status: TaskStatus::Running,
-        started_at: Instant::now(),
-        child: Some(child),
+        started_at: SystemTime::now(),
+        finished_at: None,
+        cancel,
+        server_pid: std::process::id(),
+        pid: None,
         exit_code: None,
-        stdout: String::new(),
-        stderr: String::new(),
+        stdout: OutputBuffer::default(),
+        stderr: OutputBuffer::default(),
+        result: None,
+        error: None,
+        updates: watch::Sender::new(0),
+        log: TASK_STORE.as_ref().map(|store| Arc::new(store.log(&id))),
     };
 
+    persist(&handle);
     let mut tasks = BACKGROUND_TASKS.lock().unwrap();
+    expire_tasks(&mut tasks);
     tasks.insert(id, handle);
 }
 
 pub fn get_task_info(id: &str) -> Option<TaskInfo> {
     let tasks = BACKGROUND_TASKS.lock().unwrap();
-    tasks.get(id).map(|handle| TaskInfo {
-        id: handle.id.clone(),
-        command: handle.command.clone(),
-        status: handle.status.clone(),
-        elapsed_secs: handle.started_at.elapsed().as_secs(),
-        exit_code: handle.exit_code,
-    })
+    tasks.get(id).map(BackgroundTaskHandle::info)
 }
 
 pub fn list_tasks() -> Vec<TaskInfo> {
     let tasks = BACKGROUND_TASKS.lock().unwrap();
-    tasks
-        .values()
-        .map(|handle| TaskInfo {
-            id: handle.id.clone(),
-            command: handle.command.clone(),
-            status: handle.status.clone(),
-            elapsed_secs: handle.started_at.elapsed().as_secs(),
-            exit_code: handle.exit_code,
-        })
-        .collect()
-}
-
-pub fn update_task_status(id: &str, status: TaskStatus, exit_code: Option<i32>) {
+    tasks.values().map(BackgroundTaskHandle::info).collect()
+}
+
+/// Remember the process a task is running, so it can be reported on if
+/// the server restarts before it finishes
+pub fn record_pid(id: &str, pid: u32) {
     let mut tasks = BACKGROUND_TASKS.lock().unwrap();
     if let Some(handle) = tasks.get_mut(id) {
-        handle.status = status;
-        handle.exit_code = exit_code;
-        handle.child = None; // Drop the child handle
+        handle.pid = Some(pid);
+        persist(handle);
+    }
+}
+
+/// Append a line of live output to a running task.
+///
+/// The line is written to the task's log after the registry lock is
+/// released, so disk writes don't hold up other tasks.
+pub fn append_output(id: &str, stream: Stream, line: &str) {
+    let log = {
+        let mut tasks = BACKGROUND_TASKS.lock().unwrap();
+        let Some(handle) = tasks.get_mut(id) else {
+            return;
+        };
+        let buffer = match stream {
+            Stream::Stdout => &mut handle.stdout,
+            Stream::Stderr => &mut handle.stderr,
+        };
+        buffer.push(line);
+        notify_update(handle);
+        handle.log.clone()
+    };
+    if let Some(log) = log {
+        log.append(stream, line);
+    }
+}
+
+/// Flush and close a finished task's log, outside the registry lock
+fn close_log(log: Option<Arc<TaskLog>>) {
+    if let Some(log) = log {
+        log.flush();
+    }
+}
+
+/// Record the outcome of a task's tool call.
+///
+/// A tool that returns a result with `"success": false` marks the task
+/// failed, the same as one that returns an error.
+pub fn finish_task(id: &str, outcome: Result<Value, String>) {
+    let mut tasks = BACKGROUND_TASKS.lock().unwrap();
+    let Some(handle) = tasks.get_mut(id) else {
+        return;
+    };
+    // A cancelled task has already been marked failed
+    if handle.status != TaskStatus::Running {
+        return;
+    }
+
+    handle.finished_at = Some(SystemTime::now());
+    match outcome {
+        Ok(result) => {
+            let success = result.get("success").and_then(|v| v.as_bool()) != Some(false);
+            handle.status = if success {
+                TaskStatus::Completed
+            } else {
+                TaskStatus::Failed
+            };
+            handle.exit_code = result
+                .get("exit_code")
+                .and_then(|v| v.as_i64())
+                .and_then(|c| i32::try_from(c).ok());
+            handle.result = Some(result);
+        }
+        Err(e) => {
+            handle.status = TaskStatus::Failed;
+            handle.error = Some(e);
+        }
+    }
+    persist(handle);
+    notify_update(handle);
+    let log = handle.log.take();
+    drop(tasks);
+    close_log(log);
+}
+
+/// The status of a task and up to `max_bytes` of what it has written to
+/// `stream` from byte `offset` on: the first of them, or with `tail` the
+/// last, starting at a line break if there is one.
+///
+/// Output that has left the in-memory buffer is read back from the task
+/// store's log; without a store the page starts at the oldest buffered line.
+pub fn task_output(
+    id: &str,
+    stream: Stream,
+    offset: usize,
+    max_bytes: usize,
+    tail: bool,
+) -> Option<OutputPage> {
+    let (status, total_lines, total_bytes, dropped, start, (first_byte, text)) = {
+        let tasks = BACKGROUND_TASKS.lock().unwrap();
+        let handle = tasks.get(id)?;
+        let buffer = match stream {
+            Stream::Stdout => &handle.stdout,
+            Stream::Stderr => &handle.stderr,
+        };
+        let total_bytes = buffer.total_bytes();
+        let start = match tail {
+            true => offset.max(total_bytes.saturating_sub(max_bytes)),
+            false => offset,
+        };
+        (
+            handle.status.clone(),
+            buffer.total_lines(),
+            total_bytes,
+            buffer.dropped_bytes,
+            start,
+            buffer.text_from(start),
+        )
+    };
+
+    let mut page = OutputPage {
+        status,
+        first_byte,
+        total_bytes,
+        total_lines,
+        text,
+    };
+    if start < dropped {
+        if let Some(store) = TASK_STORE.as_ref() {
+            let len = max_bytes.min(total_bytes - start);
+            page.first_byte = start;
+            page.text = store.read_output(id, stream, start, len);
+        }
+    }
+    if start > offset {
+        let lines = page.text.strip_suffix('\n').unwrap_or(&page.text);
+        if let Some(newline) = lines.find('\n') {
+            page.first_byte += newline + 1;
+            page.text.drain(..=newline);
+        }
     }
+    Some(page)
 }
 
+/// Stop a running task, killing its command and marking it failed
+pub fn cancel_task(id: &str) -> Result<TaskInfo, String> {
+    let mut tasks = BACKGROUND_TASKS.lock().unwrap();
+    let handle = tasks
+        .get_mut(id)
+        .ok_or_else(|| format!("Task not found: {}", id))?;
+    if handle.status != TaskStatus::Running {
+        return Err(format!(
+            "Task {} is not running (status: {:?})",
+            id, handle.status
+        ));
+    }
+
+    handle.cancel.cancel();
+    handle.status = TaskStatus::Failed;
+    handle.finished_at = Some(SystemTime::now());
+    handle.error = Some("cancelled by client".to_string());
+    persist(handle);
+    notify_update(handle);
+    let info = handle.info();
+    let log = handle.log.take();
+    drop(tasks);
+    close_log(log);
+    Ok(info)
+}
+
+/// The structured result of a finished task, or the error it failed with
+pub fn task_result(id: &str) -> Result<Value, String> {
+    let tasks = BACKGROUND_TASKS.lock().unwrap();
+    let handle = tasks
+        .get(id)
+        .ok_or_else(|| format!("Task not found: {}", id))?;
+    if handle.status == TaskStatus::Running {
+        return Err(format!(
+            "Task {} is still running; poll task_status until it finishes",
+            id
+        ));
+    }
+
+    match (&handle.result, &handle.error) {
+        (Some(result), _) => Ok(result.clone()),
+        (None, Some(error)) => Err(error.clone()),
+        (None, None) => Err(format!("Task {} finished without a result", id)),
+    }
+}
+
+/// Forget a task, stopping it first if it is still running
+#[cfg(test)]
 pub fn remove_task(id: &str) -> Option<BackgroundTaskHandle> {
     let mut tasks = BACKGROUND_TASKS.lock().unwrap();
-    tasks.remove(id)
+    let handle = tasks.remove(id)?;
+    if handle.status == TaskStatus::Running {
+        handle.cancel.cancel();
+    }
+    if let Some(store) = TASK_STORE.as_ref() {
+        store.remove(id);
+    }
+    Some(handle)
 }
 
 #[cfg(test)]
@@ -109,6 +543,27 @@ mod tests {
         assert_eq!(id1.len(), 36); // UUID v4 format
     }
 
+    #[test]
+    fn test_output_buffer_drops_oldest_lines() {
+        let mut buffer = OutputBuffer::default();
+        let line = "x".repeat(1023);
+        for _ in 0..1100 {
+            buffer.push(&line);
+        }
+
+        assert_eq!(buffer.total_lines(), 1100);
+        assert_eq!(buffer.total_bytes(), 1100 * 1024);
+        assert!(buffer.bytes <= MAX_BUFFERED_BYTES);
+        assert_eq!(buffer.dropped_lines, 1100 - 1024);
+
+        let (first, text) = buffer.text_from(10);
+        assert_eq!(first, buffer.dropped_bytes);
+        assert_eq!(text.len(), 1024 * 1024);
+        let (first, text) = buffer.text_from(1100 * 1024 - 4);
+        assert_eq!(first, 1100 * 1024 - 4);
+        assert_eq!(text, "xxx\n");
+    }
+
     #[test]
     fn test_task_info_serialization() {
         let info = TaskInfo {
@@ -117,10 +572,117 @@ mod tests {
             status: TaskStatus::Running,
             elapsed_secs: 10,
             exit_code: None,
+            error: None,
+            queue_position: Some(1),
         };
 
         let json = serde_json::to_string(&info).unwrap();
         assert!(json.contains("test-id"));
         assert!(json.contains("Running"));
+        assert!(json.contains("\"queue_position\":1"));
+    }
+
+    #[test]
+    fn test_task_lifecycle() {
+        let id = generate_task_id();
+        register_task(id.clone(), "build .#hello".to_string(), CancelToken::new());
+        append_output(&id, Stream::Stderr, "building hello");
+        append_output(&id, Stream::Stdout, "/nix/store/abc-hello");
+
+        assert_eq!(get_task_info(&id).unwrap().status, TaskStatus::Running);
+
+        finish_task(&id, Ok(serde_json::json!({"success": false})));
+
+        let info = get_task_info(&id).unwrap();
+        assert_eq!(info.status, TaskStatus::Failed);
+
+        let handle = remove_task(&id).unwrap();
+        assert_eq!(handle.stderr.text_from(0).1, "building hello\n");
+        assert_eq!(handle.stdout.text_from(0).1, "/nix/store/abc-hello\n");
+        assert!(handle.result.is_some());
+    }
+
+    #[test]
+    fn test_cancel_task_marks_failed_and_keeps_state() {
+        let id = generate_task_id();
+        let cancel = CancelToken::new();
+        register_task(id.clone(), "build .#hello".to_string(), cancel.clone());
+
+        let info = cancel_task(&id).unwrap();
+        assert!(cancel.is_cancelled());
+        assert_eq!(info.status, TaskStatus::Failed);
+        assert_eq!(info.error.as_deref(), Some("cancelled by client"));
+
+        // The tool returning afterwards doesn't overwrite the cancellation
+        finish_task(&id, Ok(serde_json::json!({"success": true})));
+        assert_eq!(get_task_info(&id).unwrap().status, TaskStatus::Failed);
+        assert_eq!(task_result(&id).unwrap_err(), "cancelled by client");
+
+        assert!(cancel_task(&id).unwrap_err().contains("not running"));
+        remove_task(&id);
+    }
+
+    #[test]
+    fn test_task_result_waits_for_finish() {
+        let id = generate_task_id();
+        register_task(id.clone(), "build .#hello".to_string(), CancelToken::new());
+        assert!(task_result(&id).unwrap_err().contains("still running"));
+
+        finish_task(&id, Ok(serde_json::json!({"success": true, "paths": []})));
+        assert_eq!(task_result(&id).unwrap()["success"], true);
+        remove_task(&id);
+
+        assert!(task_result(&id).unwrap_err().contains("not found"));
+    }
+
+    #[tokio::test]
+    async fn test_watch_task_sees_output_and_finish() {
+        let id = generate_task_id();
+        register_task(id.clone(), "build .#hello".to_string(), CancelToken::new());
+        let mut updates = watch_task(&id).unwrap();
+
+        append_output(&id, Stream::Stderr, "building hello");
+        updates.changed().await.unwrap();
+
+        finish_task(&id, Ok(serde_json::json!({"success": true})));
+        updates.changed().await.unwrap();
+        assert_eq!(*updates.borrow(), 2);
+
+        remove_task(&id);
+        assert!(updates.changed().await.is_err());
+        assert!(watch_task(&id).is_none());
+    }
+
+    #[test]
+    fn test_restored_running_task_is_interrupted() {
+        let stored = StoredTask {
+            record: TaskRecord {
+                id: "task-1".to_string(),
+                command: "build .#hello".to_string(),
+                status: TaskStatus::Running,
+                started_at: 1_700_000_000,
+                finished_at: None,
+                exit_code: None,
+                server_pid: 0,
+                pid: None,
+                result: None,
+                error: None,
+            },
+            stdout: String::new(),
+            stderr: "building hello\n".to_string(),
+        };
+
+        let mut handle = BackgroundTaskHandle::from_stored(stored);
+        assert_eq!(handle.stderr.text_from(0).1, "building hello\n");
+        handle.interrupt();
+
+        let record = handle.record();
+        assert_eq!(record.status, TaskStatus::Failed);
+        assert_eq!(record.started_at, 1_700_000_000);
+        assert!(record.finished_at.is_some());
+        assert_eq!(
+            record.error.as_deref(),
+            Some("interrupted by a server restart")
+        );
     }
 }
@@ -0,0 +1,391 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Rough size of a token in bytes of English text or logs
+const BYTES_PER_TOKEN: usize = 4;
+
+/// Strings shorter than this many tokens are left alone: they are paths,
+/// names and flags the agent needs, and cutting them saves nothing
+const MIN_SHRINKABLE_TOKENS: usize = 64;
+
+/// Room taken by the marker left in a shortened field
+const MARKER_TOKENS: usize = 16;
+
+/// What enforcing the response budget removed from a tool result
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BudgetReport {
+    /// Always true, so the result reads as truncated
+    pub truncated: bool,
+    pub budget_tokens: usize,
+    /// Estimated size of the result before anything was dropped
+    pub original_tokens: usize,
+    /// Estimated size after dropping, which can still be over the budget if
+    /// nothing more could go
+    pub final_tokens: usize,
+    pub dropped: Vec<DroppedText>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dropped_items: Vec<DroppedItems>,
+}
+
+/// A text field shortened to fit the budget
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DroppedText {
+    /// Where the field is, e.g. `commands[2].stdout`
+    pub field: String,
+    pub original_tokens: usize,
+    pub kept_tokens: usize,
+}
+
+/// An array cut short to fit the budget
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DroppedItems {
+    /// Where the array is, e.g. `entries`
+    pub field: String,
+    pub original_items: usize,
+    pub kept_items: usize,
+}
+
+/// A string in the result that can be shortened
+struct TextField {
+    pointer: String,
+    field: String,
+    tokens: usize,
+    /// Keep the end rather than the start: errors are at the end of stderr
+    keep_tail: bool,
+    /// Fields with a lower priority give up their space first
+    priority: u8,
+}
+
+pub fn estimate_tokens(bytes: usize) -> usize {
+    bytes.div_ceil(BYTES_PER_TOKEN)
+}
+
+/// Shrink the text fields of a tool result until it fits in
+/// `budget_tokens`.
+///
+/// Space goes to failing commands before passing ones and to stderr before
+/// other output; within a priority the longest fields are cut first, so
+/// shorter ones survive whole. A result made of many small values, like a
+/// long store listing, then loses items off the end of its largest arrays.
+/// Returns `None` if the result already fit.
+pub fn enforce_budget(value: &mut Value, budget_tokens: usize) -> Option<BudgetReport> {
+    let original_tokens = estimate_tokens(serde_json::to_string(value).ok()?.len());
+    if original_tokens <= budget_tokens {
+        return None;
+    }
+
+    let mut fields = Vec::new();
+    collect_fields(value, "", "", false, &mut fields);
+
+    let mut excess = original_tokens - budget_tokens;
+    let mut dropped = Vec::new();
+    for priority in 0..=3 {
+        if excess == 0 {
+            break;
+        }
+        let group: Vec<&TextField> = fields.iter().filter(|f| f.priority == priority).collect();
+        let sizes: Vec<usize> = group.iter().map(|f| f.tokens).collect();
+        let target = sizes.iter().sum::<usize>().saturating_sub(excess);
+        // Leave room for the marker in every field that gets cut
+        let cut = group
+            .iter()
+            .filter(|f| f.tokens > water_level(sizes.clone(), target))
+            .count();
+        let cap = water_level(sizes, target.saturating_sub(cut * MARKER_TOKENS));
+
+        for field in group.into_iter().filter(|f| f.tokens > cap) {
+            let Some(Value::String(text)) = value.pointer_mut(&field.pointer) else {
+                continue;
+            };
+            *text = shorten(text, cap * BYTES_PER_TOKEN, field.keep_tail);
+            let kept_tokens = estimate_tokens(json_len(text));
+            excess = excess.saturating_sub(field.tokens.saturating_sub(kept_tokens));
+            dropped.push(DroppedText {
+                field: field.field.clone(),
+                original_tokens: field.tokens,
+                kept_tokens,
+            });
+        }
+    }
+
+    let dropped_items = trim_arrays(value, budget_tokens);
+
+    Some(BudgetReport {
+        truncated: true,
+        budget_tokens,
+        original_tokens,
+        final_tokens: tokens_of(value),
+        dropped,
+        dropped_items,
+    })
+}
+
+fn tokens_of(value: &Value) -> usize {
+    estimate_tokens(value.to_string().len())
+}
+
+/// Drop items off the end of the largest arrays until `value` fits in
+/// `budget_tokens` or no array has items left
+fn trim_arrays(value: &mut Value, budget_tokens: usize) -> Vec<DroppedItems> {
+    let mut dropped: Vec<DroppedItems> = Vec::new();
+    loop {
+        let tokens = tokens_of(value);
+        if tokens <= budget_tokens {
+            break;
+        }
+        let mut arrays = Vec::new();
+        collect_arrays(value, "", "", &mut arrays);
+        let Some((pointer, field, array_tokens)) =
+            arrays.into_iter().max_by_key(|(_, _, tokens)| *tokens)
+        else {
+            break;
+        };
+        let Some(Value::Array(items)) = value.pointer_mut(&pointer) else {
+            break;
+        };
+
+        let original_items = items.len();
+        let per_item = array_tokens.div_ceil(original_items).max(1);
+        let drop = (tokens - budget_tokens)
+            .div_ceil(per_item)
+            .min(original_items);
+        items.truncate(original_items - drop);
+        match dropped.iter_mut().find(|d| d.field == field) {
+            Some(entry) => entry.kept_items = items.len(),
+            None => dropped.push(DroppedItems {
+                field,
+                original_items,
+                kept_items: items.len(),
+            }),
+        }
+    }
+    dropped
+}
+
+/// Every non-empty array in `value` with its pointer, field name and size
+fn collect_arrays(
+    value: &Value,
+    pointer: &str,
+    field: &str,
+    out: &mut Vec<(String, String, usize)>,
+) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let child_pointer =
+                    format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                let child_field = if field.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", field, key)
+                };
+                collect_arrays(child, &child_pointer, &child_field, out);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push((pointer.to_string(), field.to_string(), tokens_of(value)));
+            for (i, child) in items.iter().enumerate() {
+                let child_pointer = format!("{}/{}", pointer, i);
+                let child_field = format!("{}[{}]", field, i);
+                collect_arrays(child, &child_pointer, &child_field, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn collect_fields(
+    value: &Value,
+    pointer: &str,
+    field: &str,
+    failing: bool,
+    out: &mut Vec<TextField>,
+) {
+    match value {
+        Value::String(text) => {
+            let tokens = estimate_tokens(json_len(text));
+            if tokens < MIN_SHRINKABLE_TOKENS {
+                return;
+            }
+            let stderr = field.ends_with("stderr");
+            out.push(TextField {
+                pointer: pointer.to_string(),
+                field: field.to_string(),
+                tokens,
+                keep_tail: stderr,
+                priority: u8::from(failing) * 2 + u8::from(stderr),
+            });
+        }
+        Value::Object(map) => {
+            let failing = failing || is_failure(map);
+            for (key, child) in map {
+                let child_pointer =
+                    format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                let child_field = if field.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", field, key)
+                };
+                collect_fields(child, &child_pointer, &child_field, failing, out);
+            }
+        }
+        Value::Array(items) => {
+            for (i, child) in items.iter().enumerate() {
+                let child_pointer = format!("{}/{}", pointer, i);
+                let child_field = format!("{}[{}]", field, i);
+                collect_fields(child, &child_pointer, &child_field, failing, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether an object describes a command that failed
+fn is_failure(map: &serde_json::Map<String, Value>) -> bool {
+    map.get("success") == Some(&Value::Bool(false))
+        || map
+            .get("exit_code")
+            .and_then(Value::as_i64)
+            .is_some_and(|code| code != 0)
+}
+
+/// The largest per-field size that brings `sizes` down to `target` in
+/// total, cutting only the fields above it
+fn water_level(mut sizes: Vec<usize>, target: usize) -> usize {
+    sizes.sort_unstable();
+    let mut remaining = target;
+    for (i, &size) in sizes.iter().enumerate() {
+        let left = sizes.len() - i;
+        if size * left > remaining {
+            return remaining / left;
+        }
+        remaining -= size;
+    }
+    usize::MAX
+}
+
+/// Cut `text` so it serializes to about `max_bytes`, marking how much was
+/// dropped
+fn shorten(text: &str, max_bytes: usize, keep_tail: bool) -> String {
+    let dropped = estimate_tokens(json_len(text).saturating_sub(max_bytes));
+    let marker = format!(
+        "... [{} tokens dropped by the response budget] ...",
+        dropped
+    );
+    let mut room = max_bytes.saturating_sub(marker.len() + 2);
+    let mut fits = |c: char| match room.checked_sub(escaped_len(c)) {
+        Some(left) => {
+            room = left;
+            true
+        }
+        None => false,
+    };
+
+    if keep_tail {
+        let start = text
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| fits(c))
+            .last()
+            .map_or(text.len(), |(i, _)| i);
+        format!("{}\n{}", marker, &text[start..])
+    } else {
+        let end = text
+            .char_indices()
+            .take_while(|&(_, c)| fits(c))
+            .last()
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        format!("{}\n{}", &text[..end], marker)
+    }
+}
+
+/// Length of a string once escaped as JSON, without the quotes
+fn json_len(text: &str) -> usize {
+    text.chars().map(escaped_len).sum()
+}
+
+fn escaped_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{08}' | '\u{0c}' => 2,
+        c if c < ' ' => 6,
+        c => c.len_utf8(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn text(label: &str, tokens: usize) -> String {
+        let line = format!("{} line\n", label);
+        line.repeat(tokens * BYTES_PER_TOKEN / line.len() + 1)
+    }
+
+    #[test]
+    fn test_within_budget_is_untouched() {
+        let mut value = json!({"stdout": text("out", 100)});
+        let before = value.clone();
+        assert!(enforce_budget(&mut value, 1000).is_none());
+        assert_eq!(value, before);
+    }
+
+    #[test]
+    fn test_failing_stderr_is_kept_longest() {
+        let mut value = json!({
+            "commands": [
+                {"success": true, "stdout": text("ok-out", 1000), "stderr": text("ok-err", 1000)},
+                {"success": false, "stdout": text("bad-out", 1000), "stderr": text("bad-err", 1000)},
+            ]
+        });
+        let report = enforce_budget(&mut value, 2500).unwrap();
+
+        let fields: Vec<_> = report.dropped.iter().map(|d| d.field.as_str()).collect();
+        assert_eq!(fields, vec!["commands[0].stdout", "commands[0].stderr"]);
+        assert!(report.dropped[0].kept_tokens <= MARKER_TOKENS);
+        assert_eq!(value["commands"][1]["stderr"], text("bad-err", 1000));
+
+        // stderr keeps its end, where the errors are
+        let kept = value["commands"][0]["stderr"].as_str().unwrap();
+        assert!(kept.starts_with("... ["));
+        assert!(estimate_tokens(serde_json::to_string(&value).unwrap().len()) <= 2500);
+    }
+
+    #[test]
+    fn test_longest_fields_are_cut_first() {
+        let mut value = json!({"a": text("a", 2000), "b": text("b", 200)});
+        let report = enforce_budget(&mut value, 1500).unwrap();
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].field, "a");
+        assert_eq!(value["b"], text("b", 200));
+    }
+
+    #[test]
+    fn test_many_small_entries_are_trimmed() {
+        let entries: Vec<Value> = (0..2000)
+            .map(|i| json!({"name": format!("file-{}", i), "type": "regular"}))
+            .collect();
+        let mut value = json!({"path": "/nix/store/abc-hello", "entries": entries});
+        let report = enforce_budget(&mut value, 1000).unwrap();
+
+        assert!(report.dropped.is_empty());
+        assert_eq!(report.dropped_items.len(), 1);
+        let trimmed = &report.dropped_items[0];
+        assert_eq!(trimmed.field, "entries");
+        assert_eq!(trimmed.original_items, 2000);
+        assert_eq!(
+            value["entries"].as_array().unwrap().len(),
+            trimmed.kept_items
+        );
+        assert_eq!(value["entries"][0]["name"], "file-0");
+        assert!(report.final_tokens <= 1000);
+        assert_eq!(report.final_tokens, tokens_of(&value));
+    }
+
+    #[test]
+    fn test_water_level() {
+        assert_eq!(water_level(vec![10, 50, 100], 160), usize::MAX);
+        assert_eq!(water_level(vec![10, 50, 100], 90), 40);
+        assert_eq!(water_level(vec![10, 50, 100], 0), 0);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::output::strip_ansi;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::LazyLock;
+
+/// Context lines kept around each match when the caller doesn't say
+pub const DEFAULT_ERROR_CONTEXT: usize = 3;
+
+/// Lines of the failing phase kept before nix's error report
+const FAILING_PHASE_TAIL: usize = 20;
+
+/// Longest error block kept; anything past this is usually a huge trace
+const MAX_BLOCK_LINES: usize = 200;
+
+// `nix build -L` prefixes every builder line with `pname> `
+static LOG_PREFIX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[\w.+-]+> ").unwrap());
+
+static COMPILER_ERROR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        // rustc: error[E0308]: mismatched types
+        r"^error(\[E\d+\])?: ",
+        // gcc/clang: src/main.c:12:5: error: ...
+        r"|^[^\s:]+:\d+:(\d+:)? (fatal )?error: ",
+        // go: ./main.go:12:5: undefined: foo
+        r"|^[^\s:]+\.go:\d+:\d+: ",
+        // make: *** [Makefile:12: all] Error 2
+        r"|^make(\[\d+\])?: \*\*\* ",
+    ))
+    .unwrap()
+});
+
+// Lines that continue a compiler diagnostic: rustc's `  --> src/lib.rs:1:1`,
+// `   |` gutters and `12 | code` source excerpts
+static DIAGNOSTIC_CONTINUATION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\s|\d+\s*\|)").unwrap());
+
+static TEST_FAILURE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        // cargo test
+        r"^test result: FAILED|^failures:$|^test \S+ \.\.\. FAILED|panicked at ",
+        // go test
+        r"|^--- FAIL: |^FAIL\b",
+        // pytest
+        r"|^FAILED |^=+ (FAILURES|short test summary info) =+",
+        // jest and friends
+        r"|^Tests?:\s+\d+ failed|^\d+ (tests? )?failed",
+    ))
+    .unwrap()
+});
+
+static RUNNING_PHASE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"Running phase: (\w+)").unwrap());
+
+/// What a kept section of an errors-mode log is
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionKind {
+    /// An `error:` block reported by nix itself
+    NixError,
+    /// A rustc, gcc/clang, go or make error
+    CompilerError,
+    /// A test failure line or summary
+    TestFailure,
+    /// The last lines of the phase the builder failed in
+    FailingPhase,
+}
+
+/// A range of the original log kept in errors mode, 1-based and inclusive
+#[derive(Debug, Clone, Serialize, JsonSchema, PartialEq)]
+pub struct KeptSection {
+    pub kind: SectionKind,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// A log reduced to the parts that explain a failure
+#[derive(Debug, Clone)]
+pub struct Condensed {
+    /// Kept lines, with a marker where lines were left out
+    pub content: String,
+    pub sections: Vec<KeptSection>,
+    /// Lines of the log that were left out
+    pub dropped_lines: usize,
+}
+
+/// Pull the error blocks, compiler errors, test failures and the tail of the
+/// failing phase out of a build log, with `context` lines around each.
+///
+/// Returns `None` when nothing in the log looks like an error, so callers can
+/// fall back to plain head/tail limits.
+pub fn condense_errors(input: &str, context: usize) -> Option<Condensed> {
+    let lines: Vec<&str> = input.lines().collect();
+    // nix colours `error:`, so match against the plain text
+    let plain: Vec<String> = lines.iter().map(|line| strip_ansi(line)).collect();
+    let mut sections: Vec<KeptSection> = Vec::new();
+    let mut first_nix_error = None;
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = plain[i].as_str();
+        let body = LOG_PREFIX.replace(line, "");
+        let has_prefix = body.len() != line.len();
+
+        let kind = if !has_prefix && line.starts_with("error:") {
+            first_nix_error.get_or_insert(i);
+            Some(SectionKind::NixError)
+        } else if COMPILER_ERROR.is_match(&body) {
+            Some(SectionKind::CompilerError)
+        } else if TEST_FAILURE.is_match(&body) {
+            Some(SectionKind::TestFailure)
+        } else {
+            None
+        };
+
+        let Some(kind) = kind else {
+            i += 1;
+            continue;
+        };
+
+        let end = block_end(&plain, i, kind);
+        push_section(&mut sections, kind, i, end);
+        i = end + 1;
+    }
+
+    if sections.is_empty() {
+        return None;
+    }
+
+    // The builder output leading up to nix's report usually shows what broke
+    let failure_start = first_nix_error.unwrap_or(lines.len());
+    if let Some(phase) = plain[..failure_start]
+        .iter()
+        .rposition(|line| RUNNING_PHASE.is_match(line))
+    {
+        let tail_start = failure_start
+            .saturating_sub(FAILING_PHASE_TAIL)
+            .max(phase + 1);
+        push_section(&mut sections, SectionKind::FailingPhase, phase, phase);
+        if tail_start < failure_start {
+            push_section(
+                &mut sections,
+                SectionKind::FailingPhase,
+                tail_start,
+                failure_start - 1,
+            );
+        }
+    }
+    sections.sort_by_key(|s| s.start_line);
+
+    let mut keep = vec![false; lines.len()];
+    for section in &sections {
+        let (start, end) = (section.start_line - 1, section.end_line - 1);
+        let (start, end) = match section.kind {
+            SectionKind::FailingPhase => (start, end),
+            _ => (
+                start.saturating_sub(context),
+                (end + context).min(lines.len() - 1),
+            ),
+        };
+        keep[start..=end].iter_mut().for_each(|k| *k = true);
+    }
+
+    Some(Condensed {
+        content: render(&lines, &keep),
+        sections,
+        dropped_lines: keep.iter().filter(|k| !**k).count(),
+    })
+}
+
+/// Index of the last line belonging to the block that starts at `start`
+fn block_end(lines: &[String], start: usize, kind: SectionKind) -> usize {
+    let continues = |line: &str| match kind {
+        // nix indents everything that belongs to an error
+        SectionKind::NixError => line.starts_with([' ', '\t']),
+        SectionKind::CompilerError => {
+            DIAGNOSTIC_CONTINUATION.is_match(&LOG_PREFIX.replace(line, ""))
+        }
+        SectionKind::TestFailure | SectionKind::FailingPhase => false,
+    };
+
+    let mut end = start;
+    while end + 1 < lines.len() && end - start + 1 < MAX_BLOCK_LINES && continues(&lines[end + 1]) {
+        end += 1;
+    }
+    end
+}
+
+/// Record `start..=end` (0-based), merging it into the previous section of
+/// the same kind when they touch
+fn push_section(sections: &mut Vec<KeptSection>, kind: SectionKind, start: usize, end: usize) {
+    if let Some(last) = sections.last_mut() {
+        if last.kind == kind && start <= last.end_line {
+            last.end_line = last.end_line.max(end + 1);
+            return;
+        }
+    }
+    sections.push(KeptSection {
+        kind,
+        start_line: start + 1,
+        end_line: end + 1,
+    });
+}
+
+fn render(lines: &[&str], keep: &[bool]) -> String {
+    let mut out: Vec<String> = Vec::new();
+    let mut skipped = 0;
+    for (line, &kept) in lines.iter().zip(keep) {
+        if kept {
+            if skipped > 0 {
+                out.push(format!("... {} lines omitted ...", skipped));
+                skipped = 0;
+            }
+            out.push(line.to_string());
+        } else {
+            skipped += 1;
+        }
+    }
+    if skipped > 0 {
+        out.push(format!("... {} lines omitted ...", skipped));
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_log() -> String {
+        let mut lines = vec!["hello> Running phase: unpackPhase".to_string()];
+        lines.extend((0..50).map(|i| format!("hello> unpacking file {}", i)));
+        lines.push("hello> Running phase: buildPhase".to_string());
+        lines.extend((0..50).map(|i| format!("hello> compiling module {}", i)));
+        lines.push("hello> error[E0308]: mismatched types".to_string());
+        lines.push("hello>  --> src/main.rs:4:18".to_string());
+        lines.push("hello>   |".to_string());
+        lines.push("hello> 4 |     let x: u32 = \"hi\";".to_string());
+        lines.extend((0..30).map(|i| format!("hello> more output {}", i)));
+        lines.push(
+            "error: builder for '/nix/store/abc-hello.drv' failed with exit code 101;".to_string(),
+        );
+        lines.push("       last 10 log lines:".to_string());
+        lines.push("       > error: could not compile `hello`".to_string());
+        lines.push("       For full logs, run 'nix log /nix/store/abc-hello.drv'.".to_string());
+        lines.join("\n")
+    }
+
+    #[test]
+    fn test_condense_keeps_errors_and_failing_phase() {
+        let log = build_log();
+        let condensed = condense_errors(&log, 1).unwrap();
+
+        let kinds: Vec<_> = condensed.sections.iter().map(|s| s.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                SectionKind::FailingPhase,
+                SectionKind::CompilerError,
+                SectionKind::FailingPhase,
+                SectionKind::NixError,
+            ]
+        );
+
+        let compiler = &condensed.sections[1];
+        assert_eq!((compiler.start_line, compiler.end_line), (103, 106));
+        let nix = &condensed.sections[3];
+        assert_eq!((nix.start_line, nix.end_line), (137, 140));
+
+        assert!(condensed
+            .content
+            .contains("hello> Running phase: buildPhase"));
+        assert!(condensed.content.contains("error[E0308]: mismatched types"));
+        assert!(condensed.content.contains("For full logs"));
+        assert!(!condensed.content.contains("unpacking file 3"));
+        assert!(condensed.content.contains("lines omitted"));
+    }
+
+    #[test]
+    fn test_condense_test_failures() {
+        let log = "running 2 tests\ntest a ... ok\ntest b ... FAILED\n\nfailures:\n\ntest result: FAILED. 1 passed; 1 failed";
+        let condensed = condense_errors(log, 0).unwrap();
+        let lines: Vec<_> = condensed.content.lines().collect();
+        assert_eq!(lines[1], "test b ... FAILED");
+        assert!(condensed
+            .sections
+            .iter()
+            .all(|s| s.kind == SectionKind::TestFailure));
+    }
+
+    #[test]
+    fn test_condense_without_errors() {
+        assert!(condense_errors("building\nall good", 3).is_none());
+    }
+}
@@ -3,8 +3,12 @@ use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use crate::nix_runner::DEFAULT_TIMEOUT_SECS;
 use crate::output::OutputLimitsConfig;
+use crate::policy::PolicyConfig;
+use crate::workspace::WorkspaceConfig;
 
 #[derive(Debug, Default, Deserialize)]
 pub struct Config {
@@ -14,6 +18,22 @@ pub struct Config {
     pub flakehub: FlakehubConfig,
     #[serde(default)]
     pub output_limits: OutputLimitsConfig,
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
+    #[serde(default)]
+    pub nix_options: NixOptionsConfig,
+    #[serde(default)]
+    pub tasks: TasksConfig,
+    #[serde(default)]
+    pub output_cache: OutputCacheConfig,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub workspace: WorkspaceConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -35,11 +55,171 @@ pub struct FlakehubConfig {
     // This section is for future configuration options
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct ConcurrencyConfig {
+    /// Maximum number of nix processes running at once (default: 4)
+    pub max_nix_processes: Option<usize>,
+    /// Maximum builds, checks, copies and pushes running at once (default: 2)
+    pub max_heavy_jobs: Option<usize>,
+}
+
+impl ConcurrencyConfig {
+    pub fn max_nix_processes(&self) -> usize {
+        self.max_nix_processes.unwrap_or(4).max(1)
+    }
+
+    pub fn max_heavy_jobs(&self) -> usize {
+        self.max_heavy_jobs.unwrap_or(2).max(1)
+    }
+}
+
+/// Command timeouts in seconds, e.g.
+///
+/// ```toml
+/// [timeouts]
+/// default = 600
+/// build = 3600
+/// search = 30
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct TimeoutsConfig {
+    /// Timeout for tools without their own entry (default: 300)
+    pub default: Option<u64>,
+    /// Per-tool overrides, keyed by tool name
+    #[serde(flatten)]
+    pub tools: HashMap<String, u64>,
+}
+
+impl TimeoutsConfig {
+    pub fn for_tool(&self, tool: &str) -> u64 {
+        self.tools
+            .get(tool)
+            .copied()
+            .or(self.default)
+            .unwrap_or(DEFAULT_TIMEOUT_SECS)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NixOptionsConfig {
+    /// Options allowed in `nix_options` on top of the built-in allow-list
+    #[serde(default)]
+    pub allowed: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TasksConfig {
+    /// Where background tasks and their logs are kept
+    /// (default: $XDG_STATE_HOME/nix-mcp-server/tasks)
+    pub state_dir: Option<PathBuf>,
+    /// Hours to keep finished tasks before deleting them (default: 72)
+    pub retention_hours: Option<u64>,
+}
+
+impl TasksConfig {
+    pub fn state_dir(&self) -> Option<PathBuf> {
+        self.state_dir.clone().or_else(|| {
+            dirs::state_dir()
+                .or_else(dirs::data_local_dir)
+                .map(|d| d.join("nix-mcp-server").join("tasks"))
+        })
+    }
+
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.retention_hours.unwrap_or(72) * 3600)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OutputCacheConfig {
+    /// Where the full output of each tool call is kept
+    /// (default: $XDG_CACHE_HOME/nix-mcp-server/output)
+    pub dir: Option<PathBuf>,
+    /// Total size of the cache before the least recently used output is
+    /// evicted, 0 to disable it (default: 256)
+    pub max_size_mb: Option<u64>,
+}
+
+impl OutputCacheConfig {
+    pub fn dir(&self) -> Option<PathBuf> {
+        self.dir
+            .clone()
+            .or_else(|| dirs::cache_dir().map(|d| d.join("nix-mcp-server").join("output")))
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_size_mb.unwrap_or(256) * 1024 * 1024
+    }
+}
+
+/// The JSONL log of every tool call, e.g.
+///
+/// ```toml
+/// [audit]
+/// path = "/var/log/chix/audit.jsonl"
+/// max_size_mb = 50
+/// max_files = 10
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditConfig {
+    /// Set to false to stop logging tool calls (default: true)
+    pub enabled: Option<bool>,
+    /// Where the log is written
+    /// (default: $XDG_STATE_HOME/nix-mcp-server/audit.jsonl)
+    pub path: Option<PathBuf>,
+    /// Size at which the log is rotated to `<path>.1` (default: 10)
+    pub max_size_mb: Option<u64>,
+    /// Rotated logs to keep before the oldest is deleted (default: 5)
+    pub max_files: Option<usize>,
+}
+
+impl AuditConfig {
+    pub fn path(&self) -> Option<PathBuf> {
+        if self.enabled == Some(false) {
+            return None;
+        }
+        self.path.clone().or_else(|| {
+            dirs::state_dir()
+                .or_else(dirs::data_local_dir)
+                .map(|d| d.join("nix-mcp-server").join("audit.jsonl"))
+        })
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_size_mb.unwrap_or(10) * 1024 * 1024
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(5)
+    }
+}
+
 fn config_path() -> Option<PathBuf> {
+    // Unit tests run with the defaults, not the user's config
+    if cfg!(test) {
+        return None;
+    }
     dirs::config_dir().map(|d| d.join("nix-mcp-server").join("config.toml"))
 }
 
+#[cfg(test)]
+thread_local! {
+    static TEST_CONFIG: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Make `load_config` parse `toml_str` on this thread, so a test can turn
+/// on config-driven behavior
+#[cfg(test)]
+pub fn set_test_config(toml_str: &str) {
+    TEST_CONFIG.with(|config| *config.borrow_mut() = Some(toml_str.to_string()));
+}
+
 pub fn load_config() -> Config {
+    #[cfg(test)]
+    if let Some(toml_str) = TEST_CONFIG.with(|config| config.borrow().clone()) {
+        return toml::from_str(&toml_str).unwrap();
+    }
+
     let Some(path) = config_path() else {
         return Config::default();
     };
@@ -171,6 +351,111 @@ search_limit_default = 25
         assert_eq!(config.output_limits.search_limit_default(), 25);
     }
 
+    #[test]
+    fn test_concurrency_config() {
+        let config = Config::default();
+        assert_eq!(config.concurrency.max_nix_processes(), 4);
+        assert_eq!(config.concurrency.max_heavy_jobs(), 2);
+
+        let config: Config = toml::from_str("[concurrency]\nmax_nix_processes = 2\n").unwrap();
+        assert_eq!(config.concurrency.max_nix_processes(), 2);
+
+        // Zero would deadlock every nix call
+        let config: Config = toml::from_str("[concurrency]\nmax_nix_processes = 0\n").unwrap();
+        assert_eq!(config.concurrency.max_nix_processes(), 1);
+    }
+
+    #[test]
+    fn test_timeouts_config() {
+        let config = Config::default();
+        assert_eq!(config.timeouts.for_tool("build"), 300);
+
+        let toml_str = r#"
+[timeouts]
+default = 600
+build = 3600
+search = 30
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.timeouts.for_tool("build"), 3600);
+        assert_eq!(config.timeouts.for_tool("search"), 30);
+        assert_eq!(config.timeouts.for_tool("flake_check"), 600);
+    }
+
+    #[test]
+    fn test_nix_options_config() {
+        let toml_str = r#"
+[nix_options]
+allowed = ["builders", "builders-use-substitutes"]
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.nix_options.allowed,
+            vec![
+                "builders".to_string(),
+                "builders-use-substitutes".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tasks_config() {
+        let config = Config::default();
+        assert_eq!(config.tasks.retention(), Duration::from_secs(72 * 3600));
+
+        let toml_str = r#"
+[tasks]
+state_dir = "/var/lib/chix/tasks"
+retention_hours = 24
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.tasks.state_dir(),
+            Some(PathBuf::from("/var/lib/chix/tasks"))
+        );
+        assert_eq!(config.tasks.retention(), Duration::from_secs(24 * 3600));
+    }
+
+    #[test]
+    fn test_output_cache_config() {
+        let config = Config::default();
+        assert_eq!(config.output_cache.max_bytes(), 256 * 1024 * 1024);
+
+        let toml_str = r#"
+[output_cache]
+dir = "/tmp/chix-output"
+max_size_mb = 0
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.output_cache.dir(),
+            Some(PathBuf::from("/tmp/chix-output"))
+        );
+        assert_eq!(config.output_cache.max_bytes(), 0);
+    }
+
+    #[test]
+    fn test_audit_config() {
+        let config = Config::default();
+        assert_eq!(config.audit.max_bytes(), 10 * 1024 * 1024);
+        assert_eq!(config.audit.max_files(), 5);
+
+        let toml_str = r#"
+[audit]
+path = "/var/log/chix/audit.jsonl"
+max_files = 2
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.audit.path(),
+            Some(PathBuf::from("/var/log/chix/audit.jsonl"))
+        );
+        assert_eq!(config.audit.max_files(), 2);
+
+        let config: Config = toml::from_str("[audit]\nenabled = false\n").unwrap();
+        assert_eq!(config.audit.path(), None);
+    }
+
     #[test]
     fn test_output_limits_defaults() {
         let config = Config::default();
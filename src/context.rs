@@ -0,0 +1,231 @@
+use crate::audit::CommandLog;
+use crate::backend::CommandBackend;
+use crate::output_cache::OutputCapture;
+use crate::scheduler::JobClass;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Notify;
+
+/// Sends JSON-RPC messages (responses and notifications) to the client.
+///
+/// All writes to stdout go through a single channel so that notifications
+/// emitted while a tool is running never interleave with other output.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    tx: UnboundedSender<Value>,
+}
+
+impl Notifier {
+    pub fn new(tx: UnboundedSender<Value>) -> Self {
+        Notifier { tx }
+    }
+
+    pub fn send(&self, message: Value) {
+        // The receiver only goes away when the server is shutting down
+        let _ = self.tx.send(message);
+    }
+
+    pub fn notify(&self, method: &str, params: Value) {
+        self.send(serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        }));
+    }
+}
+
+/// Cancellation flag for a single request, set by `notifications/cancelled`
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<CancelState>,
+}
+
+#[derive(Debug, Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the token has been cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// State for the tool call currently being executed
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    /// `_meta.progressToken` from the tools/call request, if the client sent one
+    pub progress_token: Option<Value>,
+    /// Last `progress` value sent for the request
+    pub progress_sent: Arc<AtomicU64>,
+    pub notifier: Option<Notifier>,
+    pub cancel: Option<CancelToken>,
+    /// Runs the request's external commands, real processes when unset
+    pub backend: Option<Arc<dyn CommandBackend>>,
+    /// Timeout for each external command the request runs
+    pub timeout_secs: Option<u64>,
+    /// Background task the request runs as, which receives its output
+    pub task_id: Option<String>,
+    /// How the request's tool is scheduled
+    pub job_class: Option<JobClass>,
+    /// Collects the request's full command output for the output cache
+    pub output: Option<OutputCapture>,
+    /// Records the commands the request runs for the audit log
+    pub commands: Option<CommandLog>,
+}
+
+tokio::task_local! {
+    static CURRENT: RequestContext;
+}
+
+/// Run `fut` with `ctx` as the current request context
+pub async fn scope<F: Future>(ctx: RequestContext, fut: F) -> F::Output {
+    CURRENT.scope(ctx, fut).await
+}
+
+/// The context of the request being executed, if any
+pub fn current() -> Option<RequestContext> {
+    CURRENT.try_with(|ctx| ctx.clone()).ok()
+}
+
+/// Resolves when the current request is cancelled by the client.
+///
+/// Never resolves outside a request or when the request can't be cancelled.
+pub async fn cancelled() {
+    match current().and_then(|ctx| ctx.cancel) {
+        Some(token) => token.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Emit a `notifications/progress` for the current request.
+///
+/// MCP requires `progress` to increase with every notification, so a value
+/// at or below the last one sent, like a queue update before the build's
+/// own progress, is sent as one more than the last instead.
+///
+/// Does nothing if the client didn't ask for progress on this request.
+pub fn report_progress(progress: u64, message: &str) {
+    let Some(ctx) = current() else {
+        return;
+    };
+    let (Some(token), Some(notifier)) = (ctx.progress_token, ctx.notifier) else {
+        return;
+    };
+    let last = ctx
+        .progress_sent
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            Some(progress.max(last + 1))
+        })
+        .unwrap_or_default();
+    let progress = progress.max(last + 1);
+
+    notifier.notify(
+        "notifications/progress",
+        serde_json::json!({
+            "progressToken": token,
+            "progress": progress,
+            "message": message,
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_progress_uses_request_token() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let ctx = RequestContext {
+            progress_token: Some(serde_json::json!("tok-1")),
+            progress_sent: Arc::default(),
+            notifier: Some(Notifier::new(tx)),
+            cancel: None,
+            backend: None,
+            timeout_secs: None,
+            task_id: None,
+            job_class: None,
+            output: None,
+            commands: None,
+        };
+
+        scope(ctx, async { report_progress(3, "building hello") }).await;
+
+        let message = rx.try_recv().unwrap();
+        assert_eq!(message["method"], "notifications/progress");
+        assert_eq!(message["params"]["progressToken"], "tok-1");
+        assert_eq!(message["params"]["progress"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_report_progress_only_increases() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let ctx = RequestContext {
+            progress_token: Some(serde_json::json!("tok-1")),
+            notifier: Some(Notifier::new(tx)),
+            ..Default::default()
+        };
+
+        scope(ctx, async {
+            report_progress(0, "queued behind 1 running and 1 waiting jobs");
+            report_progress(0, "queued behind 1 running and 0 waiting jobs");
+            report_progress(2, "building hello");
+            report_progress(10, "building hello");
+            report_progress(4, "building hello");
+        })
+        .await;
+
+        let sent: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|message| message["params"]["progress"].as_u64().unwrap())
+            .collect();
+        assert_eq!(sent, vec![1, 2, 3, 10, 11]);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_resolves_after_cancel() {
+        let token = CancelToken::new();
+        let ctx = RequestContext {
+            cancel: Some(token.clone()),
+            ..Default::default()
+        };
+
+        let waiter = tokio::spawn(scope(ctx, cancelled()));
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        token.cancel();
+        waiter.await.unwrap();
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn test_report_progress_outside_request_is_noop() {
+        assert!(current().is_none());
+        report_progress(1, "ignored");
+    }
+}
@@ -0,0 +1,370 @@
+use crate::activity::ActivitySummary;
+use crate::output::strip_ansi;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::LazyLock;
+
+/// Maximum lines of an error block included in a failure report
+const MAX_ERROR_BLOCK_LINES: usize = 100;
+
+// error: builder for '/nix/store/...drv' failed with exit code 2;
+static BUILDER_FAILED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"builder for '(/nix/store/[^']+\.drv)' failed with exit code (\d+)").unwrap()
+});
+
+// error: Cannot build '/nix/store/...drv'.
+//        Reason: builder failed with exit code 2.
+static CANNOT_BUILD_EXIT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)Cannot build '(/nix/store/[^']+\.drv)'\.\s*Reason: builder failed with exit code (\d+)")
+        .unwrap()
+});
+
+static HASH_MISMATCH: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"hash mismatch in fixed-output derivation '(/nix/store/[^']+\.drv)'").unwrap()
+});
+
+static HASH_SPECIFIED: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"specified:\s*(\S+)").unwrap());
+
+static HASH_GOT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"got:\s*(\S+)").unwrap());
+
+static MISSING_ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:attribute '([^']+)' missing|does not provide attribute '([^']+)')").unwrap()
+});
+
+static DEPENDENCIES_FAILED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\d+) dependenc(?:y|ies) of derivation '(/nix/store/[^']+\.drv)' failed to build")
+        .unwrap()
+});
+
+static CANNOT_BUILD_DEPENDENCIES: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?s)Cannot build '(/nix/store/[^']+\.drv)'\.\s*Reason: (\d+) dependenc(?:y|ies) failed",
+    )
+    .unwrap()
+});
+
+static RUNNING_PHASE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"Running phase: (\w+)").unwrap());
+
+/// The kind of failure nix reported
+#[derive(Debug, Clone, Serialize, JsonSchema, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FailureKind {
+    /// A fixed-output derivation produced a different hash than declared
+    HashMismatch { specified: String, got: String },
+    /// The builder exited with a non-zero status
+    BuilderFailed,
+    /// Evaluation referenced an attribute that doesn't exist
+    MissingAttribute { attribute: String },
+    /// The derivation wasn't built because its dependencies failed
+    DependencyFailed { failed_dependencies: usize },
+    /// Any other `error:` reported by nix
+    Other,
+}
+
+/// Structured description of why a build failed
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct BuildFailure {
+    #[serde(flatten)]
+    pub kind: FailureKind,
+    /// Derivation that caused the failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drv_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Phase the builder was in when it failed (e.g. "checkPhase")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phase: Option<String>,
+    /// The `error:` block describing the root cause
+    pub error: String,
+    /// Every derivation nix reported as failed, root cause first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_derivations: Vec<String>,
+}
+
+/// Split nix stderr into `error:` blocks (the error line plus its indented
+/// continuation lines).
+fn error_blocks(stderr: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+
+    for line in stderr.lines() {
+        if line.starts_with("error:") {
+            if let Some(block) = current.take() {
+                blocks.push(block.join("\n"));
+            }
+            current = Some(vec![line]);
+        } else if let Some(ref mut block) = current {
+            if line.starts_with(char::is_whitespace) || line.is_empty() {
+                block.push(line);
+            } else {
+                blocks.push(block.join("\n"));
+                current = None;
+            }
+        }
+    }
+
+    if let Some(block) = current {
+        blocks.push(block.join("\n"));
+    }
+
+    blocks
+        .into_iter()
+        .map(|b| b.trim_end().to_string())
+        .collect()
+}
+
+fn cap_block(block: &str) -> String {
+    let lines: Vec<&str> = block.lines().collect();
+    if lines.len() <= MAX_ERROR_BLOCK_LINES {
+        return block.to_string();
+    }
+    lines[..MAX_ERROR_BLOCK_LINES].join("\n")
+}
+
+/// Find the phase a derivation's builder was in when it stopped.
+///
+/// Prefers the phase nix reported through the activity stream, falling back to
+/// the last "Running phase:" line in the log output.
+fn find_phase(
+    drv_path: &str,
+    block: &str,
+    stderr: &str,
+    activities: Option<&ActivitySummary>,
+) -> Option<String> {
+    if let Some(summary) = activities {
+        if let Some(build) = summary.builds.iter().find(|b| b.drv_path == drv_path) {
+            if build.phase.is_some() {
+                return build.phase.clone();
+            }
+        }
+    }
+
+    if let Some(caps) = RUNNING_PHASE.captures_iter(block).last() {
+        return Some(caps[1].to_string());
+    }
+
+    let prefix = format!("{}>", crate::activity::drv_name(drv_path));
+    stderr
+        .lines()
+        .rev()
+        .filter(|l| l.starts_with(&prefix))
+        .find_map(|l| RUNNING_PHASE.captures(l))
+        .map(|caps| caps[1].to_string())
+}
+
+/// Diagnose a failed nix build from its stderr.
+///
+/// Returns `None` if nix didn't report any `error:` block.
+pub fn diagnose_build_failure(
+    stderr: &str,
+    activities: Option<&ActivitySummary>,
+) -> Option<BuildFailure> {
+    let stderr = strip_ansi(stderr);
+    let blocks = error_blocks(&stderr);
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let mut failed_derivations: Vec<String> = Vec::new();
+    let mut record = |path: &str| {
+        if !failed_derivations.iter().any(|p| p == path) {
+            failed_derivations.push(path.to_string());
+        }
+    };
+
+    let mut hash_mismatch = None;
+    let mut builder_failed = None;
+    let mut missing_attribute = None;
+    let mut dependency_failed = None;
+
+    for block in &blocks {
+        if let Some(caps) = HASH_MISMATCH.captures(block) {
+            record(&caps[1]);
+            if hash_mismatch.is_none() {
+                let specified = HASH_SPECIFIED.captures(block).map(|c| c[1].to_string());
+                let got = HASH_GOT.captures(block).map(|c| c[1].to_string());
+                hash_mismatch = Some((
+                    block,
+                    caps[1].to_string(),
+                    specified.unwrap_or_default(),
+                    got.unwrap_or_default(),
+                ));
+            }
+        } else if let Some(caps) = BUILDER_FAILED
+            .captures(block)
+            .or_else(|| CANNOT_BUILD_EXIT.captures(block))
+        {
+            record(&caps[1]);
+            if builder_failed.is_none() {
+                builder_failed = Some((block, caps[1].to_string(), caps[2].parse::<i32>().ok()));
+            }
+        } else if let Some(caps) = DEPENDENCIES_FAILED.captures(block) {
+            record(&caps[2]);
+            if dependency_failed.is_none() {
+                dependency_failed =
+                    Some((block, caps[2].to_string(), caps[1].parse().unwrap_or(0)));
+            }
+        } else if let Some(caps) = CANNOT_BUILD_DEPENDENCIES.captures(block) {
+            record(&caps[1]);
+            if dependency_failed.is_none() {
+                dependency_failed =
+                    Some((block, caps[1].to_string(), caps[2].parse().unwrap_or(0)));
+            }
+        } else if let Some(caps) = MISSING_ATTRIBUTE.captures(block) {
+            if missing_attribute.is_none() {
+                let attribute = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+                missing_attribute = Some((block, attribute.to_string()));
+            }
+        }
+    }
+
+    let failure = if let Some((block, drv_path, specified, got)) = hash_mismatch {
+        BuildFailure {
+            kind: FailureKind::HashMismatch { specified, got },
+            drv_path: Some(drv_path),
+            exit_code: None,
+            phase: None,
+            error: cap_block(block),
+            failed_derivations: Vec::new(),
+        }
+    } else if let Some((block, drv_path, exit_code)) = builder_failed {
+        BuildFailure {
+            kind: FailureKind::BuilderFailed,
+            phase: find_phase(&drv_path, block, &stderr, activities),
+            drv_path: Some(drv_path),
+            exit_code,
+            error: cap_block(block),
+            failed_derivations: Vec::new(),
+        }
+    } else if let Some((block, attribute)) = missing_attribute {
+        BuildFailure {
+            kind: FailureKind::MissingAttribute { attribute },
+            drv_path: None,
+            exit_code: None,
+            phase: None,
+            error: cap_block(block),
+            failed_derivations: Vec::new(),
+        }
+    } else if let Some((block, drv_path, count)) = dependency_failed {
+        BuildFailure {
+            kind: FailureKind::DependencyFailed {
+                failed_dependencies: count,
+            },
+            drv_path: Some(drv_path),
+            exit_code: None,
+            phase: None,
+            error: cap_block(block),
+            failed_derivations: Vec::new(),
+        }
+    } else {
+        BuildFailure {
+            kind: FailureKind::Other,
+            drv_path: None,
+            exit_code: None,
+            phase: None,
+            error: cap_block(&blocks[0]),
+            failed_derivations: Vec::new(),
+        }
+    };
+
+    Some(BuildFailure {
+        failed_derivations,
+        ..failure
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_failed_with_phase() {
+        let stderr = "\
+hello> Running phase: unpackPhase
+hello> Running phase: buildPhase
+hello> make: *** [Makefile:3: all] Error 1
+error: builder for '/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello.drv' failed with exit code 2;
+       last 2 log lines:
+       > Running phase: buildPhase
+       > make: *** [Makefile:3: all] Error 1
+       For full logs, run 'nix log /nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello.drv'.
+error: 1 dependencies of derivation '/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-app.drv' failed to build";
+
+        let failure = diagnose_build_failure(stderr, None).unwrap();
+        assert_eq!(failure.kind, FailureKind::BuilderFailed);
+        assert_eq!(
+            failure.drv_path.as_deref(),
+            Some("/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello.drv")
+        );
+        assert_eq!(failure.exit_code, Some(2));
+        assert_eq!(failure.phase.as_deref(), Some("buildPhase"));
+        assert!(failure.error.starts_with("error: builder for"));
+        assert!(failure.error.contains("For full logs"));
+        assert_eq!(failure.failed_derivations.len(), 2);
+    }
+
+    #[test]
+    fn test_cannot_build_format() {
+        let stderr = "\
+error: Cannot build '/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello.drv'.
+       Reason: builder failed with exit code 1.
+       Output paths:
+         /nix/store/cccccccccccccccccccccccccccccccc-hello";
+
+        let failure = diagnose_build_failure(stderr, None).unwrap();
+        assert_eq!(failure.kind, FailureKind::BuilderFailed);
+        assert_eq!(failure.exit_code, Some(1));
+    }
+
+    #[test]
+    fn test_hash_mismatch() {
+        let stderr = "\
+\x1b[31;1merror:\x1b[0m hash mismatch in fixed-output derivation '/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-src.drv':
+         specified: sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=
+            got:    sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB=";
+
+        let failure = diagnose_build_failure(stderr, None).unwrap();
+        assert_eq!(
+            failure.kind,
+            FailureKind::HashMismatch {
+                specified: "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string(),
+                got: "sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB=".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_attribute() {
+        let stderr = "\
+error: flake 'git+file:///src/app' does not provide attribute 'packages.x86_64-linux.nope', 'legacyPackages.x86_64-linux.nope' or 'nope'";
+
+        let failure = diagnose_build_failure(stderr, None).unwrap();
+        assert_eq!(
+            failure.kind,
+            FailureKind::MissingAttribute {
+                attribute: "packages.x86_64-linux.nope".to_string()
+            }
+        );
+        assert!(failure.drv_path.is_none());
+    }
+
+    #[test]
+    fn test_no_errors() {
+        assert!(diagnose_build_failure("warning: Git tree is dirty", None).is_none());
+    }
+
+    #[test]
+    fn test_failure_serialization() {
+        let failure = diagnose_build_failure(
+            "error: 2 dependencies of derivation '/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-app.drv' failed to build",
+            None,
+        )
+        .unwrap();
+        let json = serde_json::to_value(&failure).unwrap();
+        assert_eq!(json["kind"], "dependency_failed");
+        assert_eq!(json["failed_dependencies"], 2);
+    }
+}
@@ -0,0 +1,389 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+// Owners, repos and indirect flake ids; sourcehut owners start with `~`
+static NAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^~?[a-zA-Z0-9._\-]+$").unwrap());
+
+// Local paths, URL hosts and URL paths; `:` for ports and scp-style ssh
+static LOCATION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9._\-/~+@:%]*$").unwrap());
+
+static QUERY_KEY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9_\-]*$").unwrap());
+
+// Refs, revs and hashes; narHash is base64 with `+`, `/` and `=`
+static QUERY_VALUE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9._\-/:+@%~=,]*$").unwrap());
+
+static ATTR_PATH: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9._\-+]*$").unwrap());
+
+static OUTPUT_NAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9_\-]+$").unwrap());
+
+// git+https, tarball+file, https, ...
+static URL_SCHEME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^((git|hg|tarball|file)\+)?(https?|ssh|file)$").unwrap());
+
+/// A flake reference broken into the parts nix understands, e.g.
+/// `github:NixOS/nixpkgs/nixos-24.05?dir=lib`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlakeRef {
+    /// `path`, `indirect`, `github`, `gitlab`, `sourcehut` or a URL scheme
+    /// like `git+https` or `tarball+https`
+    pub scheme: String,
+    pub host: Option<String>,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    /// Indirect flake id looked up in the registry, e.g. `nixpkgs`
+    pub id: Option<String>,
+    /// Local path, or the path part of a URL
+    pub path: Option<String>,
+    /// Branch or tag
+    pub git_ref: Option<String>,
+    pub rev: Option<String>,
+    /// Every query parameter, including `ref` and `rev`
+    pub query: BTreeMap<String, String>,
+}
+
+/// A flake reference with an optional attribute path and outputs, e.g.
+/// `nixpkgs#openssl^out,dev`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Installable {
+    pub flake_ref: FlakeRef,
+    pub attr_path: Option<String>,
+    /// Output names after `^`, or `["*"]` for all outputs
+    pub outputs: Option<Vec<String>>,
+}
+
+impl Installable {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        check_characters(input)?;
+
+        let (reference, fragment) = match input.split_once('#') {
+            Some((reference, fragment)) => (reference, Some(fragment)),
+            None => (input, None),
+        };
+
+        // `^outputs` ends the fragment, or the reference when there's none
+        let (reference, fragment, outputs) = match fragment {
+            Some(fragment) => {
+                let (attr, outputs) = split_outputs(fragment)?;
+                (reference, Some(attr), outputs)
+            }
+            None => {
+                let (reference, outputs) = split_outputs(reference)?;
+                (reference, None, outputs)
+            }
+        };
+
+        if let Some(attr) = fragment {
+            if !ATTR_PATH.is_match(attr) {
+                return Err(format!("invalid attribute path `{}`", attr));
+            }
+        }
+
+        Ok(Installable {
+            flake_ref: FlakeRef::parse(reference)?,
+            attr_path: fragment.filter(|a| !a.is_empty()).map(str::to_string),
+            outputs,
+        })
+    }
+}
+
+impl FlakeRef {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        check_characters(input)?;
+
+        let (base, query) = match input.split_once('?') {
+            Some((base, query)) => (base, parse_query(query)?),
+            None => (input, BTreeMap::new()),
+        };
+        if base.is_empty() {
+            return Err("empty flake reference".to_string());
+        }
+
+        let mut flake_ref = FlakeRef {
+            scheme: String::new(),
+            host: None,
+            owner: None,
+            repo: None,
+            id: None,
+            path: None,
+            git_ref: query.get("ref").cloned(),
+            rev: query.get("rev").cloned(),
+            query,
+        };
+
+        if base.starts_with(['.', '/']) {
+            flake_ref.scheme = "path".to_string();
+            flake_ref.path = Some(location(base)?);
+            return Ok(flake_ref);
+        }
+
+        let Some((scheme, rest)) = base.split_once(':') else {
+            flake_ref.scheme = "indirect".to_string();
+            parse_indirect(&mut flake_ref, base)?;
+            return Ok(flake_ref);
+        };
+        flake_ref.scheme = scheme.to_string();
+
+        match scheme {
+            "path" => flake_ref.path = Some(location(rest)?),
+            "flake" => {
+                flake_ref.scheme = "indirect".to_string();
+                parse_indirect(&mut flake_ref, rest)?;
+            }
+            "github" | "gitlab" | "sourcehut" => {
+                let mut parts = rest.splitn(3, '/');
+                flake_ref.owner = Some(name(parts.next(), "owner")?);
+                flake_ref.repo = Some(name(parts.next(), "repo")?);
+                if let Some(rev_or_ref) = parts.next() {
+                    set_rev_or_ref(&mut flake_ref, rev_or_ref)?;
+                }
+                flake_ref.host = flake_ref.query.get("host").cloned();
+            }
+            _ if URL_SCHEME.is_match(scheme) => {
+                let url = rest
+                    .strip_prefix("//")
+                    .ok_or_else(|| format!("expected `{}://...`", scheme))?;
+                let (authority, path) = match url.find('/') {
+                    Some(i) => url.split_at(i),
+                    None => (url, ""),
+                };
+                // Drop the `git@` of ssh URLs
+                let host = authority.rsplit('@').next().unwrap_or_default();
+                if host.is_empty() && !scheme.ends_with("file") {
+                    return Err(format!("missing host in `{}`", base));
+                }
+                if !host.is_empty() {
+                    flake_ref.host = Some(location(host)?);
+                }
+                flake_ref.path = Some(location(path)?);
+            }
+            _ => return Err(format!("unsupported scheme `{}`", scheme)),
+        }
+
+        Ok(flake_ref)
+    }
+
+    /// The directory of a `path:`, local or `file://` reference, including
+    /// `git+file`, `hg+file` and `tarball+file` ones
+    pub fn local_path(&self) -> Option<PathBuf> {
+        let path = self.path.as_deref()?;
+        match self.scheme.as_str() {
+            "path" => Some(PathBuf::from(path)),
+            scheme if scheme.ends_with("file") => {
+                Some(percent_decode_path(path).unwrap_or_else(|| PathBuf::from(path)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `path` with its `%XX` escapes decoded, `None` if one is malformed
+pub fn percent_decode_path(path: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut chars = path.bytes();
+    while let Some(b) = chars.next() {
+        if b != b'%' {
+            bytes.push(b);
+            continue;
+        }
+        let hex = [chars.next()?, chars.next()?];
+        let hex = std::str::from_utf8(&hex).ok()?;
+        if !hex.bytes().all(|h| h.is_ascii_hexdigit()) {
+            return None;
+        }
+        bytes.push(u8::from_str_radix(hex, 16).ok()?);
+    }
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// Reject anything that could be taken as an option or smuggle in quoting,
+/// whitespace or shell syntax
+fn check_characters(input: &str) -> Result<(), String> {
+    if input.is_empty() {
+        return Err("empty flake reference".to_string());
+    }
+    if input.starts_with('-') {
+        return Err("must not start with `-`".to_string());
+    }
+    if let Some(c) = input
+        .chars()
+        .find(|c| c.is_whitespace() || c.is_control() || ";|`$(){}\\<>!'\"".contains(*c))
+    {
+        return Err(format!("character `{}` not allowed", c.escape_default()));
+    }
+    // `&` only separates query parameters
+    if input.split('?').next().unwrap_or_default().contains('&') {
+        return Err("`&` is only allowed in the query".to_string());
+    }
+    Ok(())
+}
+
+/// Split off a trailing `^out,dev` or `^*`
+fn split_outputs(input: &str) -> Result<(&str, Option<Vec<String>>), String> {
+    let Some((rest, spec)) = input.rsplit_once('^') else {
+        return Ok((input, None));
+    };
+    if spec == "*" {
+        return Ok((rest, Some(vec!["*".to_string()])));
+    }
+    let outputs: Vec<String> = spec.split(',').map(str::to_string).collect();
+    if let Some(bad) = outputs.iter().find(|o| !OUTPUT_NAME.is_match(o)) {
+        return Err(format!("invalid output name `{}`", bad));
+    }
+    Ok((rest, Some(outputs)))
+}
+
+fn parse_query(query: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut params = BTreeMap::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if !QUERY_KEY.is_match(key) {
+            return Err(format!("invalid query parameter `{}`", key));
+        }
+        if !QUERY_VALUE.is_match(value) {
+            return Err(format!("invalid value for `{}`", key));
+        }
+        params.insert(key.to_string(), value.to_string());
+    }
+    Ok(params)
+}
+
+/// `nixpkgs`, `nixpkgs/nixos-24.05` or `nixpkgs/nixos-24.05/<rev>`
+fn parse_indirect(flake_ref: &mut FlakeRef, input: &str) -> Result<(), String> {
+    let mut parts = input.splitn(3, '/');
+    flake_ref.id = Some(name(parts.next(), "flake id")?);
+    for part in parts {
+        set_rev_or_ref(flake_ref, part)?;
+    }
+    Ok(())
+}
+
+/// A 40-character hex string is a rev, anything else a branch or tag
+fn set_rev_or_ref(flake_ref: &mut FlakeRef, value: &str) -> Result<(), String> {
+    if !QUERY_VALUE.is_match(value) || value.is_empty() {
+        return Err(format!("invalid ref `{}`", value));
+    }
+    if value.len() == 40 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        flake_ref.rev = Some(value.to_string());
+    } else {
+        flake_ref.git_ref = Some(value.to_string());
+    }
+    Ok(())
+}
+
+fn name(part: Option<&str>, what: &str) -> Result<String, String> {
+    match part {
+        Some(part) if NAME.is_match(part) => Ok(part.to_string()),
+        Some(part) => Err(format!("invalid {} `{}`", what, part)),
+        None => Err(format!("missing {}", what)),
+    }
+}
+
+fn location(value: &str) -> Result<String, String> {
+    if !LOCATION.is_match(value) {
+        return Err(format!("invalid path `{}`", value));
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_github_with_query() {
+        let installable =
+            Installable::parse("github:owner/repo?ref=v1.2&dir=sub#packages.x86_64-linux.default")
+                .unwrap();
+        let flake_ref = &installable.flake_ref;
+        assert_eq!(flake_ref.scheme, "github");
+        assert_eq!(flake_ref.owner.as_deref(), Some("owner"));
+        assert_eq!(flake_ref.repo.as_deref(), Some("repo"));
+        assert_eq!(flake_ref.git_ref.as_deref(), Some("v1.2"));
+        assert_eq!(flake_ref.query.get("dir").map(String::as_str), Some("sub"));
+        assert_eq!(
+            installable.attr_path.as_deref(),
+            Some("packages.x86_64-linux.default")
+        );
+    }
+
+    #[test]
+    fn test_parse_url_forms() {
+        let flake_ref = FlakeRef::parse(
+            "git+https://example.com/repo.git?rev=0123456789abcdef0123456789abcdef01234567",
+        )
+        .unwrap();
+        assert_eq!(flake_ref.scheme, "git+https");
+        assert_eq!(flake_ref.host.as_deref(), Some("example.com"));
+        assert_eq!(flake_ref.path.as_deref(), Some("/repo.git"));
+        assert!(flake_ref.rev.is_some());
+
+        let flake_ref = FlakeRef::parse("sourcehut:~user/repo/main").unwrap();
+        assert_eq!(flake_ref.owner.as_deref(), Some("~user"));
+        assert_eq!(flake_ref.git_ref.as_deref(), Some("main"));
+
+        let flake_ref = FlakeRef::parse("git+ssh://git@github.com/owner/repo").unwrap();
+        assert_eq!(flake_ref.host.as_deref(), Some("github.com"));
+
+        let flake_ref = FlakeRef::parse("tarball+https://example.com/archive/main.tar.gz").unwrap();
+        assert_eq!(flake_ref.scheme, "tarball+https");
+
+        let flake_ref = FlakeRef::parse("path:./foo?narHash=sha256-abc+/def=").unwrap();
+        assert_eq!(flake_ref.local_path(), Some(PathBuf::from("./foo")));
+        assert_eq!(
+            flake_ref.query.get("narHash").map(String::as_str),
+            Some("sha256-abc+/def=")
+        );
+
+        for scheme in ["file", "git+file", "hg+file", "tarball+file"] {
+            let flake_ref = FlakeRef::parse(&format!("{}:///srv/My%20Flake", scheme)).unwrap();
+            assert_eq!(flake_ref.local_path(), Some(PathBuf::from("/srv/My Flake")));
+        }
+        let flake_ref = FlakeRef::parse("git+https://example.com/repo.git").unwrap();
+        assert_eq!(flake_ref.local_path(), None);
+    }
+
+    #[test]
+    fn test_parse_indirect_and_outputs() {
+        let installable = Installable::parse("nixpkgs/nixos-24.05#openssl^out,dev").unwrap();
+        assert_eq!(installable.flake_ref.scheme, "indirect");
+        assert_eq!(installable.flake_ref.id.as_deref(), Some("nixpkgs"));
+        assert_eq!(
+            installable.flake_ref.git_ref.as_deref(),
+            Some("nixos-24.05")
+        );
+        assert_eq!(installable.attr_path.as_deref(), Some("openssl"));
+        assert_eq!(
+            installable.outputs,
+            Some(vec!["out".to_string(), "dev".to_string()])
+        );
+
+        let installable = Installable::parse(".#default^*").unwrap();
+        assert_eq!(installable.flake_ref.local_path(), Some(PathBuf::from(".")));
+        assert_eq!(installable.outputs, Some(vec!["*".to_string()]));
+    }
+
+    #[test]
+    fn test_rejects_injection() {
+        for bad in [
+            "$(malicious)",
+            "; rm -rf /",
+            "hello`whoami`",
+            "nixpkgs#hello; ls",
+            "--option",
+            "github:owner/repo?ref=a b",
+            "nixpkgs&ls",
+            "github:owner",
+            "ftp://example.com/x",
+            "nixpkgs#hello^out;",
+        ] {
+            assert!(Installable::parse(bad).is_err(), "{} was accepted", bad);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Golden tests: tools run against recorded fixtures from `tests/fixtures`
+//! and their results are compared with `tests/golden`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the expected results after an
+//! intentional change, then review the diff.
+
+use crate::backend::{Invocation, ReplayBackend};
+use crate::context::Notifier;
+use crate::server::Server;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn testdata(dir: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(dir)
+}
+
+/// Call `tool` with fixtures from `scenario`, returning the parsed result and
+/// the commands the tool ran
+async fn call_tool(scenario: &str, tool: &str, arguments: Value) -> (Value, Vec<Invocation>) {
+    let backend = Arc::new(ReplayBackend::load(&testdata("fixtures").join(scenario)).unwrap());
+    let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+    let server = Arc::new(Server::new(Notifier::new(tx), backend.clone()));
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": {"name": tool, "arguments": arguments},
+    });
+    let response = server.handle_request(&request.to_string()).await.unwrap();
+
+    let result = &response["result"];
+    assert_ne!(result["isError"], true, "{} failed: {}", tool, result);
+    let text = result["content"][0]["text"].as_str().unwrap();
+
+    (serde_json::from_str(text).unwrap(), backend.calls())
+}
+
+fn assert_golden(scenario: &str, actual: &Value) {
+    let path = testdata("golden").join(format!("{}.json", scenario));
+    let rendered = serde_json::to_string_pretty(actual).unwrap() + "\n";
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, rendered).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "missing golden file {:?} ({}), run with UPDATE_GOLDEN=1",
+            path, e
+        )
+    });
+    assert_eq!(rendered, expected, "result differs from {:?}", path);
+}
+
+#[tokio::test]
+async fn golden_build_success() {
+    let (result, calls) =
+        call_tool("build_success", "build", json!({"installable": ".#hello"})).await;
+
+    assert_eq!(calls.len(), 1);
+    assert_golden("build_success", &result);
+}
+
+#[tokio::test]
+async fn golden_build_hash_mismatch() {
+    let (result, _) = call_tool(
+        "build_hash_mismatch",
+        "build",
+        json!({"installable": ".#hello"}),
+    )
+    .await;
+
+    assert_eq!(result["failure"]["kind"], "hash_mismatch");
+    assert_golden("build_hash_mismatch", &result);
+}
+
+#[tokio::test]
+async fn golden_build_errors_mode() {
+    let (result, _) = call_tool(
+        "build_hash_mismatch",
+        "build",
+        json!({"installable": ".#hello", "mode": "errors", "context": 0}),
+    )
+    .await;
+
+    assert_eq!(result["truncation_info"]["position"], "errors");
+    assert_golden("build_errors_mode", &result);
+}
+
+#[tokio::test]
+async fn golden_log_tail() {
+    let (result, _) = call_tool(
+        "log_tail",
+        "log",
+        json!({"installable": ".#hello", "tail": 3}),
+    )
+    .await;
+
+    assert_eq!(result["truncated"], true);
+    assert_golden("log_tail", &result);
+}
+
+#[tokio::test]
+async fn golden_flake_update() {
+    let flake_dir = testdata("fixtures").join("flake_update").join("flake");
+    let (result, calls) = call_tool(
+        "flake_update",
+        "flake_update",
+        json!({"flake_ref": ".", "flake_dir": flake_dir, "inputs": ["nixpkgs"]}),
+    )
+    .await;
+
+    assert_eq!(
+        calls[0].command_line(),
+        "nix flake update nixpkgs --flake ."
+    );
+    assert_golden("flake_update", &result);
+}
+
+#[tokio::test]
+async fn golden_fh_search_paginated() {
+    let (result, _) = call_tool(
+        "fh_search",
+        "fh_search",
+        json!({"query": "rust", "offset": 1, "limit": 1}),
+    )
+    .await;
+
+    assert_eq!(result["pagination"]["total"], 3);
+    assert_golden("fh_search_paginated", &result);
+}
+
+#[tokio::test]
+async fn golden_nix_info_lix() {
+    let (result, calls) = call_tool("nix_info_lix", "nix_info", json!({})).await;
+
+    // Falls back to `nix show-config` when `nix config show` is missing
+    assert_eq!(calls.len(), 3);
+    assert_eq!(result["implementation"], "lix");
+    assert_golden("nix_info_lix", &result);
+}
+
+#[tokio::test]
+async fn golden_path_info_object_format() {
+    let (result, _) = call_tool(
+        "path_info_object",
+        "store_path_info",
+        json!({"path": "/nix/store/63l345l7dgcfz789w1y93j1540czafqh-hello-2.12.1"}),
+    )
+    .await;
+
+    assert_eq!(
+        result["path_info"][0]["path"],
+        "/nix/store/63l345l7dgcfz789w1y93j1540czafqh-hello-2.12.1"
+    );
+    assert_golden("path_info_object", &result);
+}
+
+#[tokio::test]
+async fn background_build_keeps_output_and_result() {
+    let (started, _) = call_tool(
+        "build_success",
+        "build",
+        json!({"installable": ".#hello", "background": true}),
+    )
+    .await;
+    let task_id = started["task_id"].as_str().unwrap().to_string();
+    assert_eq!(started["status"], "Running");
+
+    let info = loop {
+        let info = crate::background::get_task_info(&task_id).unwrap();
+        if info.status != crate::background::TaskStatus::Running {
+            break info;
+        }
+        tokio::task::yield_now().await;
+    };
+    assert_eq!(info.status, crate::background::TaskStatus::Completed);
+
+    let handle = crate::background::remove_task(&task_id).unwrap();
+    let (_, stderr) = handle.stderr.text_from(0);
+    let (_, stdout) = handle.stdout.text_from(0);
+    assert!(stderr.contains("hello-2.12.1> unpacking source archive"));
+    assert!(stdout.contains("/nix/store/63l345l7dgcfz789w1y93j1540czafqh-hello-2.12.1"));
+    assert_eq!(handle.result.unwrap()["success"], true);
+}
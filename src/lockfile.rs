@@ -0,0 +1,356 @@
+use crate::flake_ref::FlakeRef;
+use crate::output::strip_ansi;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+// • Updated input 'nixpkgs':
+static INPUT_HEADER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^• (Updated|Added|Removed) input '([^']+)'").unwrap());
+
+//     'github:NixOS/nixpkgs/1d2c...' (2026-09-01)
+//   → 'github:NixOS/nixpkgs/9f8e...' (2026-10-10)
+static INPUT_LOCK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(→\s*)?'([^']+)'(?: \((\d{4}-\d{2}-\d{2})\))?").unwrap());
+
+static URL_REV: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:/|[?&]rev=)([0-9a-f]{40})\b").unwrap());
+
+/// One input whose lock changed
+#[derive(Debug, Clone, Default, Serialize, JsonSchema, PartialEq)]
+pub struct LockChange {
+    /// Input path, with nested inputs separated by `/` (e.g. "home-manager/nixpkgs")
+    pub input: String,
+    /// "updated", "added" or "removed"
+    pub change: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_rev: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_rev: Option<String>,
+    /// Dates are the inputs' `lastModified`, as YYYY-MM-DD
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_last_modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_last_modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_url: Option<String>,
+}
+
+/// What a lock file pins one input to
+#[derive(Debug, Clone, PartialEq)]
+struct LockedInput {
+    url: String,
+    rev: Option<String>,
+    last_modified: Option<String>,
+    nar_hash: Option<String>,
+}
+
+/// Path of the `flake.lock` a local flake reference points at, inside its
+/// `?dir=` subdirectory if it has one.
+///
+/// Remote references have no lock file we could read.
+pub fn lock_path(flake_ref: &FlakeRef, flake_dir: Option<&str>) -> Option<PathBuf> {
+    let path = flake_ref.local_path()?;
+    let mut lock = Path::new(flake_dir.unwrap_or(".")).join(path);
+    if let Some(dir) = flake_ref.query.get("dir") {
+        lock.push(dir);
+    }
+    Some(lock.join("flake.lock"))
+}
+
+/// Read a lock file, `None` if it doesn't exist or can't be parsed
+pub fn read_lock(path: &Path) -> Option<Value> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
+
+/// Inputs that differ between two versions of a lock file
+pub fn diff_locks(old: Option<&Value>, new: Option<&Value>) -> Vec<LockChange> {
+    let old = old.map(locked_inputs).unwrap_or_default();
+    let new = new.map(locked_inputs).unwrap_or_default();
+
+    let mut changes = Vec::new();
+    for (input, before) in &old {
+        match new.get(input) {
+            Some(after) if after == before => {}
+            Some(after) => changes.push(LockChange {
+                input: input.clone(),
+                change: "updated".to_string(),
+                ..change_fields(Some(before), Some(after))
+            }),
+            None => changes.push(LockChange {
+                input: input.clone(),
+                change: "removed".to_string(),
+                ..change_fields(Some(before), None)
+            }),
+        }
+    }
+    for (input, after) in &new {
+        if !old.contains_key(input) {
+            changes.push(LockChange {
+                input: input.clone(),
+                change: "added".to_string(),
+                ..change_fields(None, Some(after))
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.input.cmp(&b.input));
+    changes
+}
+
+fn change_fields(old: Option<&LockedInput>, new: Option<&LockedInput>) -> LockChange {
+    LockChange {
+        old_rev: old.and_then(|l| l.rev.clone()),
+        new_rev: new.and_then(|l| l.rev.clone()),
+        old_last_modified: old.and_then(|l| l.last_modified.clone()),
+        new_last_modified: new.and_then(|l| l.last_modified.clone()),
+        old_url: old.map(|l| l.url.clone()),
+        new_url: new.map(|l| l.url.clone()),
+        ..Default::default()
+    }
+}
+
+/// Every locked input reachable from the root, keyed by input path.
+///
+/// Inputs that `follow` another input aren't locked themselves and are
+/// skipped; a node reached through several paths is reported once.
+fn locked_inputs(lock: &Value) -> BTreeMap<String, LockedInput> {
+    let mut inputs = BTreeMap::new();
+    let Some(nodes) = lock.get("nodes") else {
+        return inputs;
+    };
+    let root = lock.get("root").and_then(Value::as_str).unwrap_or("root");
+
+    let mut seen = HashSet::new();
+    let mut queue = vec![(String::new(), root.to_string())];
+    while let Some((prefix, node_name)) = queue.pop() {
+        let Some(node_inputs) = nodes
+            .get(&node_name)
+            .and_then(|n| n.get("inputs"))
+            .and_then(Value::as_object)
+        else {
+            continue;
+        };
+        for (name, target) in node_inputs {
+            // Lists are `follows` paths, not nodes of their own
+            let Some(target) = target.as_str() else {
+                continue;
+            };
+            if !seen.insert(target.to_string()) {
+                continue;
+            }
+            let path = if prefix.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            if let Some(locked) = nodes.get(target).and_then(|n| n.get("locked")) {
+                inputs.insert(path.clone(), parse_locked(locked));
+            }
+            queue.push((path, target.to_string()));
+        }
+    }
+    inputs
+}
+
+fn parse_locked(locked: &Value) -> LockedInput {
+    let field = |name: &str| locked.get(name).and_then(Value::as_str);
+    let rev = field("rev").map(str::to_string);
+
+    let url = match field("type").unwrap_or_default() {
+        kind @ ("github" | "gitlab" | "sourcehut") => format!(
+            "{}:{}/{}/{}",
+            kind,
+            field("owner").unwrap_or_default(),
+            field("repo").unwrap_or_default(),
+            rev.as_deref().unwrap_or_default()
+        ),
+        "git" | "hg" => {
+            let kind = field("type").unwrap_or_default();
+            match &rev {
+                Some(rev) => format!("{}+{}?rev={}", kind, field("url").unwrap_or_default(), rev),
+                None => format!("{}+{}", kind, field("url").unwrap_or_default()),
+            }
+        }
+        "path" => format!("path:{}", field("path").unwrap_or_default()),
+        _ => field("url").unwrap_or_default().to_string(),
+    };
+
+    LockedInput {
+        url,
+        rev,
+        last_modified: locked
+            .get("lastModified")
+            .and_then(Value::as_i64)
+            .map(unix_to_date),
+        nar_hash: field("narHash").map(str::to_string),
+    }
+}
+
+/// Format a unix timestamp as a UTC YYYY-MM-DD date
+fn unix_to_date(secs: i64) -> String {
+    // Howard Hinnant's civil_from_days
+    let z = secs.div_euclid(86_400) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Changed inputs as nix reports them on stderr, for when the lock file
+/// itself can't be read
+pub fn parse_lock_changes(stderr: &str) -> Vec<LockChange> {
+    let mut changes: Vec<LockChange> = Vec::new();
+    for line in strip_ansi(stderr).lines() {
+        if let Some(caps) = INPUT_HEADER.captures(line) {
+            changes.push(LockChange {
+                input: caps[2].to_string(),
+                change: caps[1].to_lowercase(),
+                ..Default::default()
+            });
+            continue;
+        }
+        let (Some(change), Some(caps)) = (changes.last_mut(), INPUT_LOCK.captures(line)) else {
+            continue;
+        };
+        let url = caps[2].to_string();
+        let rev = URL_REV.captures(&url).map(|c| c[1].to_string());
+        let date = caps.get(3).map(|m| m.as_str().to_string());
+        // The first locked line is the old one, except for added inputs
+        if caps.get(1).is_some() || change.change == "added" {
+            change.new_url = Some(url);
+            change.new_rev = rev;
+            change.new_last_modified = date;
+        } else {
+            change.old_url = Some(url);
+            change.old_rev = rev;
+            change.old_last_modified = date;
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn lock(nixpkgs_rev: &str, last_modified: i64, with_utils: bool) -> Value {
+        let mut root_inputs = json!({"nixpkgs": "nixpkgs", "home-manager": "home-manager"});
+        let mut nodes = json!({
+            "root": {"inputs": {}},
+            "nixpkgs": {"locked": {
+                "type": "github", "owner": "NixOS", "repo": "nixpkgs",
+                "rev": nixpkgs_rev, "lastModified": last_modified, "narHash": nixpkgs_rev,
+            }},
+            "home-manager": {
+                "inputs": {"nixpkgs": ["nixpkgs"]},
+                "locked": {
+                    "type": "github", "owner": "nix-community", "repo": "home-manager",
+                    "rev": "aaaa", "lastModified": 1_700_000_000, "narHash": "sha256-hm",
+                }
+            },
+        });
+        if with_utils {
+            root_inputs["utils"] = json!("utils");
+            nodes["utils"] = json!({"locked": {
+                "type": "git", "url": "https://example.com/utils.git",
+                "rev": "bbbb", "lastModified": 1_700_000_000, "narHash": "sha256-utils",
+            }});
+        }
+        nodes["root"]["inputs"] = root_inputs;
+        json!({"nodes": nodes, "root": "root", "version": 7})
+    }
+
+    #[test]
+    fn test_diff_locks() {
+        let old = lock("1111", 1_756_684_800, true);
+        let new = lock("2222", 1_760_054_400, false);
+        let changes = diff_locks(Some(&old), Some(&new));
+
+        assert_eq!(
+            changes,
+            vec![
+                LockChange {
+                    input: "nixpkgs".to_string(),
+                    change: "updated".to_string(),
+                    old_rev: Some("1111".to_string()),
+                    new_rev: Some("2222".to_string()),
+                    old_last_modified: Some("2025-09-01".to_string()),
+                    new_last_modified: Some("2025-10-10".to_string()),
+                    old_url: Some("github:NixOS/nixpkgs/1111".to_string()),
+                    new_url: Some("github:NixOS/nixpkgs/2222".to_string()),
+                },
+                LockChange {
+                    input: "utils".to_string(),
+                    change: "removed".to_string(),
+                    old_rev: Some("bbbb".to_string()),
+                    old_last_modified: Some("2023-11-14".to_string()),
+                    old_url: Some("git+https://example.com/utils.git?rev=bbbb".to_string()),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert!(diff_locks(Some(&new), Some(&new)).is_empty());
+    }
+
+    #[test]
+    fn test_parse_lock_changes_from_stderr() {
+        let stderr = "warning: updating lock file '/home/user/proj/flake.lock':\n\
+            • Updated input 'nixpkgs':\n    \
+            'github:NixOS/nixpkgs/1d2c3f4e5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d' (2026-09-01)\n  \
+            → 'github:NixOS/nixpkgs/9f8e7d6c5b4a39281706f5e4d3c2b1a098765432' (2026-10-10)\n\
+            • Added input 'utils':\n    \
+            'github:numtide/flake-utils/11707dc2f618dd54ca8739b309ec4fc024de578b' (2024-11-13)";
+        let changes = parse_lock_changes(stderr);
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].input, "nixpkgs");
+        assert_eq!(changes[0].change, "updated");
+        assert_eq!(
+            changes[0].old_rev.as_deref(),
+            Some("1d2c3f4e5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d")
+        );
+        assert_eq!(changes[0].new_last_modified.as_deref(), Some("2026-10-10"));
+        assert_eq!(changes[1].change, "added");
+        assert!(changes[1].old_url.is_none());
+        assert_eq!(
+            changes[1].new_url.as_deref(),
+            Some("github:numtide/flake-utils/11707dc2f618dd54ca8739b309ec4fc024de578b")
+        );
+    }
+
+    #[test]
+    fn test_lock_path() {
+        let lock = |flake_ref: &str, dir| lock_path(&FlakeRef::parse(flake_ref).unwrap(), dir);
+        assert_eq!(
+            lock(".", Some("/home/user/proj")),
+            Some(PathBuf::from("/home/user/proj/./flake.lock"))
+        );
+        assert_eq!(
+            lock("path:/srv/flake?narHash=sha256-abc=", None),
+            Some(PathBuf::from("/srv/flake/flake.lock"))
+        );
+        assert_eq!(
+            lock("./proj?dir=sub/flake", Some("/srv")),
+            Some(PathBuf::from("/srv/./proj/sub/flake/flake.lock"))
+        );
+        assert_eq!(lock("github:NixOS/nixpkgs", None), None);
+    }
+
+    #[test]
+    fn test_unix_to_date() {
+        assert_eq!(unix_to_date(0), "1970-01-01");
+        assert_eq!(unix_to_date(1_709_251_200), "2024-03-01");
+    }
+}
@@ -1,17 +1,38 @@
+mod activity;
+mod audit;
+mod backend;
 mod background;
+mod budget;
+mod condense;
 mod config;
+mod context;
+mod diagnose;
+mod flake_ref;
+#[cfg(test)]
+mod golden_tests;
+mod lockfile;
 mod lsp_client;
 mod nix_runner;
 mod output;
+mod output_cache;
+mod policy;
 mod resources;
+mod scheduler;
 mod server;
+mod task_store;
 mod tools;
 mod validators;
+mod workspace;
 
+use backend::{CommandBackend, ProcessBackend, RecordingBackend, ReplayBackend};
 use clap::{Parser, Subcommand};
+use context::Notifier;
 use server::Server;
+use std::path::Path;
 use std::process::Command;
+use std::sync::Arc;
 use tokio::io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::task::JoinSet;
 
 #[derive(Parser)]
 #[command(name = "chix")]
@@ -59,23 +80,92 @@ fn install_claude() -> anyhow::Result<()> {
     }
 }
 
+/// Pick the command backend from the environment.
+///
+/// `CHIX_REPLAY_DIR` answers every command from fixtures in that directory,
+/// `CHIX_RECORD_DIR` runs commands for real and records them there.
+fn command_backend() -> anyhow::Result<Arc<dyn CommandBackend>> {
+    if let Some(dir) = std::env::var_os("CHIX_REPLAY_DIR") {
+        return Ok(Arc::new(ReplayBackend::load(Path::new(&dir))?));
+    }
+
+    if let Some(dir) = std::env::var_os("CHIX_RECORD_DIR") {
+        return Ok(Arc::new(RecordingBackend::new(
+            Arc::new(ProcessBackend),
+            dir,
+        )));
+    }
+
+    Ok(Arc::new(ProcessBackend))
+}
+
 async fn run_server() -> anyhow::Result<()> {
-    let server = Server::new();
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+    let notifier = Notifier::new(tx);
+    let server = Arc::new(Server::new(notifier.clone(), command_backend()?));
+
+    // Pick up tasks from before a restart so task_status can report on them
+    background::init();
+
+    // Single writer so responses and notifications never interleave on stdout
+    let writer = tokio::spawn(async move {
+        let mut stdout = stdout();
+        while let Some(message) = rx.recv().await {
+            let json = serde_json::to_string(&message)?;
+            stdout.write_all(json.as_bytes()).await?;
+            stdout.write_all(b"\n").await?;
+            stdout.flush().await?;
+        }
+        Ok::<(), anyhow::Error>(())
+    });
+
+    // Probe nix in the background so the first nix_info call is instant
+    tokio::spawn({
+        let server = Arc::clone(&server);
+        async move {
+            let _ = server.nix_info().await;
+        }
+    });
+
     let stdin = BufReader::new(stdin());
-    let mut stdout = stdout();
     let mut lines = stdin.lines();
+    let mut requests = JoinSet::new();
 
+    // Each request is handled on its own task so a long build doesn't hold up
+    // quick calls like task_status. Responses are written as they complete.
     while let Some(line) = lines.next_line().await? {
         if line.is_empty() {
             continue;
         }
 
-        let response = server.handle_request(&line).await;
-        let response_json = serde_json::to_string(&response)?;
-        stdout.write_all(response_json.as_bytes()).await?;
-        stdout.write_all(b"\n").await?;
-        stdout.flush().await?;
+        // Notifications are handled inline, in order, so a cancellation
+        // never overtakes the call it targets
+        let message = serde_json::from_str::<serde_json::Value>(&line).ok();
+        if let Some(message) = &message {
+            if message.get("id").is_none() {
+                server.handle_request(&line).await;
+                continue;
+            }
+            server.track_call(message);
+        }
+
+        let server = Arc::clone(&server);
+        let notifier = notifier.clone();
+        requests.spawn(async move {
+            if let Some(response) = server.handle_request(&line).await {
+                notifier.send(response);
+            }
+        });
+
+        // Reap finished requests so the set doesn't grow without bound
+        while requests.try_join_next().is_some() {}
     }
 
+    while requests.join_next().await.is_some() {}
+
+    drop(server);
+    drop(notifier);
+    writer.await??;
+
     Ok(())
 }
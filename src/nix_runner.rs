@@ -1,15 +1,29 @@
-use std::time::Duration;
+use crate::activity::{ActivitySummary, ActivityTracker};
+use crate::backend::{self, Invocation, OutputSink, RawOutput, Stream};
+use crate::background;
+use crate::config::load_config;
+use crate::context::{self, report_progress, RequestContext};
+use crate::scheduler;
+use std::sync::LazyLock;
+use std::time::Instant;
 use thiserror::Error;
-use tokio::process::Command;
-use tokio::time::timeout;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
 #[derive(Error, Debug)]
 pub enum NixError {
     #[error("nix command failed: {0}")]
     CommandFailed(String),
 
-    #[error("nix command timed out after {0} seconds")]
-    Timeout(u64),
+    #[error("`{command}` timed out after {secs} seconds{}", describe_in_flight(.in_flight))]
+    Timeout {
+        secs: u64,
+        command: String,
+        /// Activities nix was still working on when the limit hit
+        in_flight: Vec<String>,
+    },
+
+    #[error("command cancelled by client")]
+    Cancelled,
 
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
@@ -21,19 +35,56 @@ pub struct NixOutput {
     pub stdout: String,
     pub stderr: String,
     pub exit_code: Option<i32>,
+    /// Activity summary, only present for commands run with internal-json logging
+    pub activities: Option<ActivitySummary>,
+}
+
+fn describe_in_flight(in_flight: &[String]) -> String {
+    if in_flight.is_empty() {
+        String::new()
+    } else {
+        format!("; still running: {}", in_flight.join(", "))
+    }
+}
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 300;
+
+/// Timeout for the current request, from its `timeout_secs` argument or the
+/// `[timeouts]` config
+fn request_timeout() -> u64 {
+    context::current()
+        .and_then(|ctx| ctx.timeout_secs)
+        .unwrap_or(DEFAULT_TIMEOUT_SECS)
 }
 
-const DEFAULT_TIMEOUT_SECS: u64 = 300;
+/// Caps how many nix processes run at once now that requests are handled
+/// concurrently. Sized from `[concurrency] max_nix_processes`.
+static NIX_SLOTS: LazyLock<Semaphore> =
+    LazyLock::new(|| Semaphore::new(load_config().concurrency.max_nix_processes()));
+
+/// Wait for a free nix slot, giving up if the request is cancelled meanwhile.
+///
+/// Cheap read-only queries don't take a slot, so they never wait behind
+/// builds.
+async fn acquire_nix_slot() -> Result<Option<SemaphorePermit<'static>>, NixError> {
+    if scheduler::bypasses_queue() {
+        return Ok(None);
+    }
+    tokio::select! {
+        permit = NIX_SLOTS.acquire() => Ok(Some(permit.expect("nix semaphore is never closed"))),
+        _ = context::cancelled() => Err(NixError::Cancelled),
+    }
+}
 
 pub async fn run_nix_command(args: &[&str]) -> Result<NixOutput, NixError> {
-    run_nix_command_with_options(args, None, DEFAULT_TIMEOUT_SECS).await
+    run_nix_command_with_options(args, None, request_timeout()).await
 }
 
 pub async fn run_nix_command_in_dir(
     args: &[&str],
     cwd: Option<&str>,
 ) -> Result<NixOutput, NixError> {
-    run_nix_command_with_options(args, cwd, DEFAULT_TIMEOUT_SECS).await
+    run_nix_command_with_options(args, cwd, request_timeout()).await
 }
 
 pub async fn run_nix_command_with_timeout(
@@ -48,28 +99,126 @@ pub async fn run_nix_command_with_options(
     cwd: Option<&str>,
     timeout_secs: u64,
 ) -> Result<NixOutput, NixError> {
-    let mut cmd = Command::new("nix");
-    cmd.args(args);
-    cmd.kill_on_drop(true);
+    let mut invocation = Invocation::new("nix", args, timeout_secs);
+    invocation.cwd = cwd.map(str::to_string);
 
-    if let Some(dir) = cwd {
-        cmd.current_dir(dir);
-    }
+    let _slot = acquire_nix_slot().await?;
+    run_collecting(&invocation).await
+}
+
+/// Run nix with `--log-format internal-json`, reporting build progress to the
+/// client as it happens.
+///
+/// The returned `stderr` is reconstructed from the activity stream so it reads
+/// like normal nix output. Build log lines are only included when
+/// `render_build_logs` is set.
+pub async fn run_nix_command_streaming(
+    args: &[&str],
+    cwd: Option<&str>,
+    render_build_logs: bool,
+) -> Result<NixOutput, NixError> {
+    let mut full_args = vec!["--log-format", "internal-json"];
+    full_args.extend_from_slice(args);
 
-    let result = timeout(Duration::from_secs(timeout_secs), cmd.output()).await;
+    let mut invocation = Invocation::new("nix", &full_args, request_timeout());
+    invocation.cwd = cwd.map(str::to_string);
 
-    match result {
-        Ok(output_result) => {
-            let output = output_result?;
-            Ok(NixOutput {
-                success: output.status.success(),
-                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-                exit_code: output.status.code(),
-            })
+    let _slot = acquire_nix_slot().await?;
+    let mut tracker = ActivityTracker::new(render_build_logs);
+
+    let ctx = context::current();
+
+    let result = run_on_backend(&invocation, &mut |stream, line: &str| match stream {
+        Stream::Stdout => forward_output(ctx.as_ref(), stream, line),
+        Stream::Stderr => {
+            let rendered_from = tracker.text().len();
+            if let Some(update) = tracker.process_line(line) {
+                report_progress(update.progress, &update.message);
+            }
+            let rendered = tracker.text()[rendered_from..].trim_start_matches('\n');
+            if !rendered.is_empty() {
+                forward_output(ctx.as_ref(), stream, rendered);
+            }
         }
-        Err(_) => Err(NixError::Timeout(timeout_secs)),
+    })
+    .await;
+
+    let output = match result {
+        Ok(output) => output,
+        Err(NixError::Timeout { secs, command, .. }) => {
+            let (_, summary) = tracker.finish();
+            return Err(NixError::Timeout {
+                secs,
+                command,
+                in_flight: summary.in_flight,
+            });
+        }
+        Err(e) => return Err(e),
+    };
+
+    let (stderr, summary) = tracker.finish();
+    Ok(NixOutput {
+        success: output.success(),
+        stdout: output.stdout,
+        stderr,
+        exit_code: output.exit_code,
+        activities: Some(summary),
+    })
+}
+
+/// Run a command on the current backend, recording it in the request's
+/// command log for the audit log
+async fn run_on_backend(
+    invocation: &Invocation,
+    on_line: &mut OutputSink<'_>,
+) -> Result<RawOutput, NixError> {
+    let started = Instant::now();
+    let result = backend::current().run(invocation, on_line).await;
+    if let Some(log) = context::current().and_then(|ctx| ctx.commands) {
+        let (exit_code, error) = match &result {
+            Ok(output) => (output.exit_code, None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        log.record(invocation, exit_code, error, started.elapsed());
     }
+    result
+}
+
+/// Append a line of output to the request's background task and output
+/// capture, if it has them
+fn forward_output(ctx: Option<&RequestContext>, stream: Stream, line: &str) {
+    let Some(ctx) = ctx else {
+        return;
+    };
+    if let Some(id) = &ctx.task_id {
+        background::append_output(id, stream, line);
+    }
+    if let Some(capture) = &ctx.output {
+        capture.push(stream, line);
+    }
+}
+
+/// Run a command on the current backend, collecting stderr verbatim
+async fn run_collecting(invocation: &Invocation) -> Result<NixOutput, NixError> {
+    let ctx = context::current();
+    let mut stderr = String::new();
+
+    let output = run_on_backend(invocation, &mut |stream, line: &str| {
+        if stream == Stream::Stderr {
+            stderr.push_str(line);
+            stderr.push('\n');
+        }
+        forward_output(ctx.as_ref(), stream, line);
+    })
+    .await?;
+
+    Ok(NixOutput {
+        success: output.success(),
+        stdout: output.stdout,
+        stderr,
+        exit_code: output.exit_code,
+        activities: None,
+    })
 }
 
 pub fn parse_store_paths(stdout: &str) -> Vec<String> {
@@ -80,27 +229,94 @@ pub fn parse_store_paths(stdout: &str) -> Vec<String> {
         .collect()
 }
 
+/// Store paths of the `out` output of each derivation from `nix build --json`
 pub fn parse_json_store_paths(stdout: &str) -> Vec<String> {
-    if let Ok(value) = serde_json::from_str::<serde_json::Value>(stdout) {
-        if let Some(arr) = value.as_array() {
-            return arr
-                .iter()
-                .filter_map(|v| v.get("outputs"))
-                .filter_map(|o| o.get("out"))
-                .filter_map(|s| s.as_str())
-                .map(|s| s.to_string())
-                .collect();
-        }
+    let Ok(serde_json::Value::Array(results)) = serde_json::from_str(stdout) else {
+        return Vec::new();
+    };
+
+    results
+        .iter()
+        .filter_map(|r| r.get("outputs")?.get("out")?.as_str())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn full_store_path(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/nix/store/{}", path)
     }
-    Vec::new()
+}
+
+/// Normalize `nix path-info --json` output to an array of objects with a
+/// `path` field.
+///
+/// Lix and Nix before 2.19 print that array directly. Newer Nix prints an
+/// object keyed by store path, with `null` for paths that aren't valid.
+pub fn normalize_path_info(value: serde_json::Value) -> serde_json::Value {
+    let serde_json::Value::Object(map) = value else {
+        return value;
+    };
+
+    let entries = map
+        .into_iter()
+        .map(|(path, info)| {
+            let path = serde_json::Value::String(full_store_path(&path));
+            match info {
+                serde_json::Value::Object(mut info) => {
+                    info.insert("path".to_string(), path);
+                    serde_json::Value::Object(info)
+                }
+                _ => serde_json::json!({ "path": path, "valid": false }),
+            }
+        })
+        .collect();
+
+    serde_json::Value::Array(entries)
+}
+
+/// Normalize `nix derivation show` output to an object keyed by full `.drv`
+/// path.
+///
+/// Older releases print exactly that. Newer ones wrap it as
+/// `{"derivations": {...}, "version": N}` and key it by store path basename.
+pub fn normalize_derivations(value: serde_json::Value) -> serde_json::Value {
+    let serde_json::Value::Object(mut map) = value else {
+        return value;
+    };
+
+    if let Some(serde_json::Value::Object(derivations)) = map.remove("derivations") {
+        map = derivations;
+    }
+
+    let normalized = map
+        .into_iter()
+        .map(|(path, drv)| (full_store_path(&path), drv))
+        .collect();
+
+    serde_json::Value::Object(normalized)
+}
+
+/// Number of input derivations, from `inputDrvs` or the newer `inputs.drvs`
+pub fn derivation_input_count(drv: &serde_json::Value) -> usize {
+    drv.get("inputDrvs")
+        .or_else(|| drv.get("inputs")?.get("drvs"))
+        .and_then(|v| v.as_object())
+        .map(|o| o.len())
+        .unwrap_or(0)
 }
 
 pub async fn run_fh_command(args: &[&str]) -> Result<NixOutput, NixError> {
-    run_fh_command_with_options(args, None, DEFAULT_TIMEOUT_SECS).await
+    run_fh_command_with_options(args, None, request_timeout()).await
 }
 
-pub async fn run_fh_command_in_dir(args: &[&str], cwd: Option<&str>) -> Result<NixOutput, NixError> {
-    run_fh_command_with_options(args, cwd, DEFAULT_TIMEOUT_SECS).await
+pub async fn run_fh_command_in_dir(
+    args: &[&str],
+    cwd: Option<&str>,
+) -> Result<NixOutput, NixError> {
+    run_fh_command_with_options(args, cwd, request_timeout()).await
 }
 
 pub async fn run_fh_command_with_timeout(
@@ -115,59 +331,118 @@ pub async fn run_fh_command_with_options(
     cwd: Option<&str>,
     timeout_secs: u64,
 ) -> Result<NixOutput, NixError> {
-    let mut cmd = Command::new("fh");
-    cmd.args(args);
-    cmd.kill_on_drop(true);
-
-    if let Some(dir) = cwd {
-        cmd.current_dir(dir);
-    }
-
-    let result = timeout(Duration::from_secs(timeout_secs), cmd.output()).await;
+    let mut invocation = Invocation::new("fh", args, timeout_secs);
+    invocation.cwd = cwd.map(str::to_string);
 
-    match result {
-        Ok(output_result) => {
-            let output = output_result?;
-            Ok(NixOutput {
-                success: output.status.success(),
-                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-                exit_code: output.status.code(),
-            })
-        }
-        Err(_) => Err(NixError::Timeout(timeout_secs)),
-    }
+    run_collecting(&invocation).await
 }
 
 pub async fn run_cachix_command(args: &[&str]) -> Result<NixOutput, NixError> {
-    run_cachix_command_with_env(args, &[], DEFAULT_TIMEOUT_SECS).await
+    run_cachix_command_with_env(args, &[]).await
 }
 
 pub async fn run_cachix_command_with_env(
     args: &[&str],
     env_vars: &[(&str, &str)],
-    timeout_secs: u64,
 ) -> Result<NixOutput, NixError> {
-    let mut cmd = Command::new("cachix");
-    cmd.args(args);
-    cmd.kill_on_drop(true);
+    let mut invocation = Invocation::new("cachix", args, request_timeout());
+    invocation.env = env_vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
 
-    for (key, value) in env_vars {
-        cmd.env(key, value);
-    }
+    run_collecting(&invocation).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{CommandBackend, OutputSink, RawOutput};
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::Arc;
 
-    let result = timeout(Duration::from_secs(timeout_secs), cmd.output()).await;
+    /// Starts a build, then hits the timeout
+    #[derive(Debug)]
+    struct StalledBuild;
 
-    match result {
-        Ok(output_result) => {
-            let output = output_result?;
-            Ok(NixOutput {
-                success: output.status.success(),
-                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-                exit_code: output.status.code(),
+    #[async_trait]
+    impl CommandBackend for StalledBuild {
+        async fn run(
+            &self,
+            invocation: &Invocation,
+            on_line: &mut OutputSink<'_>,
+        ) -> Result<RawOutput, NixError> {
+            on_line(
+                Stream::Stderr,
+                r#"@nix {"action":"start","id":4,"level":3,"type":105,"text":"building '/nix/store/aaaa-glibc-2.40.drv'","fields":["/nix/store/aaaa-glibc-2.40.drv","",1,1],"parent":0}"#,
+            );
+            Err(NixError::Timeout {
+                secs: invocation.timeout_secs,
+                command: invocation.command_line(),
+                in_flight: Vec::new(),
             })
         }
-        Err(_) => Err(NixError::Timeout(timeout_secs)),
+    }
+
+    #[tokio::test]
+    async fn test_streaming_timeout_reports_in_flight_builds() {
+        let ctx = RequestContext {
+            backend: Some(Arc::new(StalledBuild)),
+            timeout_secs: Some(5),
+            ..Default::default()
+        };
+
+        let err = context::scope(
+            ctx,
+            run_nix_command_streaming(&["build", ".#x"], None, true),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "`nix --log-format internal-json build .#x` timed out after 5 seconds; \
+             still running: building '/nix/store/aaaa-glibc-2.40.drv'"
+        );
+    }
+
+    #[test]
+    fn test_parse_json_store_paths_default_output() {
+        let stdout = r#"[{"drvPath":"/nix/store/a-hello.drv","outputs":{"dev":"/nix/store/b-hello-dev","out":"/nix/store/c-hello"}}]"#;
+        assert_eq!(parse_json_store_paths(stdout), vec!["/nix/store/c-hello"]);
+    }
+
+    #[test]
+    fn test_normalize_path_info_formats() {
+        let legacy = json!([{"path": "/nix/store/a-hello", "narSize": 10}]);
+        assert_eq!(normalize_path_info(legacy.clone()), legacy);
+
+        let keyed = json!({
+            "/nix/store/a-hello": {"narSize": 10},
+            "/nix/store/b-missing": null,
+        });
+        assert_eq!(
+            normalize_path_info(keyed),
+            json!([
+                {"path": "/nix/store/a-hello", "narSize": 10},
+                {"path": "/nix/store/b-missing", "valid": false},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_normalize_derivations_formats() {
+        let legacy = json!({"/nix/store/a-hello.drv": {"name": "hello"}});
+        assert_eq!(normalize_derivations(legacy.clone()), legacy);
+
+        let wrapped = json!({
+            "version": 4,
+            "derivations": {"a-hello.drv": {"name": "hello", "inputs": {"drvs": {"b-dep.drv": {}}}}},
+        });
+        let normalized = normalize_derivations(wrapped);
+        let drv = &normalized["/nix/store/a-hello.drv"];
+        assert_eq!(drv["name"], "hello");
+        assert_eq!(derivation_input_count(drv), 1);
     }
 }
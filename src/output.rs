@@ -1,5 +1,23 @@
+use crate::condense::{condense_errors, KeptSection, DEFAULT_ERROR_CONTEXT};
+use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::LazyLock;
+
+// CSI sequences (colors, cursor movement) and OSC sequences (hyperlinks, titles)
+static ANSI_ESCAPE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)").unwrap()
+});
+
+// nix's per-path progress messages, which come in long runs while substituting
+static PROGRESS_LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(copying path|downloading|querying info about|fetching|evaluating file) '")
+        .unwrap()
+});
+
+/// Shortest run of progress lines collapsed into a summary
+const MIN_COLLAPSED_RUN: usize = 3;
 
 /// Configuration for output limiting, loaded from config file
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -14,6 +32,9 @@ pub struct OutputLimitsConfig {
     pub log_tail_default: Option<usize>,
     /// Default limit for search results (default: 50)
     pub search_limit_default: Option<usize>,
+    /// Approximate tokens a whole tool result may use, 0 for no limit
+    /// (default: 25_000)
+    pub response_token_budget: Option<usize>,
 }
 
 impl OutputLimitsConfig {
@@ -36,6 +57,13 @@ impl OutputLimitsConfig {
     pub fn search_limit_default(&self) -> usize {
         self.search_limit_default.unwrap_or(50)
     }
+
+    pub fn response_token_budget(&self) -> Option<usize> {
+        match self.response_token_budget.unwrap_or(25_000) {
+            0 => None,
+            budget => Some(budget),
+        }
+    }
 }
 
 /// Parameters for limiting text output
@@ -49,6 +77,30 @@ pub struct OutputLimits {
     pub max_bytes: Option<usize>,
     /// Maximum lines to return (applied after head/tail)
     pub max_lines: Option<usize>,
+    /// What to keep before the limits above are applied
+    #[serde(default)]
+    pub mode: OutputMode,
+    /// Keep only lines matching this regex, numbered, before head/tail.
+    /// Rejected by [`validate_grep`] together with errors mode.
+    pub grep: Option<String>,
+    /// Lines of context around each kept match (default: 3 in errors mode,
+    /// 0 with `grep`)
+    pub context: Option<usize>,
+    /// Strip ANSI codes, resolve carriage returns and collapse progress
+    /// lines before limiting (default: true)
+    pub normalize: Option<bool>,
+}
+
+/// How much of a log to keep before head/tail/byte limits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// The whole output
+    #[default]
+    Full,
+    /// Only error blocks, compiler errors, test failures and the tail of the
+    /// failing phase, falling back to the whole output if none are found
+    Errors,
 }
 
 /// Parameters for limiting JSON array output
@@ -61,7 +113,7 @@ pub struct ArrayLimits {
 }
 
 /// Information about truncation that occurred
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct TruncationInfo {
     /// Original size in bytes
     pub original_bytes: usize,
@@ -79,9 +131,15 @@ pub struct TruncationInfo {
     /// Items kept after truncation
     #[serde(skip_serializing_if = "Option::is_none")]
     pub kept_items: Option<usize>,
-    /// Position of kept content: "head", "tail", or "middle"
+    /// Position of kept content: "head", "tail", "middle", "errors" or "grep"
     #[serde(skip_serializing_if = "Option::is_none")]
     pub position: Option<String>,
+    /// Parts of the original output kept in errors mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kept_sections: Option<Vec<KeptSection>>,
+    /// Lines matching `grep`, before head/tail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_count: Option<usize>,
 }
 
 /// Result of limiting text output
@@ -109,7 +167,7 @@ pub struct LimitedArray {
 }
 
 /// Pagination metadata for array results
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct PaginationInfo {
     /// Current offset
     pub offset: usize,
@@ -121,6 +179,72 @@ pub struct PaginationInfo {
     pub has_more: bool,
 }
 
+/// Remove ANSI escape sequences from text
+pub fn strip_ansi(input: &str) -> String {
+    ANSI_ESCAPE.replace_all(input, "").into_owned()
+}
+
+/// Clean up terminal output for an agent: strip escape sequences, apply
+/// `\r` overwrites the way a terminal would, and collapse runs of nix
+/// progress lines into one counted line
+pub fn normalize_output(input: &str) -> String {
+    let lines: Vec<String> = input.lines().map(clean_line).collect();
+
+    let mut out: Vec<String> = Vec::with_capacity(lines.len());
+    let mut i = 0;
+    while i < lines.len() {
+        let kind = progress_kind(&lines[i]);
+        let run = match kind {
+            Some(kind) => lines[i..]
+                .iter()
+                .take_while(|line| progress_kind(line) == Some(kind))
+                .count(),
+            None => 1,
+        };
+        match kind {
+            Some(kind) if run >= MIN_COLLAPSED_RUN => {
+                out.push(format!("[{} '{}' lines collapsed]", run, kind));
+            }
+            _ => out.extend_from_slice(&lines[i..i + run]),
+        }
+        i += run;
+    }
+    out.join("\n")
+}
+
+/// A line without ANSI codes or text overwritten after `\r`
+fn clean_line(line: &str) -> String {
+    resolve_carriage_returns(&strip_ansi(line))
+}
+
+fn progress_kind(line: &str) -> Option<&str> {
+    PROGRESS_LINE
+        .captures(line)
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str())
+}
+
+/// Overlay the segments of a line split by `\r`, as a terminal shows them
+fn resolve_carriage_returns(line: &str) -> String {
+    if !line.contains('\r') {
+        return line.to_string();
+    }
+    let mut screen: Vec<char> = Vec::new();
+    let mut cursor = 0;
+    for c in line.chars() {
+        if c == '\r' {
+            cursor = 0;
+        } else if cursor < screen.len() {
+            screen[cursor] = c;
+            cursor += 1;
+        } else {
+            screen.push(c);
+            cursor += 1;
+        }
+    }
+    screen.into_iter().collect()
+}
+
 /// Apply default max_bytes truncation to stderr
 pub fn limit_stderr(input: &str) -> LimitedOutput {
     let config = OutputLimitsConfig::default();
@@ -132,7 +256,137 @@ pub fn limit_stderr(input: &str) -> LimitedOutput {
 }
 
 /// Apply limits to text output
+///
+/// `original_bytes` and `original_lines` describe `input` as the command
+/// wrote it, before normalization collapsed anything.
 pub fn limit_text_output(input: &str, limits: &OutputLimits) -> LimitedOutput {
+    if !limits.normalize.unwrap_or(true) {
+        return limit_normalized(input, limits);
+    }
+    // Grep numbers lines as they are in the raw output, so it sees them
+    // cleaned up but not collapsed
+    let normalized = match limits.grep {
+        Some(_) => input.lines().map(clean_line).collect::<Vec<_>>().join("\n"),
+        None => normalize_output(input),
+    };
+    let mut limited = limit_normalized(&normalized, limits);
+    if let Some(info) = &mut limited.truncation_info {
+        info.original_bytes = input.len();
+        info.original_lines = Some(input.lines().count());
+    }
+    limited
+}
+
+fn limit_normalized(input: &str, limits: &OutputLimits) -> LimitedOutput {
+    if let Some(pattern) = &limits.grep {
+        // Patterns are checked by `validate_grep` before the tool runs
+        let re =
+            Regex::new(pattern).unwrap_or_else(|_| Regex::new(&regex::escape(pattern)).unwrap());
+        let matches = grep_lines(input, &re, limits.context.unwrap_or(0));
+        let limited = limit_lines(&matches.lines.join("\n"), limits);
+        let content = limited.content;
+        return LimitedOutput {
+            truncation_info: Some(TruncationInfo {
+                original_bytes: input.len(),
+                original_lines: Some(input.lines().count()),
+                original_items: None,
+                kept_bytes: content.len(),
+                kept_lines: Some(content.lines().count()),
+                kept_items: None,
+                position: Some("grep".to_string()),
+                kept_sections: None,
+                match_count: Some(matches.match_count),
+            }),
+            content,
+            truncated: matches.dropped_lines > 0 || limited.truncated,
+        };
+    }
+
+    if limits.mode == OutputMode::Errors {
+        let context = limits.context.unwrap_or(DEFAULT_ERROR_CONTEXT);
+        if let Some(condensed) = condense_errors(input, context) {
+            let limited = limit_lines(&condensed.content, limits);
+            let content = limited.content;
+            return LimitedOutput {
+                truncation_info: Some(TruncationInfo {
+                    original_bytes: input.len(),
+                    original_lines: Some(input.lines().count()),
+                    original_items: None,
+                    kept_bytes: content.len(),
+                    kept_lines: Some(content.lines().count()),
+                    kept_items: None,
+                    position: Some("errors".to_string()),
+                    kept_sections: Some(condensed.sections),
+                    match_count: None,
+                }),
+                content,
+                truncated: condensed.dropped_lines > 0 || limited.truncated,
+            };
+        }
+    }
+    limit_lines(input, limits)
+}
+
+/// Lines picked out by [`grep_lines`]
+#[derive(Debug, Clone)]
+pub struct GrepMatches {
+    /// Matches as `12:line` and context as `11-line`, like `grep -n`, with
+    /// `--` between groups that aren't adjacent
+    pub lines: Vec<String>,
+    pub match_count: usize,
+    /// Lines of the input neither matching nor in context
+    pub dropped_lines: usize,
+}
+
+/// Lines of `input` matching `pattern`, with `context` lines around each
+pub fn grep_lines(input: &str, pattern: &Regex, context: usize) -> GrepMatches {
+    let lines: Vec<&str> = input.lines().collect();
+    let matched: Vec<bool> = lines.iter().map(|line| pattern.is_match(line)).collect();
+    let match_count = matched.iter().filter(|m| **m).count();
+
+    let mut keep = vec![false; lines.len()];
+    for (i, _) in matched.iter().enumerate().filter(|(_, m)| **m) {
+        let end = (i + context).min(lines.len() - 1);
+        keep[i.saturating_sub(context)..=end]
+            .iter_mut()
+            .for_each(|k| *k = true);
+    }
+
+    let mut out = Vec::new();
+    let mut last = None;
+    for (i, line) in lines.iter().enumerate().filter(|(i, _)| keep[*i]) {
+        if last.is_some_and(|last| last + 1 < i) {
+            out.push("--".to_string());
+        }
+        let separator = if matched[i] { ':' } else { '-' };
+        out.push(format!("{}{}{}", i + 1, separator, line));
+        last = Some(i);
+    }
+
+    GrepMatches {
+        lines: out,
+        match_count,
+        dropped_lines: keep.iter().filter(|k| !**k).count(),
+    }
+}
+
+/// Check a tool call's `grep` argument, so a bad pattern is reported
+/// instead of silently matching nothing, and so is asking for grep and
+/// errors mode at once, where one would silently win
+pub fn validate_grep(arguments: &Value) -> Result<(), String> {
+    let Some(pattern) = arguments.get("grep").and_then(Value::as_str) else {
+        return Ok(());
+    };
+    if arguments.get("mode").and_then(Value::as_str) == Some("errors") {
+        return Err("grep can't be combined with mode 'errors'".to_string());
+    }
+    Regex::new(pattern)
+        .map(|_| ())
+        .map_err(|e| format!("Invalid grep pattern: {}", e))
+}
+
+/// Apply head/tail, line and byte limits
+fn limit_lines(input: &str, limits: &OutputLimits) -> LimitedOutput {
     let original_bytes = input.len();
     let lines: Vec<&str> = input.lines().collect();
     let original_lines = lines.len();
@@ -201,6 +455,8 @@ pub fn limit_text_output(input: &str, limits: &OutputLimits) -> LimitedOutput {
             kept_lines: Some(content.lines().count()),
             kept_items: None,
             position,
+            kept_sections: None,
+            match_count: None,
         })
     } else {
         None
@@ -248,12 +504,17 @@ pub fn merge_limits_with_config(
             tail: limits.tail,
             max_bytes: limits.max_bytes.or(Some(config.default_max_bytes())),
             max_lines: limits.max_lines.or(Some(config.default_max_lines())),
+            mode: limits.mode,
+            grep: limits.grep,
+            context: limits.context,
+            normalize: limits.normalize,
         },
         None => OutputLimits {
             head: None,
             tail: None,
             max_bytes: Some(config.default_max_bytes()),
             max_lines: Some(config.default_max_lines()),
+            ..Default::default()
         },
     }
 }
@@ -279,6 +540,135 @@ pub fn merge_array_limits_with_config(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_normalize_output() {
+        let input = concat!(
+            "\x1b[31;1merror:\x1b[0m build failed\n",
+            "progress  10%\rprogress 100%\n",
+            "copying path '/nix/store/aaa-a' from 'https://cache.nixos.org'...\n",
+            "copying path '/nix/store/bbb-b' from 'https://cache.nixos.org'...\n",
+            "copying path '/nix/store/ccc-c' from 'https://cache.nixos.org'...\n",
+            "fetching 'github:NixOS/nixpkgs'\n",
+            "done\r\n",
+        );
+        assert_eq!(
+            normalize_output(input),
+            concat!(
+                "error: build failed\n",
+                "progress 100%\n",
+                "[3 'copying path' lines collapsed]\n",
+                "fetching 'github:NixOS/nixpkgs'\n",
+                "done",
+            )
+        );
+    }
+
+    #[test]
+    fn test_grep_lines_with_context() {
+        let input = "a\nerror: one\nb\nc\nd\ne\nerror: two\nf";
+        let re = Regex::new("^error").unwrap();
+        let matches = grep_lines(input, &re, 1);
+        assert_eq!(matches.match_count, 2);
+        assert_eq!(matches.dropped_lines, 2);
+        assert_eq!(
+            matches.lines,
+            vec![
+                "1-a",
+                "2:error: one",
+                "3-b",
+                "--",
+                "6-e",
+                "7:error: two",
+                "8-f"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limit_text_output_grep_before_tail() {
+        let input = (1..=20)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let limits = OutputLimits {
+            grep: Some("1$".to_string()),
+            tail: Some(1),
+            ..Default::default()
+        };
+        let limited = limit_text_output(&input, &limits);
+        assert_eq!(limited.content, "11:line 11");
+        let info = limited.truncation_info.unwrap();
+        assert_eq!(info.match_count, Some(2));
+        assert_eq!(info.position.as_deref(), Some("grep"));
+    }
+
+    #[test]
+    fn test_limit_text_output_grep_numbers_raw_lines() {
+        let input = concat!(
+            "copying path '/nix/store/aaa-a' from 'https://cache.nixos.org'...\n",
+            "copying path '/nix/store/bbb-b' from 'https://cache.nixos.org'...\n",
+            "copying path '/nix/store/ccc-c' from 'https://cache.nixos.org'...\n",
+            "\x1b[31;1merror:\x1b[0m build failed",
+        );
+        let limits = OutputLimits {
+            grep: Some("^error".to_string()),
+            ..Default::default()
+        };
+        let limited = limit_text_output(input, &limits);
+        assert_eq!(limited.content, "4:error: build failed");
+        assert!(limited.truncated);
+
+        let limits = OutputLimits {
+            grep: Some("error|copying".to_string()),
+            ..Default::default()
+        };
+        let limited = limit_text_output(input, &limits);
+        assert_eq!(limited.content.lines().count(), 4);
+        assert!(!limited.truncated);
+    }
+
+    #[test]
+    fn test_validate_grep() {
+        assert!(validate_grep(&serde_json::json!({"grep": "error|warning"})).is_ok());
+        assert!(validate_grep(&serde_json::json!({"grep": "("})).is_err());
+        assert!(validate_grep(&serde_json::json!({})).is_ok());
+        assert!(validate_grep(&serde_json::json!({"grep": "x", "mode": "errors"})).is_err());
+        assert!(validate_grep(&serde_json::json!({"grep": "x", "mode": "full"})).is_ok());
+    }
+
+    #[test]
+    fn test_resolve_carriage_returns_overlays() {
+        assert_eq!(resolve_carriage_returns("[###   ]\r[####"), "[####  ]");
+        assert_eq!(resolve_carriage_returns("plain"), "plain");
+    }
+
+    #[test]
+    fn test_limit_text_output_normalize_opt_out() {
+        let input = "\x1b[1mbold\x1b[0m";
+        let limited = limit_text_output(input, &OutputLimits::default());
+        assert_eq!(limited.content, "bold");
+
+        let limits = OutputLimits {
+            normalize: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(limit_text_output(input, &limits).content, input);
+    }
+
+    #[test]
+    fn test_limit_text_output_measures_raw_input() {
+        let input = "\x1b[31merror\x1b[0m\n10%\r100%\nline 3\nline 4\n";
+        let limits = OutputLimits {
+            tail: Some(1),
+            ..Default::default()
+        };
+        let limited = limit_text_output(input, &limits);
+        assert_eq!(limited.content, "line 4");
+        let info = limited.truncation_info.unwrap();
+        assert_eq!(info.original_bytes, input.len());
+        assert_eq!(info.original_lines, Some(4));
+    }
+
     #[test]
     fn test_limit_text_no_truncation() {
         let input = "line1\nline2\nline3";
@@ -349,6 +739,45 @@ mod tests {
         assert_eq!(result.content, "line1\nline2\nline3");
     }
 
+    #[test]
+    fn test_limit_text_errors_mode() {
+        let input = "unpacking\nconfiguring\nerror: builder failed\n       exit code 2\ndone";
+        let limits = OutputLimits {
+            mode: OutputMode::Errors,
+            context: Some(0),
+            ..Default::default()
+        };
+        let result = limit_text_output(input, &limits);
+
+        assert!(result.truncated);
+        assert_eq!(
+            result.content,
+            "... 2 lines omitted ...\nerror: builder failed\n       exit code 2\n... 1 lines omitted ..."
+        );
+        let info = result.truncation_info.unwrap();
+        assert_eq!(info.position.as_deref(), Some("errors"));
+        assert_eq!(info.original_lines, Some(5));
+        assert_eq!(info.kept_sections.unwrap()[0].start_line, 3);
+
+        // Nothing error-like falls back to the normal limits
+        let result = limit_text_output("all\ngood", &limits);
+        assert!(!result.truncated);
+
+        // A log that is all error keeps every line
+        let result = limit_text_output("error: builder failed\n       exit code 2", &limits);
+        assert!(!result.truncated);
+        assert_eq!(result.content, "error: builder failed\n       exit code 2");
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(
+            strip_ansi("\x1b[31;1merror:\x1b[0m builder failed"),
+            "error: builder failed"
+        );
+        assert_eq!(strip_ansi("plain text"), "plain text");
+    }
+
     #[test]
     fn test_limit_json_array_no_truncation() {
         let items = vec![
@@ -436,6 +865,13 @@ mod tests {
         assert_eq!(config.default_max_items(), 100);
         assert_eq!(config.log_tail_default(), 500);
         assert_eq!(config.search_limit_default(), 50);
+        assert_eq!(config.response_token_budget(), Some(25_000));
+
+        let unlimited = OutputLimitsConfig {
+            response_token_budget: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(unlimited.response_token_budget(), None);
     }
 
     #[test]
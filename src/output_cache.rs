@@ -0,0 +1,413 @@
+use crate::backend::Stream;
+use crate::config::load_config;
+use crate::context;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+
+/// Streams the full stdout and stderr of every command a tool call runs
+/// into the cache, before any output limits are applied
+#[derive(Debug, Clone)]
+pub struct OutputCapture {
+    id: String,
+    dir: PathBuf,
+    limit: u64,
+    streams: Arc<Mutex<CapturedStreams>>,
+}
+
+#[derive(Debug, Default)]
+struct CapturedStreams {
+    /// Opened on the first line of each stream
+    stdout: Option<BufWriter<File>>,
+    stderr: Option<BufWriter<File>>,
+    /// Streams with a log in the cache
+    written: Vec<Stream>,
+    bytes: u64,
+    /// Bytes already added to the cache's running total
+    counted: u64,
+    /// Set once a log can't be opened, to stop retrying on every line
+    failed: bool,
+}
+
+impl CapturedStreams {
+    fn writer(&mut self, stream: Stream) -> &mut Option<BufWriter<File>> {
+        match stream {
+            Stream::Stdout => &mut self.stdout,
+            Stream::Stderr => &mut self.stderr,
+        }
+    }
+}
+
+impl OutputCapture {
+    /// Capture up to `limit` bytes for the call `id` into `dir`; more could
+    /// never fit in the cache anyway
+    fn new(id: String, dir: PathBuf, limit: u64) -> Self {
+        OutputCapture {
+            id,
+            dir,
+            limit,
+            streams: Arc::new(Mutex::new(CapturedStreams::default())),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn log_path(&self, stream: Stream) -> PathBuf {
+        self.dir.join(format!("{}.log", stream.as_str()))
+    }
+
+    pub fn push(&self, stream: Stream, line: &str) {
+        let mut streams = self.streams.lock().unwrap();
+        let captured = streams.bytes + line.len() as u64 + 1;
+        if captured > self.limit || streams.failed {
+            return;
+        }
+        if streams.writer(stream).is_none() {
+            let path = self.log_path(stream);
+            let file = fs::create_dir_all(&self.dir)
+                .and_then(|()| File::options().create(true).append(true).open(&path));
+            match file {
+                Ok(file) => *streams.writer(stream) = Some(BufWriter::new(file)),
+                Err(e) => {
+                    eprintln!("Warning: failed to cache output of {}: {}", self.id, e);
+                    streams.failed = true;
+                    return;
+                }
+            }
+            if !streams.written.contains(&stream) {
+                streams.written.push(stream);
+            }
+        }
+        if let Some(writer) = streams.writer(stream) {
+            if writeln!(writer, "{}", line).is_ok() {
+                streams.bytes = captured;
+            }
+        }
+    }
+
+    /// Flush and close the capture's logs, returning the streams that had
+    /// any output and the bytes written since the last call
+    fn finish(&self) -> (Vec<Stream>, u64) {
+        let mut streams = self.streams.lock().unwrap();
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            if let Some(mut writer) = streams.writer(stream).take() {
+                if let Err(e) = writer.flush() {
+                    eprintln!("Warning: failed to cache output of {}: {}", self.id, e);
+                    streams.written.retain(|s| *s != stream);
+                }
+            }
+        }
+        let added = streams.bytes - streams.counted;
+        streams.counted = streams.bytes;
+        (streams.written.clone(), added)
+    }
+}
+
+/// Directory holding `<call-id>/{stdout,stderr}.log` for recent tool calls.
+///
+/// Once the total size passes `max_bytes` the least recently written or read
+/// calls are evicted.
+#[derive(Debug)]
+pub struct OutputCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Running total of the cache's size, counted from disk on first use
+    total: Mutex<Option<u64>>,
+}
+
+impl OutputCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        OutputCache {
+            dir,
+            max_bytes,
+            total: Mutex::new(None),
+        }
+    }
+
+    /// The cache configured under `[output_cache]`, or the XDG cache directory
+    pub fn from_config() -> Option<Self> {
+        // Unit tests must not write to the user's cache
+        if cfg!(test) {
+            return None;
+        }
+        let config = load_config().output_cache;
+        if config.max_bytes() == 0 {
+            return None;
+        }
+        config.dir().map(|dir| Self::new(dir, config.max_bytes()))
+    }
+
+    /// Start capturing the output of the call `call_id`
+    pub fn capture(&self, call_id: String) -> OutputCapture {
+        let dir = self.dir.join(&call_id);
+        OutputCapture::new(call_id, dir, self.max_bytes)
+    }
+
+    fn log_path(&self, call_id: &str, stream: Stream) -> PathBuf {
+        self.dir
+            .join(call_id)
+            .join(format!("{}.log", stream.as_str()))
+    }
+
+    /// Finish writing a call's captured output and evict old calls if the
+    /// cache has grown past its limit.
+    ///
+    /// Returns the streams that had any output. This touches the disk, so
+    /// call it off the async runtime.
+    pub fn store(&self, capture: &OutputCapture) -> Vec<Stream> {
+        let (stored, bytes) = capture.finish();
+        if stored.is_empty() {
+            return stored;
+        }
+
+        let mut total = self.total.lock().unwrap();
+        let size = match *total {
+            Some(size) => size + bytes,
+            None => self.scan().0,
+        };
+        *total = Some(if size > self.max_bytes {
+            self.evict(&capture.id)
+        } else {
+            size
+        });
+        stored
+    }
+
+    /// Read a cached stream, marking the call as recently used
+    pub fn read(&self, call_id: &str, stream: Stream) -> Option<String> {
+        if !valid_call_id(call_id) {
+            return None;
+        }
+        let path = self.log_path(call_id, stream);
+        let text = fs::read_to_string(&path).ok()?;
+        if let Ok(file) = File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(text)
+    }
+
+    /// The cache's total size, and each call's last use, size and directory
+    fn scan(&self) -> (u64, Vec<(SystemTime, u64, PathBuf)>) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return (0, Vec::new());
+        };
+
+        let mut calls = Vec::new();
+        let mut total = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(files) = fs::read_dir(&path) else {
+                continue;
+            };
+            let mut size = 0;
+            let mut used = SystemTime::UNIX_EPOCH;
+            for meta in files.flatten().filter_map(|f| f.metadata().ok()) {
+                size += meta.len();
+                used = used.max(meta.modified().unwrap_or(used));
+            }
+            total += size;
+            calls.push((used, size, path));
+        }
+        (total, calls)
+    }
+
+    /// Delete the least recently used calls until the cache fits, never
+    /// touching `keep`, and return the size left
+    fn evict(&self, keep: &str) -> u64 {
+        let (mut total, mut calls) = self.scan();
+        calls.retain(|(_, _, path)| !path.ends_with(keep));
+        calls.sort();
+        for (_, size, path) in calls {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_dir_all(&path).is_ok() {
+                total -= size;
+            }
+        }
+        total
+    }
+}
+
+/// Call ids are uuids; anything else could escape the cache directory
+pub fn valid_call_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+static OUTPUT_CACHE: LazyLock<Option<OutputCache>> = LazyLock::new(OutputCache::from_config);
+
+/// The configured cache, if it is enabled
+pub fn cache() -> Option<&'static OutputCache> {
+    OUTPUT_CACHE.as_ref()
+}
+
+/// A capture for a new tool call, if the cache is enabled
+pub fn new_capture(call_id: String) -> Option<OutputCapture> {
+    cache().map(|cache| cache.capture(call_id))
+}
+
+pub fn output_uri(call_id: &str, stream: Stream) -> String {
+    format!("nix://output/{}/{}", call_id, stream.as_str())
+}
+
+/// Store the current call's output and, if the tool truncated anything,
+/// point the result at the full streams under `full_output`
+pub async fn finish_call(result: Result<Value, String>) -> Result<Value, String> {
+    let Some(capture) = context::current().and_then(|ctx| ctx.output) else {
+        return result;
+    };
+    let Some(cache) = cache() else {
+        return result;
+    };
+    // Flushing and eviction hit the disk, so keep them off the runtime
+    tokio::task::spawn_blocking(move || attach_full_output(cache, &capture, result))
+        .await
+        .unwrap_or_else(|e| Err(format!("Failed to store the call's output: {}", e)))
+}
+
+fn attach_full_output(
+    cache: &OutputCache,
+    capture: &OutputCapture,
+    result: Result<Value, String>,
+) -> Result<Value, String> {
+    let stored = cache.store(capture);
+    let mut value = result?;
+    if stored.is_empty() || !is_truncated(&value) {
+        return Ok(value);
+    }
+    if let Some(fields) = value.as_object_mut() {
+        let uris: serde_json::Map<String, Value> = stored
+            .into_iter()
+            .map(|stream| {
+                let uri = output_uri(capture.id(), stream);
+                (stream.as_str().to_string(), Value::String(uri))
+            })
+            .collect();
+        fields.insert("full_output".to_string(), Value::Object(uris));
+    }
+    Ok(value)
+}
+
+/// Whether any `truncated` flag in a tool result is set
+pub fn is_truncated(value: &Value) -> bool {
+    match value {
+        Value::Object(fields) => fields
+            .iter()
+            .any(|(key, v)| (key == "truncated" && v == &Value::Bool(true)) || is_truncated(v)),
+        Value::Array(items) => items.iter().any(is_truncated),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_cache(max_bytes: u64) -> OutputCache {
+        let dir = std::env::temp_dir().join(format!("chix-output-{}", uuid::Uuid::new_v4()));
+        OutputCache::new(dir, max_bytes)
+    }
+
+    fn capture(cache: &OutputCache, id: &str, stderr: &str) -> OutputCapture {
+        let capture = cache.capture(id.to_string());
+        for line in stderr.lines() {
+            capture.push(Stream::Stderr, line);
+        }
+        capture
+    }
+
+    #[test]
+    fn test_store_and_read() {
+        let cache = temp_cache(1024);
+        let stored = cache.store(&capture(&cache, "call-1", "building\ndone"));
+        assert_eq!(stored, vec![Stream::Stderr]);
+        assert_eq!(
+            cache.read("call-1", Stream::Stderr).as_deref(),
+            Some("building\ndone\n")
+        );
+        assert_eq!(cache.read("call-1", Stream::Stdout), None);
+        assert_eq!(cache.read("../call-1", Stream::Stderr), None);
+
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = temp_cache(25);
+        let old = SystemTime::now() - std::time::Duration::from_secs(60);
+        cache.store(&capture(&cache, "first", "0123456789"));
+        cache.store(&capture(&cache, "second", "0123456789"));
+        for id in ["first", "second"] {
+            let file = File::options()
+                .append(true)
+                .open(cache.log_path(id, Stream::Stderr))
+                .unwrap();
+            file.set_modified(old).unwrap();
+        }
+
+        // Reading marks `first` as used, so `second` goes when `third` arrives
+        cache.read("first", Stream::Stderr).unwrap();
+        cache.store(&capture(&cache, "third", "0123456789"));
+
+        assert!(cache.read("first", Stream::Stderr).is_some());
+        assert!(cache.read("second", Stream::Stderr).is_none());
+        assert!(cache.read("third", Stream::Stderr).is_some());
+
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn test_full_output_only_when_truncated() {
+        let cache = temp_cache(1024);
+        let call = capture(&cache, "call-2", "line 1\nline 2");
+
+        let result = attach_full_output(&cache, &call, Ok(json!({"truncated": false})));
+        assert!(result.unwrap().get("full_output").is_none());
+
+        let result = attach_full_output(
+            &cache,
+            &call,
+            Ok(json!({"commands": [{"truncation_info": {"truncated": true}}]})),
+        );
+        assert_eq!(
+            result.unwrap()["full_output"],
+            json!({"stderr": "nix://output/call-2/stderr"})
+        );
+
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn test_capture_stops_at_limit() {
+        let cache = temp_cache(8);
+        let capture = cache.capture("call-3".to_string());
+        capture.push(Stream::Stdout, "abc");
+        capture.push(Stream::Stdout, "defghij");
+        assert_eq!(cache.store(&capture), vec![Stream::Stdout]);
+        assert_eq!(
+            cache.read("call-3", Stream::Stdout).as_deref(),
+            Some("abc\n")
+        );
+
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn test_running_total_only_evicts_past_limit() {
+        let cache = temp_cache(25);
+        cache.store(&capture(&cache, "first", "0123456789"));
+        assert_eq!(*cache.total.lock().unwrap(), Some(11));
+        cache.store(&capture(&cache, "second", "0123456789"));
+        assert_eq!(*cache.total.lock().unwrap(), Some(22));
+        cache.store(&capture(&cache, "third", "0123456789"));
+        assert_eq!(*cache.total.lock().unwrap(), Some(22));
+
+        let _ = fs::remove_dir_all(&cache.dir);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::flake_ref::{FlakeRef, Installable};
+use crate::workspace::is_store_path;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// How a tool argument names a flake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RefKind {
+    /// A nix flake reference or installable; store paths are left alone
+    Nix,
+    /// A FlakeHub `owner/repo[/version]`, as `fh` takes it
+    FlakeHub,
+}
+
+/// An argument that names a flake, its kind and the value the tool uses
+/// without it
+type FlakeArg = (&'static str, RefKind, Option<&'static str>);
+
+/// Arguments of each tool that name a flake. A test checks this against
+/// the tool schemas, so a new tool can't slip past the flake ref rules.
+fn flake_ref_args(tool: &str) -> &'static [FlakeArg] {
+    use RefKind::{FlakeHub, Nix};
+    const FLAKE_REF: &[FlakeArg] = &[("flake_ref", Nix, Some("."))];
+    const INSTALLABLE: &[FlakeArg] = &[("installable", Nix, Some(".#default"))];
+    const FH_FLAKE_REF: &[FlakeArg] = &[("flake_ref", FlakeHub, None)];
+    const FH_FLAKE: &[FlakeArg] = &[("flake", FlakeHub, None)];
+    match tool {
+        "flake_show" | "flake_check" | "flake_metadata" | "flake_update" | "develop_run" => {
+            FLAKE_REF
+        }
+        "flake_lock" => &[
+            ("flake_ref", Nix, Some(".")),
+            ("override_inputs", Nix, None),
+        ],
+        "build" | "run" | "derivation_show" => INSTALLABLE,
+        "log" | "copy" | "eval" => &[("installable", Nix, None)],
+        "flake_init" => &[("template", Nix, None)],
+        "search" => &[("flake_ref", Nix, Some("nixpkgs"))],
+        "store_path_info" => &[("path", Nix, None)],
+        "fh_add" => &[("input_ref", FlakeHub, None)],
+        "fh_resolve" | "fh_fetch" => FH_FLAKE_REF,
+        "fh_list_releases" | "fh_list_versions" => FH_FLAKE,
+        _ => &[],
+    }
+}
+
+/// Arguments holding nix code, which can fetch any flake with
+/// `builtins.getFlake`
+const NIX_CODE_ARGS: &[&str] = &["expr", "apply"];
+
+static FLAKEHUB_NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9._\-]+$").unwrap());
+
+/// What an agent may do, from the `[policy]` section of config.toml, e.g.
+///
+/// ```toml
+/// [policy]
+/// deny_tools = ["store_gc", "fh_login"]
+/// allow_flake_refs = ["github.com/NixOS/*", "path:*", "nixpkgs"]
+/// allow_copy_destinations = ["ssh://builder.internal"]
+/// ```
+///
+/// Patterns are globs where `*` matches anything, `/` included. Deny rules
+/// win over allow rules, and an empty allow list allows everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PolicyConfig {
+    #[serde(default)]
+    pub allow_tools: Vec<String>,
+    #[serde(default)]
+    pub deny_tools: Vec<String>,
+    /// Matched against `host/owner/repo` for github, gitlab and sourcehut
+    /// refs, `host/path` for URLs, `path:<dir>` for local flakes, the flake
+    /// id for registry refs like `nixpkgs` and `flakehub.com/owner/repo` for
+    /// FlakeHub refs. While any of these rules are set, refs that can't be
+    /// parsed and `eval` expressions are denied.
+    #[serde(default)]
+    pub allow_flake_refs: Vec<String>,
+    #[serde(default)]
+    pub deny_flake_refs: Vec<String>,
+    /// Matched against the `to` and `from` store URLs of `copy`
+    #[serde(default)]
+    pub allow_copy_destinations: Vec<String>,
+    #[serde(default)]
+    pub deny_copy_destinations: Vec<String>,
+}
+
+impl PolicyConfig {
+    /// Check a tool call against every rule, naming the rule that denied it
+    pub fn check(&self, tool: &str, arguments: &Value) -> Result<(), String> {
+        check_rules("tool", tool, &self.allow_tools, &self.deny_tools, "tools")?;
+
+        if !self.allow_flake_refs.is_empty() || !self.deny_flake_refs.is_empty() {
+            self.check_flake_refs(tool, arguments)?;
+        }
+
+        if tool == "copy" {
+            for (key, what) in [("to", "copy destination"), ("from", "copy source")] {
+                if let Some(store) = arguments.get(key).and_then(Value::as_str) {
+                    check_rules(
+                        what,
+                        store,
+                        &self.allow_copy_destinations,
+                        &self.deny_copy_destinations,
+                        "copy_destinations",
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_flake_refs(&self, tool: &str, arguments: &Value) -> Result<(), String> {
+        for key in NIX_CODE_ARGS {
+            if arguments.get(*key).is_some() {
+                return Err(format!(
+                    "Denied by policy: `{}` can fetch any flake while [policy] has flake_refs rules",
+                    key
+                ));
+            }
+        }
+
+        for (name, kind, default) in flake_ref_args(tool) {
+            let values: Vec<&str> = match arguments.get(*name) {
+                Some(Value::String(value)) => vec![value],
+                // `override_inputs` maps input names to refs
+                Some(Value::Object(map)) => map.values().filter_map(Value::as_str).collect(),
+                Some(_) => continue,
+                None => default.iter().copied().collect(),
+            };
+            for value in values {
+                self.check_flake_ref_value(value, *kind)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a flake reference named outside a tool call, such as in a
+    /// resource URI, against the flake_refs rules
+    pub fn check_flake_ref(&self, value: &str) -> Result<(), String> {
+        if self.allow_flake_refs.is_empty() && self.deny_flake_refs.is_empty() {
+            return Ok(());
+        }
+        self.check_flake_ref_value(value, RefKind::Nix)
+    }
+
+    fn check_flake_ref_value(&self, value: &str, kind: RefKind) -> Result<(), String> {
+        let source = match kind {
+            RefKind::Nix if is_store_path(Path::new(value)) => return Ok(()),
+            RefKind::Nix => Installable::parse(value).map(|i| flake_source(&i.flake_ref)),
+            RefKind::FlakeHub => flakehub_source(value),
+        }
+        .map_err(|reason| {
+            format!(
+                "Denied by policy: flake reference `{}` can't be checked against [policy] flake_refs rules: {}",
+                value, reason
+            )
+        })?;
+        check_rules(
+            "flake reference",
+            &source,
+            &self.allow_flake_refs,
+            &self.deny_flake_refs,
+            "flake_refs",
+        )
+    }
+}
+
+fn check_rules(
+    what: &str,
+    value: &str,
+    allow: &[String],
+    deny: &[String],
+    rules: &str,
+) -> Result<(), String> {
+    if let Some(rule) = deny.iter().find(|p| glob_match(p, value)) {
+        return Err(format!(
+            "Denied by policy: {} `{}` matches \"{}\" in [policy] deny_{}",
+            what, value, rule, rules
+        ));
+    }
+    if !allow.is_empty() && !allow.iter().any(|p| glob_match(p, value)) {
+        return Err(format!(
+            "Denied by policy: {} `{}` matches no rule in [policy] allow_{}",
+            what, value, rules
+        ));
+    }
+    Ok(())
+}
+
+/// Where a flake comes from, in the form policy patterns match against
+fn flake_source(flake_ref: &FlakeRef) -> String {
+    let default_host = match flake_ref.scheme.as_str() {
+        "github" => "github.com",
+        "gitlab" => "gitlab.com",
+        "sourcehut" => "git.sr.ht",
+        "path" => return format!("path:{}", flake_ref.path.as_deref().unwrap_or_default()),
+        "indirect" => return flake_ref.id.clone().unwrap_or_default(),
+        _ => {
+            return format!(
+                "{}{}",
+                flake_ref.host.as_deref().unwrap_or_default(),
+                flake_ref.path.as_deref().unwrap_or_default()
+            )
+        }
+    };
+    format!(
+        "{}/{}/{}",
+        flake_ref.host.as_deref().unwrap_or(default_host),
+        flake_ref.owner.as_deref().unwrap_or_default(),
+        flake_ref.repo.as_deref().unwrap_or_default()
+    )
+}
+
+/// `flakehub.com/owner/repo` for `owner/repo`, `owner/repo/0.1.*`,
+/// `owner/repo/*#attr` or the same under `https://flakehub.com/f/`
+fn flakehub_source(input: &str) -> Result<String, String> {
+    let reference = input.split('#').next().unwrap_or_default();
+    let reference = reference
+        .strip_prefix("https://flakehub.com/f/")
+        .unwrap_or(reference);
+    let mut parts = reference.splitn(3, '/');
+    match (parts.next(), parts.next()) {
+        (Some(owner), Some(repo))
+            if FLAKEHUB_NAME.is_match(owner) && FLAKEHUB_NAME.is_match(repo) =>
+        {
+            Ok(format!("flakehub.com/{}/{}", owner, repo))
+        }
+        _ => Err("expected `owner/repo` or `owner/repo/version`".to_string()),
+    }
+}
+
+/// Match `text` against a pattern where `*` is any run of characters and
+/// `?` any single character
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn policy(toml_str: &str) -> PolicyConfig {
+        toml::from_str(toml_str).unwrap()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("github.com/NixOS/*", "github.com/NixOS/nixpkgs"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("ssh://builder?", "ssh://builder1"));
+        assert!(glob_match("*.internal*", "ssh://cache.internal/store"));
+        assert!(!glob_match("github.com/NixOS/*", "github.com/evil/nixpkgs"));
+        assert!(!glob_match("store_*", "build"));
+    }
+
+    #[test]
+    fn test_deny_tool_names_rule() {
+        let policy = policy(r#"deny_tools = ["store_gc", "fh_*"]"#);
+        let err = policy.check("fh_login", &json!({})).unwrap_err();
+        assert!(err.contains("\"fh_*\" in [policy] deny_tools"), "{}", err);
+        assert!(policy.check("build", &json!({})).is_ok());
+    }
+
+    #[test]
+    fn test_flake_ref_allow_list() {
+        let policy = policy(
+            r#"
+allow_flake_refs = ["github.com/NixOS/*", "path:*", "nixpkgs"]
+deny_flake_refs = ["github.com/NixOS/secret"]
+"#,
+        );
+        let ok = |installable: &str| policy.check("build", &json!({"installable": installable}));
+
+        assert!(ok("github:NixOS/nixpkgs#hello").is_ok());
+        assert!(ok(".#default").is_ok());
+        assert!(ok("nixpkgs#hello").is_ok());
+        let err = ok("github:evil/repo#x").unwrap_err();
+        assert!(err.contains("matches no rule in [policy] allow_flake_refs"));
+        let err = ok("github:NixOS/secret").unwrap_err();
+        assert!(err.contains("deny_flake_refs"));
+        assert!(ok("git+https://example.com/repo.git").is_err());
+    }
+
+    #[test]
+    fn test_flake_refs_fail_closed() {
+        let policy = policy(r#"allow_flake_refs = ["github.com/NixOS/*", "flakehub.com/NixOS/*"]"#);
+
+        let err = policy
+            .check("build", &json!({"installable": "github:NixOS/nixpkgs#a b"}))
+            .unwrap_err();
+        assert!(err.contains("can't be checked"), "{}", err);
+        // Left out, the tool builds `.#default`
+        assert!(policy.check("build", &json!({})).is_err());
+        assert!(PolicyConfig::default()
+            .check("build", &json!({"installable": "#a b"}))
+            .is_ok());
+
+        let err = policy
+            .check("fh_add", &json!({"input_ref": "evil/repo/0.1.*"}))
+            .unwrap_err();
+        assert!(err.contains("`flakehub.com/evil/repo`"), "{}", err);
+        assert!(policy
+            .check("fh_add", &json!({"input_ref": "NixOS/nixpkgs/0.2411.*"}))
+            .is_ok());
+        assert!(policy
+            .check(
+                "fh_fetch",
+                &json!({"flake_ref": "https://flakehub.com/f/NixOS/nixpkgs/*#hello"})
+            )
+            .is_ok());
+
+        assert!(policy
+            .check("store_path_info", &json!({"path": "github:evil/repo#x"}))
+            .is_err());
+        assert!(policy
+            .check("store_path_info", &json!({"path": "/nix/store/abc-hello"}))
+            .is_ok());
+        assert!(policy
+            .check(
+                "build",
+                &json!({"installable": "/nix/store/../../home/victim/flake#x"})
+            )
+            .is_err());
+        assert!(policy
+            .check(
+                "flake_lock",
+                &json!({"flake_ref": "github:NixOS/nixpkgs", "override_inputs": {"x": "github:evil/x"}})
+            )
+            .is_err());
+        let err = policy
+            .check(
+                "eval",
+                &json!({"expr": "builtins.getFlake \"github:evil/x\""}),
+            )
+            .unwrap_err();
+        assert!(err.contains("`expr`"), "{}", err);
+    }
+
+    #[test]
+    fn test_flake_ref_args_cover_tool_schemas() {
+        let names_flake = Regex::new(r"(?i)installable|flake references?\b|flake to ").unwrap();
+        let is_nix_code = Regex::new(r"^Nix (expression|function)").unwrap();
+        for tool in crate::tools::list_tools() {
+            let properties = tool.input_schema["properties"].as_object().unwrap();
+            let covered = flake_ref_args(tool.name);
+            for (name, property) in properties {
+                let description = property["description"].as_str().unwrap_or_default();
+                if is_nix_code.is_match(description) {
+                    assert!(
+                        NIX_CODE_ARGS.contains(&name.as_str()),
+                        "{}.{} holds nix code but isn't in NIX_CODE_ARGS",
+                        tool.name,
+                        name
+                    );
+                } else if names_flake.is_match(description) {
+                    assert!(
+                        covered.iter().any(|(arg, _, _)| arg == name),
+                        "{}.{} names a flake but isn't in flake_ref_args",
+                        tool.name,
+                        name
+                    );
+                }
+            }
+            assert!(
+                covered.is_empty() || tool.annotations.open_world_hint,
+                "{} takes a flake reference but isn't marked open world",
+                tool.name
+            );
+            for (arg, _, _) in covered {
+                assert!(
+                    properties.contains_key(*arg),
+                    "{} has no `{}` argument",
+                    tool.name,
+                    arg
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_copy_destinations() {
+        let policy = policy(r#"allow_copy_destinations = ["ssh://builder.internal"]"#);
+        let args = |to: &str| json!({"installable": ".#default", "to": to});
+        assert!(policy
+            .check("copy", &args("ssh://builder.internal"))
+            .is_ok());
+        let err = policy.check("copy", &args("s3://bucket")).unwrap_err();
+        assert!(err.contains("copy destination `s3://bucket`"));
+        let err = policy
+            .check(
+                "copy",
+                &json!({"installable": ".#default", "from": "https://evil.example"}),
+            )
+            .unwrap_err();
+        assert!(err.contains("copy source"), "{}", err);
+    }
+}
@@ -1,7 +1,9 @@
-use crate::nix_runner::run_nix_command;
+use crate::config::load_config;
+use crate::nix_runner::{normalize_path_info, run_nix_command};
 use crate::output::PaginationInfo;
 use crate::resources::{ParsedUri, ResourceContent};
 use crate::validators::{validate_flake_ref, validate_store_path};
+use crate::workspace::check_flake_ref_path;
 use serde::Serialize;
 
 #[derive(Debug, Serialize)]
@@ -25,7 +27,9 @@ pub async fn read_closure(parsed: &ParsedUri) -> Result<ResourceContent, String>
     if path.starts_with("/nix/store/") {
         validate_store_path(&path).map_err(|e| e.to_string())?;
     } else {
-        validate_flake_ref(&path).map_err(|e| e.to_string())?;
+        let flake_ref = validate_flake_ref(&path).map_err(|e| e.to_string())?;
+        check_flake_ref_path(&flake_ref, None).await?;
+        load_config().policy.check_flake_ref(&path)?;
     }
 
     // Parse pagination params
@@ -46,6 +50,7 @@ pub async fn read_closure(parsed: &ParsedUri) -> Result<ResourceContent, String>
 
     let parsed_json: serde_json::Value =
         serde_json::from_str(&result.stdout).map_err(|e| e.to_string())?;
+    let parsed_json = normalize_path_info(parsed_json);
 
     let response = if let serde_json::Value::Array(arr) = parsed_json {
         let total = arr.len();
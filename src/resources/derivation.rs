@@ -1,7 +1,9 @@
-use crate::nix_runner::run_nix_command;
+use crate::config::load_config;
+use crate::nix_runner::{derivation_input_count, normalize_derivations, run_nix_command};
 use crate::output::PaginationInfo;
 use crate::resources::{ParsedUri, ResourceContent};
 use crate::validators::{validate_flake_ref, validate_store_path};
+use crate::workspace::check_flake_ref_path;
 use serde::Serialize;
 
 #[derive(Debug, Serialize)]
@@ -34,11 +36,7 @@ fn extract_summary(path: &str, drv: &serde_json::Value) -> DerivationSummary {
         .map(|o| o.keys().cloned().collect())
         .unwrap_or_default();
 
-    let input_count = drv
-        .get("inputDrvs")
-        .and_then(|v| v.as_object())
-        .map(|o| o.len())
-        .unwrap_or(0);
+    let input_count = derivation_input_count(drv);
 
     DerivationSummary {
         path: path.to_string(),
@@ -63,7 +61,9 @@ pub async fn read_derivation(parsed: &ParsedUri) -> Result<ResourceContent, Stri
     if path.starts_with("/nix/store/") {
         validate_store_path(&path).map_err(|e| e.to_string())?;
     } else {
-        validate_flake_ref(&path).map_err(|e| e.to_string())?;
+        let flake_ref = validate_flake_ref(&path).map_err(|e| e.to_string())?;
+        check_flake_ref_path(&flake_ref, None).await?;
+        load_config().policy.check_flake_ref(&path)?;
     }
 
     // Parse params
@@ -99,6 +99,7 @@ pub async fn read_derivation(parsed: &ParsedUri) -> Result<ResourceContent, Stri
 
     let parsed_json: serde_json::Value =
         serde_json::from_str(&result.stdout).map_err(|e| e.to_string())?;
+    let parsed_json = normalize_derivations(parsed_json);
 
     let response = if let serde_json::Value::Object(map) = parsed_json {
         let total = map.len();
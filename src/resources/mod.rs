@@ -1,10 +1,14 @@
 mod build_log;
 mod closure;
 mod derivation;
+mod output;
+mod task;
 
 pub use build_log::read_build_log;
 pub use closure::read_closure;
 pub use derivation::read_derivation;
+pub use output::read_output;
+pub use task::read_task;
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -14,6 +18,8 @@ use std::collections::HashMap;
 /// - nix://build-log/abc123-hello?offset=0&limit=1000
 /// - nix://derivation/abc123-hello.drv?summary=true
 /// - nix://closure/abc123-hello?offset=0&limit=100
+/// - nix://task/{task-id}?stream=stderr&limit=100
+/// - nix://output/{call-id}/stderr?grep=error&limit=50
 
 #[derive(Debug, Serialize)]
 pub struct ResourceInfo {
@@ -37,6 +43,11 @@ pub struct ResourceReadParams {
     pub uri: String,
 }
 
+#[derive(Debug, Deserialize, Default)]
+pub struct ResourceSubscribeParams {
+    pub uri: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct ParsedUri {
     pub resource_type: String,
@@ -73,7 +84,7 @@ pub fn parse_nix_uri(uri: &str) -> Result<ParsedUri, String> {
             if let Some(idx) = pair.find('=') {
                 let key = &pair[..idx];
                 let value = &pair[idx + 1..];
-                params.insert(key.to_string(), value.to_string());
+                params.insert(key.to_string(), percent_decode(value));
             }
         }
     }
@@ -85,6 +96,27 @@ pub fn parse_nix_uri(uri: &str) -> Result<ParsedUri, String> {
     })
 }
 
+/// Decode `%XX` escapes in a query value, leaving malformed ones as they are
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = value.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 /// List available resource templates
 pub fn list_resources() -> Vec<ResourceInfo> {
     vec![
@@ -106,6 +138,18 @@ pub fn list_resources() -> Vec<ResourceInfo> {
             description: "Access closure information for a store path. Query params: offset, limit".to_string(),
             mime_type: "application/json".to_string(),
         },
+        ResourceInfo {
+            uri: "nix://task/{task-id}".to_string(),
+            name: "Background Task".to_string(),
+            description: "Status and live output of a background task. Supports resources/subscribe for updates as it runs. Query params: stream (stdout/stderr), offset (bytes), limit (lines)".to_string(),
+            mime_type: "application/json".to_string(),
+        },
+        ResourceInfo {
+            uri: "nix://output/{call-id}/{stream}".to_string(),
+            name: "Full Tool Output".to_string(),
+            description: "Untruncated stdout or stderr of a recent tool call, linked from `full_output` in truncated results. Query params: offset, limit, grep (regex)".to_string(),
+            mime_type: "application/json".to_string(),
+        },
     ]
 }
 
@@ -117,6 +161,8 @@ pub async fn read_resource(uri: &str) -> Result<ResourceContent, String> {
         "build-log" => read_build_log(&parsed).await,
         "derivation" => read_derivation(&parsed).await,
         "closure" => read_closure(&parsed).await,
+        "task" => read_task(&parsed).await,
+        "output" => read_output(&parsed).await,
         _ => Err(format!("Unknown resource type: {}", parsed.resource_type)),
     }
 }
@@ -153,6 +199,13 @@ mod tests {
         assert_eq!(parsed.params.get("limit"), Some(&"50".to_string()));
     }
 
+    #[test]
+    fn test_parse_nix_uri_decodes_params() {
+        let uri = "nix://output/abc/stderr?grep=error%3A%20.*%zz";
+        let parsed = parse_nix_uri(uri).unwrap();
+        assert_eq!(parsed.params.get("grep"), Some(&"error: .*%zz".to_string()));
+    }
+
     #[test]
     fn test_parse_nix_uri_invalid_scheme() {
         let uri = "http://example.com";
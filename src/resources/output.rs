@@ -0,0 +1,136 @@
+use crate::backend::Stream;
+use crate::output::PaginationInfo;
+use crate::output_cache;
+use crate::resources::{ParsedUri, ResourceContent};
+use regex::Regex;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct OutputResponse {
+    call_id: String,
+    stream: Stream,
+    /// The page of lines, prefixed with their line numbers when grepping
+    content: String,
+    total_lines: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    match_count: Option<usize>,
+    pagination: PaginationInfo,
+}
+
+/// The full output of an earlier tool call, from the output cache.
+///
+/// Pages with `offset`/`limit` (default 200 lines). With `grep`, only
+/// matching lines are paged, each prefixed with its 1-based line number.
+pub async fn read_output(parsed: &ParsedUri) -> Result<ResourceContent, String> {
+    let (call_id, stream) = parsed.path.split_once('/').ok_or_else(|| {
+        format!(
+            "Expected nix://output/{{call-id}}/{{stream}}, got: {}",
+            parsed.path
+        )
+    })?;
+    let stream = match stream {
+        "stdout" => Stream::Stdout,
+        "stderr" => Stream::Stderr,
+        other => return Err(format!("Invalid stream: {}", other)),
+    };
+
+    let cache = output_cache::cache().ok_or("The output cache is disabled")?;
+    let text = cache.read(call_id, stream).ok_or_else(|| {
+        format!(
+            "No cached output for {}/{}; it may have been evicted",
+            call_id,
+            stream.as_str()
+        )
+    })?;
+
+    let response = page_output(call_id, stream, &text, parsed)?;
+    Ok(ResourceContent {
+        uri: format!("nix://output/{}", parsed.path),
+        mime_type: "application/json".to_string(),
+        text: serde_json::to_string_pretty(&response).map_err(|e| e.to_string())?,
+    })
+}
+
+fn page_output(
+    call_id: &str,
+    stream: Stream,
+    text: &str,
+    parsed: &ParsedUri,
+) -> Result<OutputResponse, String> {
+    let offset: usize = parsed
+        .params
+        .get("offset")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    let limit: usize = parsed
+        .params
+        .get("limit")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(200);
+
+    let lines: Vec<&str> = text.lines().collect();
+    let total_lines = lines.len();
+
+    let (selected, match_count) = match parsed.params.get("grep") {
+        Some(pattern) => {
+            let re = Regex::new(pattern).map_err(|e| format!("Invalid grep pattern: {}", e))?;
+            let matches: Vec<String> = lines
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| re.is_match(line))
+                .map(|(i, line)| format!("{}: {}", i + 1, line))
+                .collect();
+            let count = matches.len();
+            (matches, Some(count))
+        }
+        None => (lines.iter().map(|line| line.to_string()).collect(), None),
+    };
+
+    let total = selected.len();
+    let page: Vec<String> = selected.into_iter().skip(offset).take(limit).collect();
+    let pagination = PaginationInfo {
+        offset,
+        limit,
+        total,
+        has_more: offset + page.len() < total,
+    };
+
+    Ok(OutputResponse {
+        call_id: call_id.to_string(),
+        stream,
+        content: page.join("\n"),
+        total_lines,
+        match_count,
+        pagination,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::parse_nix_uri;
+
+    const LOG: &str = "building\nwarning: a\ncompiling\nwarning: b\ndone\n";
+
+    #[test]
+    fn test_page_output() {
+        let parsed = parse_nix_uri("nix://output/call-1/stderr?offset=1&limit=2").unwrap();
+        let response = page_output("call-1", Stream::Stderr, LOG, &parsed).unwrap();
+        assert_eq!(response.content, "warning: a\ncompiling");
+        assert_eq!(response.total_lines, 5);
+        assert!(response.pagination.has_more);
+        assert_eq!(response.match_count, None);
+    }
+
+    #[test]
+    fn test_page_output_grep() {
+        let parsed = parse_nix_uri("nix://output/call-1/stderr?grep=%5Ewarning").unwrap();
+        let response = page_output("call-1", Stream::Stderr, LOG, &parsed).unwrap();
+        assert_eq!(response.content, "2: warning: a\n4: warning: b");
+        assert_eq!(response.match_count, Some(2));
+        assert!(!response.pagination.has_more);
+
+        let parsed = parse_nix_uri("nix://output/call-1/stderr?grep=(").unwrap();
+        assert!(page_output("call-1", Stream::Stderr, LOG, &parsed).is_err());
+    }
+}
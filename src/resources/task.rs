@@ -0,0 +1,91 @@
+use crate::backend::Stream;
+use crate::background::{get_task_info, TaskInfo};
+use crate::resources::{ParsedUri, ResourceContent};
+use crate::tools::{task_output, TaskOutputParams};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct TaskResponse {
+    task: TaskInfo,
+    stream: Stream,
+    output: String,
+    total_lines: usize,
+    next_offset: usize,
+}
+
+/// A background task's status and a page of its live output.
+///
+/// Without `offset` the last `limit` lines (default 100) are returned, so
+/// re-reading after each update notification tails the task.
+pub async fn read_task(parsed: &ParsedUri) -> Result<ResourceContent, String> {
+    let task =
+        get_task_info(&parsed.path).ok_or_else(|| format!("Task not found: {}", parsed.path))?;
+
+    let stream = match parsed.params.get("stream").map(String::as_str) {
+        None | Some("stderr") => Stream::Stderr,
+        Some("stdout") => Stream::Stdout,
+        Some(other) => return Err(format!("Invalid stream: {}", other)),
+    };
+    let offset: Option<usize> = parsed.params.get("offset").and_then(|s| s.parse().ok());
+    let limit: usize = parsed
+        .params
+        .get("limit")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(100);
+
+    let page = task_output(TaskOutputParams {
+        task_id: parsed.path.clone(),
+        stream: Some(stream),
+        offset,
+        tail: offset.is_none().then_some(limit),
+        max_lines: Some(limit),
+        ..Default::default()
+    })?;
+
+    let response = TaskResponse {
+        task,
+        stream,
+        output: page.output,
+        total_lines: page.total_lines,
+        next_offset: page.next_offset,
+    };
+
+    Ok(ResourceContent {
+        uri: format!("nix://task/{}", parsed.path),
+        mime_type: "application/json".to_string(),
+        text: serde_json::to_string_pretty(&response).map_err(|e| e.to_string())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::{append_output, generate_task_id, register_task, remove_task};
+    use crate::context::CancelToken;
+    use crate::resources::parse_nix_uri;
+
+    #[tokio::test]
+    async fn test_read_task_tails_output() {
+        let id = generate_task_id();
+        register_task(id.clone(), "build .#hello".to_string(), CancelToken::new());
+        for i in 0..5 {
+            append_output(&id, Stream::Stderr, &format!("line {}", i));
+        }
+
+        let parsed = parse_nix_uri(&format!("nix://task/{}?limit=2", id)).unwrap();
+        let content = read_task(&parsed).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&content.text).unwrap();
+        assert_eq!(response["task"]["status"], "Running");
+        assert_eq!(response["output"], "line 3\nline 4");
+        assert_eq!(response["next_offset"], 35);
+
+        let parsed = parse_nix_uri(&format!("nix://task/{}?offset=7&limit=2", id)).unwrap();
+        let content = read_task(&parsed).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&content.text).unwrap();
+        assert_eq!(response["output"], "line 1\nline 2");
+        assert_eq!(response["next_offset"], 21);
+
+        remove_task(&id);
+        assert!(read_task(&parsed).await.is_err());
+    }
+}
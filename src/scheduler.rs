@@ -0,0 +1,295 @@
+use crate::config::load_config;
+use crate::context;
+use crate::nix_runner::NixError;
+use std::collections::VecDeque;
+use std::sync::{LazyLock, Mutex};
+use tokio::sync::Notify;
+
+/// How a tool call is scheduled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobClass {
+    /// Builds and other store-heavy work, queued behind `max_heavy_jobs`
+    Heavy,
+    /// Anything else that runs nix, limited only by `max_nix_processes`
+    Normal,
+    /// Cheap queries that skip every queue
+    ReadOnly,
+}
+
+const HEAVY_TOOLS: &[&str] = &[
+    "build",
+    "flake_check",
+    "develop_run",
+    "copy",
+    "cachix_push",
+    "store_gc",
+];
+
+const READ_ONLY_TOOLS: &[&str] = &[
+    "eval",
+    "store_path_info",
+    "store_ls",
+    "store_cat",
+    "derivation_show",
+    "hash_path",
+    "hash_file",
+    "log",
+    "nix_info",
+];
+
+impl JobClass {
+    pub fn of(tool: &str) -> Self {
+        if HEAVY_TOOLS.contains(&tool) {
+            JobClass::Heavy
+        } else if READ_ONLY_TOOLS.contains(&tool) {
+            JobClass::ReadOnly
+        } else {
+            JobClass::Normal
+        }
+    }
+}
+
+/// FIFO queue in front of heavy jobs.
+///
+/// Garbage collection runs alone: it is refused while any heavy job is
+/// running or queued, and heavy jobs wait for it to finish.
+#[derive(Debug)]
+pub struct Scheduler {
+    heavy_slots: usize,
+    state: Mutex<SchedulerState>,
+    changed: Notify,
+}
+
+#[derive(Debug, Default)]
+struct SchedulerState {
+    running: usize,
+    gc_running: bool,
+    next_ticket: u64,
+    waiting: VecDeque<Ticket>,
+}
+
+#[derive(Debug)]
+struct Ticket {
+    id: u64,
+    task_id: Option<String>,
+}
+
+/// Holds a heavy job slot until dropped
+#[derive(Debug)]
+pub struct JobGuard<'a> {
+    scheduler: &'a Scheduler,
+    gc: bool,
+}
+
+impl Drop for JobGuard<'_> {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        if self.gc {
+            state.gc_running = false;
+        } else {
+            state.running -= 1;
+        }
+        drop(state);
+        self.scheduler.changed.notify_waiters();
+    }
+}
+
+/// Removes a ticket from the queue if its caller stops waiting
+struct Waiting<'a> {
+    scheduler: &'a Scheduler,
+    ticket: u64,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        state.waiting.retain(|t| t.id != self.ticket);
+        drop(state);
+        self.scheduler.changed.notify_waiters();
+    }
+}
+
+impl Scheduler {
+    pub fn new(heavy_slots: usize) -> Self {
+        Scheduler {
+            heavy_slots: heavy_slots.max(1),
+            state: Mutex::new(SchedulerState::default()),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Wait for a heavy job slot, or run `store_gc` if nothing else is
+    /// building. `task_id` lets the queue position show up in task info.
+    pub async fn admit_heavy(
+        &self,
+        tool: &str,
+        task_id: Option<String>,
+    ) -> Result<JobGuard<'_>, String> {
+        if tool == "store_gc" {
+            return self.admit_gc();
+        }
+
+        let ticket = {
+            let mut state = self.state.lock().unwrap();
+            let id = state.next_ticket;
+            state.next_ticket += 1;
+            state.waiting.push_back(Ticket { id, task_id });
+            id
+        };
+        let waiting = Waiting {
+            scheduler: self,
+            ticket,
+        };
+
+        let mut reported = None;
+        loop {
+            let changed = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                let position = state.waiting.iter().position(|t| t.id == ticket);
+                if position == Some(0) && state.running < self.heavy_slots && !state.gc_running {
+                    state.waiting.pop_front();
+                    state.running += 1;
+                    break;
+                }
+                if position != reported {
+                    reported = position;
+                    let ahead = position.unwrap_or_default();
+                    // No progress of its own; report_progress still bumps it
+                    context::report_progress(
+                        0,
+                        &format!(
+                            "queued behind {} running and {} waiting jobs",
+                            state.running, ahead
+                        ),
+                    );
+                }
+            }
+            tokio::select! {
+                _ = changed => {}
+                _ = context::cancelled() => return Err(NixError::Cancelled.to_string()),
+            }
+        }
+
+        // Off the queue now, which moves everyone behind us up
+        drop(waiting);
+        Ok(JobGuard {
+            scheduler: self,
+            gc: false,
+        })
+    }
+
+    fn admit_gc(&self) -> Result<JobGuard<'_>, String> {
+        let mut state = self.state.lock().unwrap();
+        let busy = state.running + state.waiting.len();
+        if busy > 0 || state.gc_running {
+            return Err(format!(
+                "store_gc refused: {} build job(s) are running or queued, and collecting garbage now could delete paths they need. Retry once they finish.",
+                busy.max(1)
+            ));
+        }
+        state.gc_running = true;
+        Ok(JobGuard {
+            scheduler: self,
+            gc: true,
+        })
+    }
+
+    /// 1-based position of a background task waiting for a heavy slot
+    pub fn queue_position(&self, task_id: &str) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        state
+            .waiting
+            .iter()
+            .position(|t| t.task_id.as_deref() == Some(task_id))
+            .map(|index| index + 1)
+    }
+}
+
+static SCHEDULER: LazyLock<Scheduler> =
+    LazyLock::new(|| Scheduler::new(load_config().concurrency.max_heavy_jobs()));
+
+/// Admit a tool call, queueing it first if it's a heavy job.
+///
+/// Other calls are admitted straight away and hold no slot.
+pub async fn admit(tool: &str) -> Result<Option<JobGuard<'static>>, String> {
+    if JobClass::of(tool) != JobClass::Heavy {
+        return Ok(None);
+    }
+    let task_id = context::current().and_then(|ctx| ctx.task_id);
+    SCHEDULER.admit_heavy(tool, task_id).await.map(Some)
+}
+
+/// Whether the current request's nix processes skip the process limit
+pub fn bypasses_queue() -> bool {
+    context::current().and_then(|ctx| ctx.job_class) == Some(JobClass::ReadOnly)
+}
+
+/// Where a background task is in the heavy job queue, if it is waiting
+pub fn queue_position(task_id: &str) -> Option<usize> {
+    SCHEDULER.queue_position(task_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_class() {
+        assert_eq!(JobClass::of("build"), JobClass::Heavy);
+        assert_eq!(JobClass::of("store_gc"), JobClass::Heavy);
+        assert_eq!(JobClass::of("store_path_info"), JobClass::ReadOnly);
+        assert_eq!(JobClass::of("eval"), JobClass::ReadOnly);
+        assert_eq!(JobClass::of("flake_update"), JobClass::Normal);
+    }
+
+    #[tokio::test]
+    async fn test_heavy_jobs_queue_in_order() {
+        let scheduler = Scheduler::new(1);
+        let first = scheduler.admit_heavy("build", None).await.unwrap();
+
+        let second = scheduler.admit_heavy("build", Some("task-2".to_string()));
+        let third = scheduler.admit_heavy("copy", Some("task-3".to_string()));
+        tokio::pin!(second, third);
+
+        // Poll both once so they join the queue
+        assert!(futures_poll(&mut second).await.is_none());
+        assert!(futures_poll(&mut third).await.is_none());
+        assert_eq!(scheduler.queue_position("task-2"), Some(1));
+        assert_eq!(scheduler.queue_position("task-3"), Some(2));
+
+        drop(first);
+        let second = second.await.unwrap();
+        assert_eq!(scheduler.queue_position("task-3"), Some(1));
+        drop(second);
+        third.await.unwrap();
+        assert_eq!(scheduler.queue_position("task-3"), None);
+    }
+
+    #[tokio::test]
+    async fn test_gc_refused_while_building() {
+        let scheduler = Scheduler::new(2);
+        let build = scheduler.admit_heavy("build", None).await.unwrap();
+        let err = scheduler.admit_heavy("store_gc", None).await.unwrap_err();
+        assert!(err.contains("store_gc refused"));
+
+        drop(build);
+        let gc = scheduler.admit_heavy("store_gc", None).await.unwrap();
+
+        // Builds wait for the collection to finish
+        let build = scheduler.admit_heavy("build", None);
+        tokio::pin!(build);
+        assert!(futures_poll(&mut build).await.is_none());
+        drop(gc);
+        build.await.unwrap();
+    }
+
+    /// Poll a future once, returning its output if it was ready
+    async fn futures_poll<F: std::future::Future + Unpin>(fut: &mut F) -> Option<F::Output> {
+        tokio::select! {
+            biased;
+            out = fut => Some(out),
+            _ = tokio::task::yield_now() => None,
+        }
+    }
+}
@@ -1,17 +1,53 @@
-use crate::background::{get_task_info, list_tasks};
-use crate::resources::{self, ResourceReadParams};
+use crate::audit;
+use crate::backend::CommandBackend;
+use crate::background::{
+    cancel_task, finish_task, generate_task_id, get_task_info, list_tasks, register_task,
+    task_result, watch_task, TaskStarted, TaskStatus,
+};
+use crate::budget;
+use crate::config::load_config;
+use crate::context::{self, CancelToken, Notifier, RequestContext};
+use crate::output;
+use crate::output_cache;
+use crate::resources::{self, ResourceReadParams, ResourceSubscribeParams};
+use crate::scheduler::{self, JobClass};
 use crate::tools::{
-    self, CachixPushParams, CachixStatusParams, CachixUseParams, FhAddParams, FhFetchParams,
-    FhListFlakesParams, FhListReleasesParams, FhListVersionsParams, FhLoginParams, FhResolveParams,
-    FhSearchParams, NilCompletionsParams, NilDefinitionParams, NilDiagnosticsParams,
-    NilHoverParams, NixBuildParams, NixCopyParams, NixDerivationShowParams, NixDevelopRunParams,
-    NixEvalParams, NixFlakeCheckParams, NixFlakeInitParams, NixFlakeLockParams,
-    NixFlakeMetadataParams, NixFlakeShowParams, NixFlakeUpdateParams, NixHashFileParams,
-    NixHashPathParams, NixLogParams, NixRunParams, NixSearchParams, NixStoreCatParams,
-    NixStoreGcParams, NixStoreLsParams, NixStorePathInfoParams, TaskStatusParams,
+    self, AuditQueryParams, CachixPushParams, CachixStatusParams, CachixUseParams, FhAddParams,
+    FhFetchParams, FhListFlakesParams, FhListReleasesParams, FhListVersionsParams, FhLoginParams,
+    FhResolveParams, FhSearchParams, NilCompletionsParams, NilDefinitionParams,
+    NilDiagnosticsParams, NilHoverParams, NixBuildParams, NixCopyParams, NixDerivationShowParams,
+    NixDevelopRunParams, NixEvalParams, NixFlakeCheckParams, NixFlakeInitParams,
+    NixFlakeLockParams, NixFlakeMetadataParams, NixFlakeShowParams, NixFlakeUpdateParams,
+    NixHashFileParams, NixHashPathParams, NixInfo, NixLogParams, NixRunParams, NixSearchParams,
+    NixStoreCatParams, NixStoreGcParams, NixStoreLsParams, NixStorePathInfoParams, TaskIdParams,
+    TaskOutputParams, TaskStatusParams, ToolAnnotations,
 };
+use crate::workspace;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+/// JSON-RPC error code for requests cancelled by the client
+const REQUEST_CANCELLED: i32 = -32800;
+
+/// Tools that accept `background: true`
+const BACKGROUND_TOOLS: &[&str] = &["build", "flake_check", "develop_run", "copy", "cachix_push"];
+
+/// MCP protocol versions the server speaks, newest first
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// First protocol version with `structuredContent` and `outputSchema`
+const STRUCTURED_CONTENT_VERSION: &str = "2025-06-18";
+
+/// Minimum gap between update notifications for one subscribed resource
+const SUBSCRIPTION_INTERVAL: Duration = Duration::from_millis(500);
 
 #[derive(Debug, Deserialize)]
 struct JsonRpcRequest {
@@ -21,6 +57,17 @@ struct JsonRpcRequest {
     params: Option<Value>,
 }
 
+/// A response from the client to a request the server sent it
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonRpcClientResponse {
+    #[serde(rename = "jsonrpc")]
+    _jsonrpc: String,
+    id: Value,
+    result: Option<Value>,
+    error: Option<Value>,
+}
+
 #[derive(Debug, Serialize)]
 struct JsonRpcResponse {
     jsonrpc: String,
@@ -83,13 +130,18 @@ struct ToolsListResult {
 struct ToolDefinition {
     name: String,
     description: String,
+    annotations: ToolAnnotations,
     #[serde(rename = "inputSchema")]
     input_schema: Value,
+    #[serde(rename = "outputSchema", skip_serializing_if = "Option::is_none")]
+    output_schema: Option<Value>,
 }
 
 #[derive(Debug, Serialize)]
 struct ToolCallResult {
     content: Vec<ContentItem>,
+    #[serde(rename = "structuredContent", skip_serializing_if = "Option::is_none")]
+    structured_content: Option<Value>,
     #[serde(rename = "isError", skip_serializing_if = "Option::is_none")]
     is_error: Option<bool>,
 }
@@ -128,18 +180,69 @@ struct ContentItem {
     text: String,
 }
 
-pub struct Server {}
+pub struct Server {
+    notifier: Notifier,
+    backend: Arc<dyn CommandBackend>,
+    /// Cancel tokens for tool calls that are still running, keyed by request id
+    in_flight: Mutex<HashMap<String, CancelToken>>,
+    /// Detected nix implementation, probed once at startup
+    nix_info: OnceCell<NixInfo>,
+    /// Tasks forwarding update notifications for subscribed resources, keyed by URI
+    subscriptions: Mutex<HashMap<String, AbortHandle>>,
+    /// Whether the client declared the `roots` capability in `initialize`
+    client_roots: AtomicBool,
+    /// Id of the `roots/list` request awaiting the client's response
+    pending_roots: Mutex<Option<String>>,
+    /// Protocol version agreed in `initialize`
+    protocol_version: Mutex<&'static str>,
+}
 
 impl Server {
-    pub fn new() -> Self {
-        Server {}
+    /// Create a server whose tools run external commands through `backend`
+    pub fn new(notifier: Notifier, backend: Arc<dyn CommandBackend>) -> Self {
+        Server {
+            notifier,
+            backend,
+            in_flight: Mutex::new(HashMap::new()),
+            nix_info: OnceCell::new(),
+            subscriptions: Mutex::new(HashMap::new()),
+            client_roots: AtomicBool::new(false),
+            pending_roots: Mutex::new(None),
+            protocol_version: Mutex::new(PROTOCOL_VERSIONS[PROTOCOL_VERSIONS.len() - 1]),
+        }
+    }
+
+    /// Detect the nix implementation, caching the result.
+    ///
+    /// Failures aren't cached so a later call can succeed once nix is
+    /// available.
+    pub async fn nix_info(&self) -> Result<&NixInfo, String> {
+        self.nix_info
+            .get_or_try_init(|| context::scope(self.request_context(), tools::nix_info()))
+            .await
     }
 
-    pub async fn handle_request(&self, request: &str) -> Value {
+    fn request_context(&self) -> RequestContext {
+        RequestContext {
+            notifier: Some(self.notifier.clone()),
+            backend: Some(Arc::clone(&self.backend)),
+            ..Default::default()
+        }
+    }
+
+    /// Handle a single JSON-RPC message.
+    ///
+    /// Returns `None` for notifications, which never get a response.
+    pub async fn handle_request(self: &Arc<Self>, request: &str) -> Option<Value> {
+        if let Ok(response) = serde_json::from_str::<JsonRpcClientResponse>(request) {
+            self.handle_client_response(response);
+            return None;
+        }
+
         let parsed: Result<JsonRpcRequest, _> = serde_json::from_str(request);
 
         let response = match parsed {
-            Ok(req) => self.dispatch(req).await,
+            Ok(req) => self.dispatch(req).await?,
             Err(e) => JsonRpcResponse {
                 jsonrpc: "2.0".to_string(),
                 id: Value::Null,
@@ -152,19 +255,32 @@ impl Server {
             },
         };
 
-        serde_json::to_value(response).unwrap_or(Value::Null)
+        serde_json::to_value(response).ok()
     }
 
-    async fn dispatch(&self, req: JsonRpcRequest) -> JsonRpcResponse {
+    async fn dispatch(self: &Arc<Self>, req: JsonRpcRequest) -> Option<JsonRpcResponse> {
         let id = req.id.clone().unwrap_or(Value::Null);
 
         let result = match req.method.as_str() {
-            "initialize" => self.handle_initialize().await,
-            "notifications/initialized" => return self.empty_response(id),
+            "initialize" => self.handle_initialize(req.params).await,
+            "notifications/initialized" | "notifications/roots/list_changed" => {
+                self.request_roots();
+                return None;
+            }
+            "notifications/cancelled" => {
+                self.handle_cancelled(req.params);
+                return None;
+            }
             "tools/list" => self.handle_tools_list().await,
-            "tools/call" => self.handle_tool_call(req.params).await,
+            "tools/call" => {
+                let result = self.handle_tool_call(&id, req.params).await;
+                self.in_flight.lock().unwrap().remove(&id.to_string());
+                result
+            }
             "resources/list" => self.handle_resources_list().await,
             "resources/read" => self.handle_resources_read(req.params).await,
+            "resources/subscribe" => self.handle_resources_subscribe(req.params),
+            "resources/unsubscribe" => self.handle_resources_unsubscribe(req.params),
             _ => Err(JsonRpcError {
                 code: -32601,
                 message: format!("Method not found: {}", req.method),
@@ -172,7 +288,7 @@ impl Server {
             }),
         };
 
-        match result {
+        let response = match result {
             Ok(value) => JsonRpcResponse {
                 jsonrpc: "2.0".to_string(),
                 id,
@@ -185,27 +301,101 @@ impl Server {
                 result: None,
                 error: Some(e),
             },
+        };
+        Some(response)
+    }
+
+    /// Register a `tools/call` request as in flight before it's dispatched.
+    ///
+    /// The read loop calls this before spawning the request, so a
+    /// `notifications/cancelled` read after it always finds the call.
+    pub fn track_call(&self, message: &Value) {
+        if message.get("method").and_then(Value::as_str) != Some("tools/call") {
+            return;
+        }
+        if let Some(id) = message.get("id") {
+            self.in_flight
+                .lock()
+                .unwrap()
+                .entry(id.to_string())
+                .or_default();
+        }
+    }
+
+    /// Handle `notifications/cancelled` by cancelling the matching tool call.
+    ///
+    /// Unknown or already finished request ids are ignored, as the spec allows.
+    fn handle_cancelled(&self, params: Option<Value>) {
+        let Some(request_id) = params.as_ref().and_then(|p| p.get("requestId")) else {
+            return;
+        };
+
+        let in_flight = self.in_flight.lock().unwrap();
+        if let Some(token) = in_flight.get(&request_id.to_string()) {
+            token.cancel();
         }
     }
 
-    fn empty_response(&self, id: Value) -> JsonRpcResponse {
-        JsonRpcResponse {
-            jsonrpc: "2.0".to_string(),
-            id,
-            result: Some(Value::Object(serde_json::Map::new())),
-            error: None,
+    /// Ask the client for its roots, if it has any and config uses them.
+    ///
+    /// The answer arrives as a separate message, handled by
+    /// `handle_client_response`.
+    fn request_roots(&self) {
+        if !self.client_roots.load(Ordering::Relaxed) || !load_config().workspace.use_client_roots {
+            return;
         }
+        let id = format!("roots-{}", Uuid::new_v4());
+        *self.pending_roots.lock().unwrap() = Some(id.clone());
+        self.notifier.send(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "roots/list",
+        }));
     }
 
-    async fn handle_initialize(&self) -> Result<Value, JsonRpcError> {
+    /// Handle the client's response to a request the server sent.
+    ///
+    /// Only `roots/list` is ever sent; responses to an older `roots/list`
+    /// than the latest are dropped.
+    fn handle_client_response(&self, response: JsonRpcClientResponse) {
+        let mut pending = self.pending_roots.lock().unwrap();
+        if response.id.as_str() != pending.as_deref() {
+            return;
+        }
+        *pending = None;
+        match (response.result, response.error) {
+            (Some(result), _) => workspace::set_client_roots(&result),
+            (None, error) => eprintln!("Warning: roots/list failed: {:?}", error),
+        }
+    }
+
+    async fn handle_initialize(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let client_roots = params
+            .as_ref()
+            .and_then(|p| p.pointer("/capabilities/roots"))
+            .is_some();
+        self.client_roots.store(client_roots, Ordering::Relaxed);
+
+        // Use the client's version if we speak it, otherwise offer our newest
+        // and let the client decide whether to carry on
+        let requested = params
+            .as_ref()
+            .and_then(|p| p.get("protocolVersion"))
+            .and_then(Value::as_str);
+        let version = PROTOCOL_VERSIONS
+            .iter()
+            .find(|v| Some(**v) == requested)
+            .unwrap_or(&PROTOCOL_VERSIONS[0]);
+        *self.protocol_version.lock().unwrap() = version;
+
         let result = InitializeResult {
-            protocol_version: "2024-11-05".to_string(),
+            protocol_version: version.to_string(),
             capabilities: Capabilities {
                 tools: ToolsCapability {
                     list_changed: false,
                 },
                 resources: ResourcesCapability {
-                    subscribe: false,
+                    subscribe: true,
                     list_changed: false,
                 },
             },
@@ -221,14 +411,24 @@ impl Server {
         })
     }
 
+    /// Whether the client understands `structuredContent` and `outputSchema`
+    fn structured_content(&self) -> bool {
+        *self.protocol_version.lock().unwrap() >= STRUCTURED_CONTENT_VERSION
+    }
+
     async fn handle_tools_list(&self) -> Result<Value, JsonRpcError> {
+        let structured = self.structured_content();
         let tool_infos = tools::list_tools();
         let tools: Vec<ToolDefinition> = tool_infos
             .into_iter()
             .map(|t| ToolDefinition {
                 name: t.name.to_string(),
                 description: t.description.to_string(),
+                annotations: t.annotations,
                 input_schema: t.input_schema,
+                output_schema: structured
+                    .then(|| tools::output_schema(t.name, BACKGROUND_TOOLS.contains(&t.name)))
+                    .flatten(),
             })
             .collect();
 
@@ -240,7 +440,11 @@ impl Server {
         })
     }
 
-    async fn handle_tool_call(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+    async fn handle_tool_call(
+        self: &Arc<Self>,
+        id: &Value,
+        params: Option<Value>,
+    ) -> Result<Value, JsonRpcError> {
         let params = params.ok_or_else(|| JsonRpcError {
             code: -32602,
             message: "Missing params".to_string(),
@@ -261,211 +465,293 @@ impl Server {
             .cloned()
             .unwrap_or(Value::Object(serde_json::Map::new()));
 
-        let result = self.call_tool(name, arguments).await;
+        let progress_token = params
+            .get("_meta")
+            .and_then(|m| m.get("progressToken"))
+            .cloned();
 
-        match result {
-            Ok(value) => {
-                let tool_result = ToolCallResult {
-                    content: vec![ContentItem {
-                        content_type: "text".to_string(),
-                        text: serde_json::to_string_pretty(&value).unwrap_or_default(),
-                    }],
-                    is_error: None,
-                };
-                serde_json::to_value(tool_result).map_err(|e| JsonRpcError {
-                    code: -32603,
-                    message: e.to_string(),
-                    data: None,
-                })
-            }
-            Err(e) => {
-                let tool_result = ToolCallResult {
-                    content: vec![ContentItem {
-                        content_type: "text".to_string(),
-                        text: e,
-                    }],
-                    is_error: Some(true),
-                };
-                serde_json::to_value(tool_result).map_err(|e| JsonRpcError {
-                    code: -32603,
-                    message: e.to_string(),
-                    data: None,
-                })
+        let structured = self.structured_content();
+
+        // Per-call timeout_secs wins over the [timeouts] config
+        let timeout_secs = arguments
+            .get("timeout_secs")
+            .and_then(|v| v.as_u64())
+            .unwrap_or_else(|| load_config().timeouts.for_tool(name));
+
+        if arguments.get("background").and_then(|v| v.as_bool()) == Some(true) {
+            if !BACKGROUND_TOOLS.contains(&name) {
+                return tool_call_result(
+                    Err(format!(
+                        "{} can't run in the background; supported tools: {}",
+                        name,
+                        BACKGROUND_TOOLS.join(", ")
+                    )),
+                    structured,
+                );
+            }
+            // A denied call fails now instead of leaving a failed task behind
+            if let Err(e) = load_config().policy.check(name, &arguments) {
+                let denied = Err(e);
+                audit::record_call(name, &arguments, &denied, Duration::ZERO);
+                return tool_call_result(denied, structured);
             }
+            let started = self.start_background_task(name, arguments, timeout_secs);
+            return tool_call_result(Ok(started), structured);
+        }
+
+        // Usually already registered by `track_call` on the read loop
+        let cancel = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .clone();
+
+        let ctx = RequestContext {
+            progress_token,
+            cancel: Some(cancel.clone()),
+            timeout_secs: Some(timeout_secs),
+            job_class: Some(JobClass::of(name)),
+            output: output_cache::new_capture(Uuid::new_v4().to_string()),
+            commands: audit::new_command_log(),
+            ..self.request_context()
+        };
+
+        let result = context::scope(ctx, self.run_tool(name, arguments)).await;
+
+        if cancel.is_cancelled() {
+            return Err(JsonRpcError {
+                code: REQUEST_CANCELLED,
+                message: "Request cancelled".to_string(),
+                data: None,
+            });
+        }
+
+        tool_call_result(result, structured)
+    }
+
+    /// Run a tool call as a background task, returning its id right away.
+    ///
+    /// Output streams into the task as the command runs and the final result
+    /// is kept in the task registry.
+    fn start_background_task(
+        self: &Arc<Self>,
+        name: &str,
+        mut arguments: Value,
+        timeout_secs: u64,
+    ) -> Value {
+        if let Some(args) = arguments.as_object_mut() {
+            args.remove("background");
         }
+
+        let task_id = generate_task_id();
+        let command = format!("{} {}", name, arguments);
+        let cancel = CancelToken::new();
+        register_task(task_id.clone(), command.clone(), cancel.clone());
+
+        let ctx = RequestContext {
+            cancel: Some(cancel),
+            timeout_secs: Some(timeout_secs),
+            task_id: Some(task_id.clone()),
+            job_class: Some(JobClass::of(name)),
+            output: output_cache::new_capture(task_id.clone()),
+            commands: audit::new_command_log(),
+            ..self.request_context()
+        };
+
+        let server = Arc::clone(self);
+        let name = name.to_string();
+        let id = task_id.clone();
+        tokio::spawn(async move {
+            let result = context::scope(ctx, server.run_tool(&name, arguments)).await;
+            finish_task(&id, result);
+        });
+
+        serde_json::json!(TaskStarted {
+            resource_uri: format!("nix://task/{}", task_id),
+            task_id,
+            status: TaskStatus::Running,
+            command,
+        })
+    }
+
+    /// Run a tool and record the call in the audit log
+    async fn run_tool(&self, name: &str, arguments: Value) -> Result<Value, String> {
+        let started = Instant::now();
+        let client_arguments = arguments.clone();
+        let result = self.admit_and_call(name, arguments).await;
+        audit::record_call(name, &client_arguments, &result, started.elapsed());
+        result
+    }
+
+    /// Run a tool once the scheduler admits it
+    async fn admit_and_call(&self, name: &str, arguments: Value) -> Result<Value, String> {
+        output::validate_grep(&arguments)?;
+        let _job = scheduler::admit(name).await?;
+        let result = self.call_tool(name, arguments).await.map(|mut value| {
+            let budget = load_config().output_limits.response_token_budget();
+            if let Some(report) = budget.and_then(|b| budget::enforce_budget(&mut value, b)) {
+                if let Some(fields) = value.as_object_mut() {
+                    fields.insert("response_budget".to_string(), serde_json::json!(report));
+                }
+            }
+            value
+        });
+        output_cache::finish_call(result).await
     }
 
     async fn call_tool(&self, name: &str, arguments: Value) -> Result<Value, String> {
+        load_config().policy.check(name, &arguments)?;
+
         match name {
             "build" => {
-                let params: NixBuildParams = serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixBuildParams = parse_params_or_default(arguments);
                 let result = tools::nix_build(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "flake_show" => {
-                let params: NixFlakeShowParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixFlakeShowParams = parse_params_or_default(arguments);
                 let result = tools::nix_flake_show(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "flake_check" => {
-                let params: NixFlakeCheckParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixFlakeCheckParams = parse_params_or_default(arguments);
                 let result = tools::nix_flake_check(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "flake_metadata" => {
-                let params: NixFlakeMetadataParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixFlakeMetadataParams = parse_params_or_default(arguments);
                 let result = tools::nix_flake_metadata(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "flake_update" => {
-                let params: NixFlakeUpdateParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixFlakeUpdateParams = parse_params_or_default(arguments);
                 let result = tools::nix_flake_update(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "flake_lock" => {
-                let params: NixFlakeLockParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixFlakeLockParams = parse_params_or_default(arguments);
                 let result = tools::nix_flake_lock(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "flake_init" => {
-                let params: NixFlakeInitParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixFlakeInitParams = parse_params_or_default(arguments);
                 let result = tools::nix_flake_init(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "run" => {
-                let params: NixRunParams = serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixRunParams = parse_params_or_default(arguments);
                 let result = tools::nix_run(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "develop_run" => {
-                let params: NixDevelopRunParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NixDevelopRunParams = parse_params(arguments)?;
                 let result = tools::nix_develop_run(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "log" => {
-                let params: NixLogParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NixLogParams = parse_params(arguments)?;
                 let result = tools::nix_log(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "eval" => {
-                let params: NixEvalParams = serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixEvalParams = parse_params_or_default(arguments);
                 let result = tools::nix_eval(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "search" => {
-                let params: NixSearchParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NixSearchParams = parse_params(arguments)?;
                 let result = tools::nix_search(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "store_path_info" => {
-                let params: NixStorePathInfoParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NixStorePathInfoParams = parse_params(arguments)?;
                 let result = tools::nix_store_path_info(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "store_gc" => {
-                let params: NixStoreGcParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixStoreGcParams = parse_params_or_default(arguments);
                 let result = tools::nix_store_gc(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "store_ls" => {
-                let params: NixStoreLsParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NixStoreLsParams = parse_params(arguments)?;
                 let result = tools::nix_store_ls(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "store_cat" => {
-                let params: NixStoreCatParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NixStoreCatParams = parse_params(arguments)?;
                 let result = tools::nix_store_cat(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "derivation_show" => {
-                let params: NixDerivationShowParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: NixDerivationShowParams = parse_params_or_default(arguments);
                 let result = tools::nix_derivation_show(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
+            "nix_info" => {
+                let info = self.nix_info().await?;
+                serde_json::to_value(info).map_err(|e| e.to_string())
+            }
             "hash_path" => {
-                let params: NixHashPathParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NixHashPathParams = parse_params(arguments)?;
                 let result = tools::nix_hash_path(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "hash_file" => {
-                let params: NixHashFileParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NixHashFileParams = parse_params(arguments)?;
                 let result = tools::nix_hash_file(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "copy" => {
-                let params: NixCopyParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NixCopyParams = parse_params(arguments)?;
                 let result = tools::nix_copy(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "fh_search" => {
-                let params: FhSearchParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: FhSearchParams = parse_params(arguments)?;
                 let result = tools::fh_search(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "fh_add" => {
-                let params: FhAddParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: FhAddParams = parse_params(arguments)?;
                 let result = tools::fh_add(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "fh_list_flakes" => {
-                let params: FhListFlakesParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: FhListFlakesParams = parse_params_or_default(arguments);
                 let result = tools::fh_list_flakes(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "fh_list_releases" => {
-                let params: FhListReleasesParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: FhListReleasesParams = parse_params(arguments)?;
                 let result = tools::fh_list_releases(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "fh_list_versions" => {
-                let params: FhListVersionsParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: FhListVersionsParams = parse_params(arguments)?;
                 let result = tools::fh_list_versions(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "fh_resolve" => {
-                let params: FhResolveParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: FhResolveParams = parse_params(arguments)?;
                 let result = tools::fh_resolve(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             // Cachix tools
             "cachix_push" => {
-                let params: CachixPushParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: CachixPushParams = parse_params(arguments)?;
                 let result = tools::cachix_push(params.cache_name, params.store_paths).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "cachix_use" => {
-                let params: CachixUseParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: CachixUseParams = parse_params(arguments)?;
                 let result = tools::cachix_use(params.cache_name).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "cachix_status" => {
-                let _params: CachixStatusParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let _params: CachixStatusParams = parse_params_or_default(arguments);
                 let result = tools::cachix_status().await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
@@ -475,21 +761,18 @@ impl Server {
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "fh_fetch" => {
-                let params: FhFetchParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: FhFetchParams = parse_params(arguments)?;
                 let result = tools::fh_fetch(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "fh_login" => {
-                let params: FhLoginParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: FhLoginParams = parse_params_or_default(arguments);
                 let result = tools::fh_login(params).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             // Background task tools
             "task_status" => {
-                let params: TaskStatusParams =
-                    serde_json::from_value(arguments).unwrap_or_default();
+                let params: TaskStatusParams = parse_params_or_default(arguments);
                 let result = match params.task_id {
                     Some(id) => {
                         if let Some(info) = get_task_info(&id) {
@@ -505,17 +788,34 @@ impl Server {
                 };
                 Ok(result)
             }
+            "task_output" => {
+                let params: TaskOutputParams = parse_params(arguments)?;
+                let result = tools::task_output(params)?;
+                serde_json::to_value(result).map_err(|e| e.to_string())
+            }
+            "task_cancel" => {
+                let params: TaskIdParams = parse_params(arguments)?;
+                let info = cancel_task(&params.task_id)?;
+                Ok(serde_json::json!({ "task": info }))
+            }
+            "task_result" => {
+                let params: TaskIdParams = parse_params(arguments)?;
+                task_result(&params.task_id)
+            }
+            "audit_query" => {
+                let params: AuditQueryParams = parse_params_or_default(arguments);
+                let result = tools::audit_query(params)?;
+                serde_json::to_value(result).map_err(|e| e.to_string())
+            }
             // nil LSP tools
             "nil_diagnostics" => {
-                let params: NilDiagnosticsParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NilDiagnosticsParams = parse_params(arguments)?;
                 let result =
                     tools::nil_diagnostics(params.file_path, params.offset, params.limit).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "nil_completions" => {
-                let params: NilCompletionsParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NilCompletionsParams = parse_params(arguments)?;
                 let result = tools::nil_completions(
                     params.file_path,
                     params.line,
@@ -527,15 +827,13 @@ impl Server {
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "nil_hover" => {
-                let params: NilHoverParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NilHoverParams = parse_params(arguments)?;
                 let result =
                     tools::nil_hover(params.file_path, params.line, params.character).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
             }
             "nil_definition" => {
-                let params: NilDefinitionParams =
-                    serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+                let params: NilDefinitionParams = parse_params(arguments)?;
                 let result =
                     tools::nil_definition(params.file_path, params.line, params.character).await?;
                 serde_json::to_value(result).map_err(|e| e.to_string())
@@ -578,13 +876,16 @@ impl Server {
                 data: None,
             })?;
 
-        let content = resources::read_resource(&read_params.uri)
-            .await
-            .map_err(|e| JsonRpcError {
-                code: -32603,
-                message: e,
-                data: None,
-            })?;
+        let content = context::scope(
+            self.request_context(),
+            resources::read_resource(&read_params.uri),
+        )
+        .await
+        .map_err(|e| JsonRpcError {
+            code: -32603,
+            message: e,
+            data: None,
+        })?;
 
         let result = ResourceReadResult {
             contents: vec![ResourceContentItem {
@@ -600,4 +901,409 @@ impl Server {
             data: None,
         })
     }
+
+    /// Handle `resources/subscribe`, sending `notifications/resources/updated`
+    /// as the resource changes.
+    ///
+    /// Only `nix://task/{id}` resources change over time; notifications stop
+    /// and the subscription is dropped once the task finishes.
+    fn handle_resources_subscribe(
+        self: &Arc<Self>,
+        params: Option<Value>,
+    ) -> Result<Value, JsonRpcError> {
+        let uri = subscription_uri(params)?;
+        let parsed = resources::parse_nix_uri(&uri).map_err(|e| JsonRpcError {
+            code: -32602,
+            message: e,
+            data: None,
+        })?;
+        if parsed.resource_type != "task" {
+            return Err(JsonRpcError {
+                code: -32602,
+                message: format!(
+                    "Subscriptions are only supported for nix://task/{{id}} resources: {}",
+                    uri
+                ),
+                data: None,
+            });
+        }
+        let mut updates = watch_task(&parsed.path).ok_or_else(|| JsonRpcError {
+            code: -32602,
+            message: format!("Task not found: {}", parsed.path),
+            data: None,
+        })?;
+
+        let notifier = self.notifier.clone();
+        let server = Arc::downgrade(self);
+        let task_id = parsed.path;
+        let forward_uri = uri.clone();
+        // Held until the forwarder is registered, so one that finishes
+        // straight away still finds its own entry to remove
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let forwarder = tokio::spawn(async move {
+            while updates.changed().await.is_ok() {
+                notifier.notify(
+                    "notifications/resources/updated",
+                    serde_json::json!({ "uri": forward_uri }),
+                );
+                let finished =
+                    get_task_info(&task_id).is_none_or(|info| info.status != TaskStatus::Running);
+                if finished {
+                    break;
+                }
+                // Coalesce bursts of log lines into one notification
+                tokio::time::sleep(SUBSCRIPTION_INTERVAL).await;
+            }
+
+            // Unless a newer subscription to the same URI replaced this one
+            if let Some(server) = server.upgrade() {
+                let mut subscriptions = server.subscriptions.lock().unwrap();
+                let current = subscriptions.get(&forward_uri).map(AbortHandle::id);
+                if current == Some(tokio::task::id()) {
+                    subscriptions.remove(&forward_uri);
+                }
+            }
+        });
+
+        if let Some(previous) = subscriptions.insert(uri, forwarder.abort_handle()) {
+            previous.abort();
+        }
+        Ok(serde_json::json!({}))
+    }
+
+    fn handle_resources_unsubscribe(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let uri = subscription_uri(params)?;
+        if let Some(forwarder) = self.subscriptions.lock().unwrap().remove(&uri) {
+            forwarder.abort();
+        }
+        Ok(serde_json::json!({}))
+    }
+}
+
+/// Parse a tool's arguments and note them for the audit log
+fn parse_params<T: DeserializeOwned + Serialize>(arguments: Value) -> Result<T, String> {
+    let params: T = serde_json::from_value(arguments).map_err(|e| e.to_string())?;
+    audit::record_arguments(&params);
+    Ok(params)
+}
+
+/// [`parse_params`] for tools that fall back to their defaults on arguments
+/// that don't parse; the audit log then shows the defaults that ran
+fn parse_params_or_default<T: DeserializeOwned + Serialize + Default>(arguments: Value) -> T {
+    let params: T = serde_json::from_value(arguments).unwrap_or_default();
+    audit::record_arguments(&params);
+    params
+}
+
+fn subscription_uri(params: Option<Value>) -> Result<String, JsonRpcError> {
+    let params = params.ok_or_else(|| JsonRpcError {
+        code: -32602,
+        message: "Missing params".to_string(),
+        data: None,
+    })?;
+    let params: ResourceSubscribeParams =
+        serde_json::from_value(params).map_err(|e| JsonRpcError {
+            code: -32602,
+            message: format!("Invalid params: {}", e),
+            data: None,
+        })?;
+    Ok(params.uri)
+}
+
+/// Wrap a tool's result for the client.
+///
+/// Older clients read the result from the JSON text in `content`; with
+/// `structured` it is also sent as `structuredContent`.
+fn tool_call_result(
+    result: Result<Value, String>,
+    structured: bool,
+) -> Result<Value, JsonRpcError> {
+    let tool_result = match result {
+        Ok(value) => ToolCallResult {
+            content: vec![ContentItem {
+                content_type: "text".to_string(),
+                text: serde_json::to_string_pretty(&value).unwrap_or_default(),
+            }],
+            structured_content: (structured && value.is_object()).then_some(value),
+            is_error: None,
+        },
+        Err(e) => ToolCallResult {
+            content: vec![ContentItem {
+                content_type: "text".to_string(),
+                text: e,
+            }],
+            structured_content: None,
+            is_error: Some(true),
+        },
+    };
+
+    serde_json::to_value(tool_result).map_err(|e| JsonRpcError {
+        code: -32603,
+        message: e.to_string(),
+        data: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::ProcessBackend;
+
+    fn test_server() -> (Arc<Server>, tokio::sync::mpsc::UnboundedReceiver<Value>) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let server = Server::new(Notifier::new(tx), Arc::new(ProcessBackend));
+        (Arc::new(server), rx)
+    }
+
+    #[tokio::test]
+    async fn test_notifications_get_no_response() {
+        let (server, _rx) = test_server();
+
+        let initialized = r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#;
+        assert!(server.handle_request(initialized).await.is_none());
+
+        let cancelled =
+            r#"{"jsonrpc":"2.0","method":"notifications/cancelled","params":{"requestId":7}}"#;
+        assert!(server.handle_request(cancelled).await.is_none());
+    }
+
+    async fn initialize(server: &Arc<Server>, version: &str) -> Value {
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {"protocolVersion": version, "capabilities": {}},
+        });
+        let response = server.handle_request(&request.to_string()).await.unwrap();
+        response["result"]["protocolVersion"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_protocol_version_negotiation() {
+        let (server, _rx) = test_server();
+        assert_eq!(initialize(&server, "2024-11-05").await, "2024-11-05");
+        assert!(!server.structured_content());
+        assert_eq!(initialize(&server, "2025-06-18").await, "2025-06-18");
+        assert!(server.structured_content());
+        // Unknown versions get our newest
+        assert_eq!(
+            initialize(&server, "1999-01-01").await,
+            PROTOCOL_VERSIONS[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_schema_only_for_new_clients() {
+        let (server, _rx) = test_server();
+        let list = r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#;
+        let find = |response: &Value, name: &str| {
+            response["result"]["tools"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|t| t["name"] == name)
+                .cloned()
+                .unwrap()
+        };
+
+        initialize(&server, "2024-11-05").await;
+        let response = server.handle_request(list).await.unwrap();
+        assert!(find(&response, "store_ls").get("outputSchema").is_none());
+
+        initialize(&server, "2025-06-18").await;
+        let response = server.handle_request(list).await.unwrap();
+        let schema = &find(&response, "store_ls")["outputSchema"];
+        assert_eq!(schema["type"], "object");
+        assert!(schema["properties"]["entries"].is_object());
+        // Background tools may also return the task they started
+        let schema = &find(&response, "build")["outputSchema"];
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["anyOf"].as_array().unwrap().len(), 2);
+        assert!(schema["definitions"]["TaskStarted"].is_object());
+        assert!(find(&response, "task_status").get("outputSchema").is_none());
+        // MCP requires every output schema to describe an object
+        for tool in response["result"]["tools"].as_array().unwrap() {
+            if let Some(schema) = tool.get("outputSchema") {
+                assert_eq!(schema["type"], "object", "{}", tool["name"]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_annotations() {
+        let (server, _rx) = test_server();
+        let list = r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#;
+        let response = server.handle_request(list).await.unwrap();
+        let tools = response["result"]["tools"].as_array().unwrap();
+        let annotations =
+            |name: &str| tools.iter().find(|t| t["name"] == name).unwrap()["annotations"].clone();
+
+        for tool in tools {
+            assert!(tool["annotations"]["title"].is_string(), "{}", tool["name"]);
+        }
+        assert_eq!(annotations("store_ls")["readOnlyHint"], true);
+        assert_eq!(annotations("store_gc")["readOnlyHint"], false);
+        assert_eq!(annotations("store_gc")["destructiveHint"], true);
+        assert_eq!(annotations("build")["destructiveHint"], false);
+        // fh_fetch replaces whatever is at target_link
+        assert_eq!(annotations("fh_fetch")["destructiveHint"], true);
+        assert_eq!(annotations("fh_search")["openWorldHint"], true);
+        assert_eq!(annotations("eval")["openWorldHint"], true);
+        assert_eq!(annotations("store_ls")["openWorldHint"], false);
+        assert_eq!(
+            annotations("flake_lock")["destructiveHint"],
+            annotations("flake_update")["destructiveHint"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_policy_denies_background_calls_and_resources() {
+        crate::config::set_test_config(
+            r#"
+[policy]
+deny_tools = ["flake_check"]
+deny_flake_refs = ["github.com/evil/*"]
+"#,
+        );
+        let (server, _rx) = test_server();
+
+        let call = r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"flake_check","arguments":{"background":true}}}"#;
+        let response = server.handle_request(call).await.unwrap();
+        assert_eq!(response["result"]["isError"], true);
+        let text = response["result"]["content"][0]["text"].as_str().unwrap();
+        assert!(text.contains("Denied by policy"), "{}", text);
+        assert!(!text.contains("task_id"), "{}", text);
+
+        for uri in [
+            "nix://closure/github:evil/flake#pkg",
+            "nix://derivation/github:evil/flake#pkg",
+        ] {
+            let read = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 5,
+                "method": "resources/read",
+                "params": {"uri": uri},
+            });
+            let response = server.handle_request(&read.to_string()).await.unwrap();
+            let message = response["error"]["message"].as_str().unwrap();
+            assert!(message.contains("Denied by policy"), "{}: {}", uri, message);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_structured_content() {
+        let (server, _rx) = test_server();
+        let call = r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"task_status","arguments":{}}}"#;
+
+        initialize(&server, "2024-11-05").await;
+        let response = server.handle_request(call).await.unwrap();
+        assert!(response["result"].get("structuredContent").is_none());
+
+        initialize(&server, "2025-06-18").await;
+        let response = server.handle_request(call).await.unwrap();
+        let result = &response["result"];
+        assert!(result["structuredContent"]["tasks"].is_array());
+        let text = result["content"][0]["text"].as_str().unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(text).unwrap(),
+            result["structuredContent"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_roots_requested_from_client() {
+        let (server, mut rx) = test_server();
+
+        let initialize = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{"roots":{"listChanged":true}}}}"#;
+        server.handle_request(initialize).await.unwrap();
+        let initialized = r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#;
+        assert!(server.handle_request(initialized).await.is_none());
+
+        let request = rx.recv().await.unwrap();
+        assert_eq!(request["method"], "roots/list");
+        let response = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "result": {"roots": []},
+        });
+        assert!(server.handle_request(&response.to_string()).await.is_none());
+        assert!(server.pending_roots.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_notification_cancels_in_flight_call() {
+        let (server, _rx) = test_server();
+        let token = CancelToken::new();
+        server
+            .in_flight
+            .lock()
+            .unwrap()
+            .insert(serde_json::json!(7).to_string(), token.clone());
+
+        let cancelled = r#"{"jsonrpc":"2.0","method":"notifications/cancelled","params":{"requestId":7,"reason":"user"}}"#;
+        server.handle_request(cancelled).await;
+
+        assert!(token.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_before_dispatch_is_kept() {
+        let (server, _rx) = test_server();
+        let call = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 8,
+            "method": "tools/call",
+            "params": {"name": "nix_info"}
+        });
+        server.track_call(&call);
+
+        let cancelled =
+            r#"{"jsonrpc":"2.0","method":"notifications/cancelled","params":{"requestId":8}}"#;
+        server.handle_request(cancelled).await;
+
+        let response = server.handle_request(&call.to_string()).await.unwrap();
+        assert_eq!(response["error"]["code"], REQUEST_CANCELLED);
+        assert!(server.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_task_subscription_notifies_until_finished() {
+        let (server, mut rx) = test_server();
+        let id = generate_task_id();
+        register_task(id.clone(), "build .#hello".to_string(), CancelToken::new());
+        let uri = format!("nix://task/{}", id);
+
+        let subscribe = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "resources/subscribe",
+            "params": { "uri": uri },
+        });
+        let response = server.handle_request(&subscribe.to_string()).await.unwrap();
+        assert!(response.get("error").is_none());
+
+        crate::background::append_output(&id, crate::backend::Stream::Stderr, "building");
+        let update = rx.recv().await.unwrap();
+        assert_eq!(update["method"], "notifications/resources/updated");
+        assert_eq!(update["params"]["uri"], uri);
+
+        finish_task(&id, Ok(serde_json::json!({"success": true})));
+        let update = rx.recv().await.unwrap();
+        assert_eq!(update["params"]["uri"], uri);
+
+        // The finished forwarder drops its subscription
+        while server.subscriptions.lock().unwrap().contains_key(&uri) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        crate::background::remove_task(&id);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_rejects_unknown_task() {
+        let (server, _rx) = test_server();
+        let subscribe = r#"{"jsonrpc":"2.0","id":1,"method":"resources/subscribe","params":{"uri":"nix://task/missing"}}"#;
+        let response = server.handle_request(subscribe).await.unwrap();
+        assert_eq!(response["error"]["code"], -32602);
+    }
 }
@@ -0,0 +1,348 @@
+use crate::backend::Stream;
+use crate::background::TaskStatus;
+use crate::config::load_config;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What is kept on disk about a background task, next to its output logs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskRecord {
+    pub id: String,
+    pub command: String,
+    pub status: TaskStatus,
+    /// Unix timestamps in seconds
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub exit_code: Option<i32>,
+    /// The chix process running the task
+    pub server_pid: u32,
+    /// The last command the task spawned
+    pub pid: Option<u32>,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+}
+
+/// A task read back from disk along with the output it had logged
+#[derive(Debug)]
+pub struct StoredTask {
+    pub record: TaskRecord,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Directory holding one `<id>.json` record and `<id>.{stdout,stderr}.log`
+/// per background task, so tasks outlive the server process.
+#[derive(Debug)]
+pub struct TaskStore {
+    dir: PathBuf,
+}
+
+impl TaskStore {
+    pub fn new(dir: PathBuf) -> Self {
+        TaskStore { dir }
+    }
+
+    /// The store configured under `[tasks]`, or the XDG state directory
+    pub fn from_config() -> Option<Self> {
+        // Unit tests must not read or write the user's task state
+        if cfg!(test) {
+            return None;
+        }
+        load_config().tasks.state_dir().map(Self::new)
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn log_path(&self, id: &str, stream: Stream) -> PathBuf {
+        let name = match stream {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        };
+        self.dir.join(format!("{}.{}.log", id, name))
+    }
+
+    /// Write a task's record, replacing any earlier version
+    pub fn save(&self, record: &TaskRecord) {
+        let result = fs::create_dir_all(&self.dir).and_then(|()| {
+            let path = self.record_path(&record.id);
+            let tmp = path.with_extension("json.tmp");
+            fs::write(&tmp, serde_json::to_vec_pretty(record)?)?;
+            fs::rename(&tmp, &path)
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: failed to save task {}: {}", record.id, e);
+        }
+    }
+
+    /// The output logs of a task, for appending to as it runs
+    pub fn log(&self, id: &str) -> TaskLog {
+        TaskLog {
+            id: id.to_string(),
+            stdout: Mutex::new(LogFile::new(self.log_path(id, Stream::Stdout))),
+            stderr: Mutex::new(LogFile::new(self.log_path(id, Stream::Stderr))),
+        }
+    }
+
+    /// Up to `len` bytes a task has logged to `stream`, from byte `offset` on
+    pub fn read_output(&self, id: &str, stream: Stream, offset: usize, len: usize) -> String {
+        let mut bytes = Vec::new();
+        let result = File::open(self.log_path(id, stream)).and_then(|mut file| {
+            file.seek(SeekFrom::Start(offset as u64))?;
+            file.take(len as u64).read_to_end(&mut bytes)
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: failed to read output of task {}: {}", id, e);
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Delete everything stored for a task
+    pub fn remove(&self, id: &str) {
+        let _ = fs::remove_file(self.record_path(id));
+        let _ = fs::remove_file(self.log_path(id, Stream::Stdout));
+        let _ = fs::remove_file(self.log_path(id, Stream::Stderr));
+    }
+
+    /// Every task with a readable record
+    pub fn load_all(&self) -> Vec<StoredTask> {
+        self.load_records()
+            .into_iter()
+            .map(|record| StoredTask {
+                stdout: read_log(&self.log_path(&record.id, Stream::Stdout)),
+                stderr: read_log(&self.log_path(&record.id, Stream::Stderr)),
+                record,
+            })
+            .collect()
+    }
+
+    /// Every readable task record, without its logs
+    fn load_records(&self) -> Vec<TaskRecord> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                serde_json::from_slice(&fs::read(&path).ok()?).ok()
+            })
+            .collect()
+    }
+
+    /// Delete tasks that finished more than `retention` ago, along with logs
+    /// and temporary files whose record is gone.
+    ///
+    /// Files younger than `retention` are left alone, as they may belong to
+    /// a task another instance is still saving.
+    pub fn prune(&self, retention: Duration) {
+        let cutoff = unix_now().saturating_sub(retention.as_secs());
+        let mut kept = Vec::new();
+        for record in self.load_records() {
+            match record.finished_at {
+                Some(finished) if finished < cutoff => self.remove(&record.id),
+                _ => kept.push(record.id),
+            }
+        }
+
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let id = name.split('.').next().unwrap_or_default();
+            let modified = entry.metadata().and_then(|m| m.modified()).map(to_unix);
+            let old = modified.is_ok_and(|modified| modified < cutoff);
+            if old && !kept.iter().any(|k| k == id) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// Output logs of one running task.
+///
+/// Each stream keeps its file open behind a buffer, so appending a line
+/// doesn't open the file or hit the disk every time. Call `flush` once the
+/// task finishes.
+#[derive(Debug)]
+pub struct TaskLog {
+    id: String,
+    stdout: Mutex<LogFile>,
+    stderr: Mutex<LogFile>,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    /// Opened on the first write
+    writer: Option<BufWriter<File>>,
+}
+
+impl LogFile {
+    fn new(path: PathBuf) -> Self {
+        LogFile { path, writer: None }
+    }
+
+    fn append(&mut self, line: &str) -> std::io::Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                self.writer.insert(BufWriter::new(file))
+            }
+        };
+        writeln!(writer, "{}", line)
+    }
+}
+
+impl TaskLog {
+    /// Append a line of output to the log of `stream`
+    pub fn append(&self, stream: Stream, line: &str) {
+        if let Err(e) = self.file(stream).lock().unwrap().append(line) {
+            eprintln!("Warning: failed to log output of task {}: {}", self.id, e);
+        }
+    }
+
+    /// Write out buffered output of both streams
+    pub fn flush(&self) {
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            if let Some(writer) = &mut self.file(stream).lock().unwrap().writer {
+                if let Err(e) = writer.flush() {
+                    eprintln!("Warning: failed to log output of task {}: {}", self.id, e);
+                }
+            }
+        }
+    }
+
+    fn file(&self, stream: Stream) -> &Mutex<LogFile> {
+        match stream {
+            Stream::Stdout => &self.stdout,
+            Stream::Stderr => &self.stderr,
+        }
+    }
+}
+
+fn read_log(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_default()
+}
+
+pub fn unix_now() -> u64 {
+    to_unix(SystemTime::now())
+}
+
+pub fn to_unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Whether a process with this pid exists
+pub fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the pid; EPERM means it exists but isn't ours.
+    // SAFETY: kill with signal 0 sends nothing and touches no memory
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store() -> TaskStore {
+        let dir = std::env::temp_dir().join(format!("chix-tasks-{}", uuid::Uuid::new_v4()));
+        TaskStore::new(dir)
+    }
+
+    fn record(id: &str, finished_at: Option<u64>) -> TaskRecord {
+        TaskRecord {
+            id: id.to_string(),
+            command: "build .#hello".to_string(),
+            status: TaskStatus::Completed,
+            started_at: 1_700_000_000,
+            finished_at,
+            exit_code: Some(0),
+            server_pid: std::process::id(),
+            pid: None,
+            result: Some(serde_json::json!({"success": true})),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let store = temp_store();
+        store.save(&record("task-1", Some(unix_now())));
+        let log = store.log("task-1");
+        log.append(Stream::Stderr, "building hello");
+        log.append(Stream::Stderr, "done");
+        log.flush();
+
+        let tasks = store.load_all();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].record.status, TaskStatus::Completed);
+        assert_eq!(
+            tasks[0].record.result,
+            Some(serde_json::json!({"success": true}))
+        );
+        assert_eq!(tasks[0].stderr, "building hello\ndone\n");
+        assert_eq!(tasks[0].stdout, "");
+
+        let _ = fs::remove_dir_all(&store.dir);
+    }
+
+    #[test]
+    fn test_prune_removes_old_tasks_and_stray_logs() {
+        let store = temp_store();
+        let log_line = |id: &str, stream: Stream, line: &str| {
+            let log = store.log(id);
+            log.append(stream, line);
+            log.flush();
+        };
+        store.save(&record("old", Some(1_700_000_000)));
+        log_line("old", Stream::Stdout, "/nix/store/abc-hello");
+        store.save(&record("recent", Some(unix_now())));
+        store.save(&record("running", None));
+        log_line("gone", Stream::Stderr, "orphaned log");
+        log_line("saving", Stream::Stderr, "record not written yet");
+        let long_ago = SystemTime::now() - Duration::from_secs(7200);
+        File::options()
+            .write(true)
+            .open(store.log_path("gone", Stream::Stderr))
+            .and_then(|file| file.set_modified(long_ago))
+            .unwrap();
+
+        store.prune(Duration::from_secs(3600));
+
+        let mut ids: Vec<_> = store.load_all().into_iter().map(|t| t.record.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["recent", "running"]);
+        assert!(!store.log_path("old", Stream::Stdout).exists());
+        assert!(!store.log_path("gone", Stream::Stderr).exists());
+        assert!(store.log_path("saving", Stream::Stderr).exists());
+
+        let _ = fs::remove_dir_all(&store.dir);
+    }
+
+    #[test]
+    fn test_process_alive() {
+        assert!(process_alive(std::process::id()));
+        assert!(!process_alive(u32::MAX));
+    }
+}
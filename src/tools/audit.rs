@@ -0,0 +1,30 @@
+use crate::audit::{self, AuditFilter, AuditRecord};
+use crate::tools::AuditQueryParams;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AuditQueryResult {
+    pub log_path: String,
+    /// Newest first
+    pub entries: Vec<AuditRecord>,
+    /// Whether older matching entries were left out by `limit`
+    pub has_more: bool,
+}
+
+/// Read recent entries from the audit log, including rotated files
+pub fn audit_query(params: AuditQueryParams) -> Result<AuditQueryResult, String> {
+    let log = audit::log().ok_or("The audit log is disabled; set [audit] enabled = true")?;
+    let filter = AuditFilter {
+        tool: params.tool,
+        since: params.since,
+        failed_only: params.failed_only.unwrap_or(false),
+    };
+    let (entries, has_more) = log.query(&filter, params.limit.unwrap_or(20));
+
+    Ok(AuditQueryResult {
+        log_path: log.path().display().to_string(),
+        entries,
+        has_more,
+    })
+}
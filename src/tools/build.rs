@@ -1,10 +1,14 @@
-use crate::nix_runner::{parse_json_store_paths, parse_store_paths, run_nix_command_in_dir};
+use crate::activity::ActivitySummary;
+use crate::diagnose::{diagnose_build_failure, BuildFailure};
+use crate::nix_runner::{parse_json_store_paths, parse_store_paths, run_nix_command_streaming};
 use crate::output::{limit_text_output, OutputLimits, TruncationInfo};
-use crate::tools::NixBuildParams;
-use crate::validators::{validate_installable, validate_path};
+use crate::tools::{nix_option_args, NixBuildParams};
+use crate::validators::validate_installable;
+use crate::workspace::{check_flake_ref_path, resolve_flake_dir};
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixBuildResult {
     pub success: bool,
     pub store_paths: Vec<String>,
@@ -13,28 +17,36 @@ pub struct NixBuildResult {
     pub truncated: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub truncation_info: Option<TruncationInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activities: Option<ActivitySummary>,
+    /// Diagnosis of what went wrong, only present when the build failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<BuildFailure>,
 }
 
 pub async fn nix_build(params: NixBuildParams) -> Result<NixBuildResult, String> {
     let installable = params
         .installable
         .unwrap_or_else(|| ".#default".to_string());
-    validate_installable(&installable).map_err(|e| e.to_string())?;
+    let parsed = validate_installable(&installable).map_err(|e| e.to_string())?;
 
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
+    check_flake_ref_path(&parsed.flake_ref, flake_dir).await?;
 
     let mut args = vec!["build", "--json", "--print-out-paths"];
 
-    if params.print_build_logs.unwrap_or(true) {
+    let print_build_logs = params.print_build_logs.unwrap_or(true);
+    if print_build_logs {
         args.push("-L");
     }
 
+    let option_args = nix_option_args(params.nix_options.as_ref())?;
+    args.extend(option_args.iter().map(String::as_str));
+
     args.push(&installable);
 
-    let result = run_nix_command_in_dir(&args, flake_dir)
+    let result = run_nix_command_streaming(&args, flake_dir, print_build_logs)
         .await
         .map_err(|e| e.to_string())?;
 
@@ -43,12 +55,23 @@ pub async fn nix_build(params: NixBuildParams) -> Result<NixBuildResult, String>
         store_paths = parse_store_paths(&result.stdout);
     }
 
+    // Diagnose from the full stderr, before truncation can cut the error off
+    let failure = if result.success {
+        None
+    } else {
+        diagnose_build_failure(&result.stderr, result.activities.as_ref())
+    };
+
     // Apply output limits to stderr (build logs)
     let limits = OutputLimits {
         head: None,
         tail: params.log_tail,
         max_bytes: params.max_log_bytes,
         max_lines: None,
+        mode: params.mode.unwrap_or_default(),
+        grep: params.grep.clone(),
+        context: params.context,
+        normalize: params.normalize,
     };
 
     let limited_stderr = limit_text_output(&result.stderr, &limits);
@@ -63,5 +86,7 @@ pub async fn nix_build(params: NixBuildParams) -> Result<NixBuildResult, String>
             None
         },
         truncation_info: limited_stderr.truncation_info,
+        activities: result.activities,
+        failure,
     })
 }
@@ -2,11 +2,10 @@ use crate::config::{get_cachix_token, get_default_cache, load_config};
 use crate::nix_runner::{run_cachix_command, run_cachix_command_with_env, NixError};
 use crate::output::{limit_stderr, TruncationInfo};
 use crate::validators::{validate_cache_name, validate_store_paths};
+use schemars::JsonSchema;
 use serde::Serialize;
 
-const DEFAULT_TIMEOUT_SECS: u64 = 300;
-
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct CachixPushResult {
     pub success: bool,
     pub paths_pushed: Vec<String>,
@@ -18,7 +17,7 @@ pub struct CachixPushResult {
     pub truncation_info: Option<TruncationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct CachixUseResult {
     pub success: bool,
     pub cache_name: String,
@@ -30,7 +29,7 @@ pub struct CachixUseResult {
     pub truncation_info: Option<TruncationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct CachixStatusResult {
     pub success: bool,
     pub authenticated: bool,
@@ -73,11 +72,12 @@ pub async fn cachix_push(
     let path_refs: Vec<&str> = store_paths.iter().map(|s| s.as_str()).collect();
     args.extend(path_refs.iter());
 
-    let output = run_cachix_command_with_env(&args, &env_vars, DEFAULT_TIMEOUT_SECS)
+    let output = run_cachix_command_with_env(&args, &env_vars)
         .await
         .map_err(|e| match e {
-            NixError::Timeout(secs) => format!("cachix push timed out after {} seconds", secs),
+            e @ NixError::Timeout { .. } => e.to_string(),
             NixError::CommandFailed(msg) => format!("cachix push failed: {}", msg),
+            NixError::Cancelled => "cachix push cancelled".to_string(),
             NixError::Io(e) => format!("IO error running cachix: {}", e),
         })?;
 
@@ -103,8 +103,9 @@ pub async fn cachix_use(cache_name: String) -> Result<CachixUseResult, String> {
     let output = run_cachix_command(&["use", &cache_name])
         .await
         .map_err(|e| match e {
-            NixError::Timeout(secs) => format!("cachix use timed out after {} seconds", secs),
+            e @ NixError::Timeout { .. } => e.to_string(),
             NixError::CommandFailed(msg) => format!("cachix use failed: {}", msg),
+            NixError::Cancelled => "cachix use cancelled".to_string(),
             NixError::Io(e) => format!("IO error running cachix: {}", e),
         })?;
 
@@ -126,8 +127,9 @@ pub async fn cachix_status() -> Result<CachixStatusResult, String> {
     let output = run_cachix_command(&["authtoken"])
         .await
         .map_err(|e| match e {
-            NixError::Timeout(secs) => format!("cachix authtoken timed out after {} seconds", secs),
+            e @ NixError::Timeout { .. } => e.to_string(),
             NixError::CommandFailed(msg) => format!("cachix authtoken failed: {}", msg),
+            NixError::Cancelled => "cachix authtoken cancelled".to_string(),
             NixError::Io(e) => format!("IO error running cachix: {}", e),
         })?;
 
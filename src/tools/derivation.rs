@@ -1,10 +1,12 @@
-use crate::nix_runner::run_nix_command_in_dir;
+use crate::nix_runner::{derivation_input_count, normalize_derivations, run_nix_command_in_dir};
 use crate::output::{limit_stderr, PaginationInfo, TruncationInfo};
 use crate::tools::NixDerivationShowParams;
-use crate::validators::{validate_flake_ref, validate_path, validate_store_path};
+use crate::validators::{validate_flake_ref, validate_store_path};
+use crate::workspace::{check_flake_ref_path, resolve_flake_dir};
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct DerivationSummary {
     pub path: String,
     pub name: Option<String>,
@@ -12,7 +14,7 @@ pub struct DerivationSummary {
     pub input_count: usize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixDerivationShowResult {
     pub success: bool,
     /// Full derivation data (when summary_only=false) or null (when summary_only=true)
@@ -45,11 +47,7 @@ fn extract_derivation_summary(
         .map(|o| o.keys().cloned().collect())
         .unwrap_or_default();
 
-    let input_count = drv
-        .get("inputDrvs")
-        .and_then(|v| v.as_object())
-        .map(|o| o.len())
-        .unwrap_or(0);
+    let input_count = derivation_input_count(drv);
 
     DerivationSummary {
         path: path.to_string(),
@@ -64,16 +62,15 @@ pub async fn nix_derivation_show(
 ) -> Result<NixDerivationShowResult, String> {
     let installable = params.installable.unwrap_or_else(|| ".#default".to_string());
 
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
 
     // Validate based on whether it's a store path or installable
     if installable.starts_with("/nix/store/") {
         validate_store_path(&installable).map_err(|e| e.to_string())?;
     } else {
-        validate_flake_ref(&installable).map_err(|e| e.to_string())?;
+        let parsed = validate_flake_ref(&installable).map_err(|e| e.to_string())?;
+        check_flake_ref_path(&parsed, flake_dir).await?;
     }
 
     let mut args = vec!["derivation", "show"];
@@ -104,6 +101,7 @@ pub async fn nix_derivation_show(
 
     let parsed: serde_json::Value =
         serde_json::from_str(&result.stdout).unwrap_or(serde_json::Value::Null);
+    let parsed = normalize_derivations(parsed);
 
     // Handle summary_only mode
     if params.summary_only.unwrap_or(false) {
@@ -1,10 +1,12 @@
 use crate::nix_runner::run_nix_command_in_dir;
 use crate::output::{limit_stderr, limit_text_output, OutputLimits, TruncationInfo};
-use crate::tools::NixEvalParams;
-use crate::validators::{validate_installable, validate_nix_expr, validate_path};
+use crate::tools::{nix_option_args, NixEvalParams};
+use crate::validators::{validate_installable, validate_nix_expr};
+use crate::workspace::{check_flake_ref_path, resolve_flake_dir};
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixEvalResult {
     pub success: bool,
     pub value: serde_json::Value,
@@ -16,10 +18,8 @@ pub struct NixEvalResult {
 }
 
 pub async fn nix_eval(params: NixEvalParams) -> Result<NixEvalResult, String> {
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
 
     let mut args = vec!["eval", "--json"];
 
@@ -28,7 +28,8 @@ pub async fn nix_eval(params: NixEvalParams) -> Result<NixEvalResult, String> {
     let apply: Option<String>;
 
     if let Some(ref i) = params.installable {
-        validate_installable(i).map_err(|e| e.to_string())?;
+        let parsed = validate_installable(i).map_err(|e| e.to_string())?;
+        check_flake_ref_path(&parsed.flake_ref, flake_dir).await?;
         installable = Some(i.clone());
     } else {
         installable = None;
@@ -66,6 +67,9 @@ pub async fn nix_eval(params: NixEvalParams) -> Result<NixEvalResult, String> {
         return Err("Either 'installable' or 'expr' must be provided".to_string());
     }
 
+    let option_args = nix_option_args(params.nix_options.as_ref())?;
+    args.extend(option_args.iter().map(String::as_str));
+
     let result = run_nix_command_in_dir(&args, flake_dir)
         .await
         .map_err(|e| e.to_string())?;
@@ -87,6 +91,10 @@ pub async fn nix_eval(params: NixEvalParams) -> Result<NixEvalResult, String> {
         tail: params.tail,
         max_bytes: params.max_bytes,
         max_lines: None,
+        grep: params.grep.clone(),
+        context: params.context,
+        normalize: params.normalize,
+        ..Default::default()
     };
 
     let limited = limit_text_output(&result.stdout, &limits);
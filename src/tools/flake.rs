@@ -1,13 +1,18 @@
-use crate::nix_runner::run_nix_command_in_dir;
+use crate::lockfile::{diff_locks, lock_path, parse_lock_changes, read_lock, LockChange};
+use crate::nix_runner::{run_nix_command_in_dir, run_nix_command_streaming, NixOutput};
 use crate::output::{limit_stderr, limit_text_output, OutputLimits, TruncationInfo};
 use crate::tools::{
     NixFlakeCheckParams, NixFlakeInitParams, NixFlakeLockParams, NixFlakeMetadataParams,
     NixFlakeShowParams, NixFlakeUpdateParams,
 };
-use crate::validators::{validate_args, validate_flake_ref, validate_path};
+use crate::validators::{validate_args, validate_flake_ref};
+use crate::workspace::{check_flake_ref_path, resolve_flake_dir};
+use schemars::JsonSchema;
 use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixFlakeShowResult {
     pub success: bool,
     pub outputs: serde_json::Value,
@@ -20,12 +25,11 @@ pub struct NixFlakeShowResult {
 
 pub async fn nix_flake_show(params: NixFlakeShowParams) -> Result<NixFlakeShowResult, String> {
     let flake_ref = params.flake_ref.unwrap_or_else(|| ".".to_string());
-    validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
+    let parsed = validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
 
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
+    check_flake_ref_path(&parsed, flake_dir).await?;
 
     let mut args = vec!["flake", "show", "--json"];
 
@@ -56,6 +60,8 @@ pub async fn nix_flake_show(params: NixFlakeShowParams) -> Result<NixFlakeShowRe
         tail: params.tail,
         max_bytes: params.max_bytes,
         max_lines: None,
+        normalize: params.normalize,
+        ..Default::default()
     };
 
     let limited = limit_text_output(&result.stdout, &limits);
@@ -74,7 +80,7 @@ pub async fn nix_flake_show(params: NixFlakeShowParams) -> Result<NixFlakeShowRe
     })
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixFlakeCheckResult {
     pub success: bool,
     pub stdout: String,
@@ -87,12 +93,11 @@ pub struct NixFlakeCheckResult {
 
 pub async fn nix_flake_check(params: NixFlakeCheckParams) -> Result<NixFlakeCheckResult, String> {
     let flake_ref = params.flake_ref.unwrap_or_else(|| ".".to_string());
-    validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
+    let parsed = validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
 
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
+    check_flake_ref_path(&parsed, flake_dir).await?;
 
     let mut args = vec!["flake", "check"];
 
@@ -102,7 +107,7 @@ pub async fn nix_flake_check(params: NixFlakeCheckParams) -> Result<NixFlakeChec
 
     args.push(&flake_ref);
 
-    let result = run_nix_command_in_dir(&args, flake_dir)
+    let result = run_nix_command_streaming(&args, flake_dir, true)
         .await
         .map_err(|e| e.to_string())?;
 
@@ -112,6 +117,10 @@ pub async fn nix_flake_check(params: NixFlakeCheckParams) -> Result<NixFlakeChec
         tail: params.tail,
         max_bytes: params.max_bytes,
         max_lines: None,
+        mode: params.mode.unwrap_or_default(),
+        grep: params.grep.clone(),
+        context: params.context,
+        normalize: params.normalize,
     };
 
     let limited_stdout = limit_text_output(&result.stdout, &limits);
@@ -129,7 +138,7 @@ pub async fn nix_flake_check(params: NixFlakeCheckParams) -> Result<NixFlakeChec
     })
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixFlakeMetadataResult {
     pub success: bool,
     pub metadata: serde_json::Value,
@@ -144,12 +153,11 @@ pub async fn nix_flake_metadata(
     params: NixFlakeMetadataParams,
 ) -> Result<NixFlakeMetadataResult, String> {
     let flake_ref = params.flake_ref.unwrap_or_else(|| ".".to_string());
-    validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
+    let parsed = validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
 
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
+    check_flake_ref_path(&parsed, flake_dir).await?;
 
     let args = vec!["flake", "metadata", "--json", &flake_ref];
 
@@ -174,6 +182,8 @@ pub async fn nix_flake_metadata(
         tail: params.tail,
         max_bytes: params.max_bytes,
         max_lines: None,
+        normalize: params.normalize,
+        ..Default::default()
     };
 
     let limited = limit_text_output(&result.stdout, &limits);
@@ -192,11 +202,60 @@ pub async fn nix_flake_metadata(
     })
 }
 
-#[derive(Debug, Serialize)]
+/// Raw output of a command that rewrites flake.lock
+#[derive(Debug, Default)]
+struct CommandOutput {
+    stdout: Option<String>,
+    stderr: Option<String>,
+    truncated: Option<bool>,
+    truncation_info: Option<TruncationInfo>,
+}
+
+/// Keep a lock command's output only if it failed or the caller asked for
+/// it; on success the lock changes say everything that matters.
+fn command_output(
+    result: &NixOutput,
+    raw_output: Option<bool>,
+    limits: &OutputLimits,
+) -> CommandOutput {
+    if result.success && !raw_output.unwrap_or(false) {
+        return CommandOutput::default();
+    }
+
+    let limited_stdout = limit_text_output(&result.stdout, limits);
+    let limited_stderr = limit_text_output(&result.stderr, limits);
+    let truncated = limited_stdout.truncated || limited_stderr.truncated;
+
+    CommandOutput {
+        stdout: Some(limited_stdout.content),
+        stderr: Some(limited_stderr.content),
+        truncated: if truncated { Some(true) } else { None },
+        truncation_info: limited_stdout.truncation_info.or(limited_stderr.truncation_info),
+    }
+}
+
+/// Compare the lock file with its contents before the command ran, falling
+/// back to what nix printed when the lock can't be read
+fn lock_changes(before: Option<Value>, lock: Option<&Path>, stderr: &str) -> Vec<LockChange> {
+    let after = lock.and_then(read_lock);
+    let changes = diff_locks(before.as_ref(), after.as_ref());
+    if changes.is_empty() {
+        parse_lock_changes(stderr)
+    } else {
+        changes
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixFlakeUpdateResult {
     pub success: bool,
-    pub stdout: String,
-    pub stderr: String,
+    /// Inputs whose lock changed
+    pub changes: Vec<LockChange>,
+    /// Raw output, only on failure or when `raw_output` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub truncated: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -205,12 +264,11 @@ pub struct NixFlakeUpdateResult {
 
 pub async fn nix_flake_update(params: NixFlakeUpdateParams) -> Result<NixFlakeUpdateResult, String> {
     let flake_ref = params.flake_ref.unwrap_or_else(|| ".".to_string());
-    validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
+    let parsed = validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
 
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
+    check_flake_ref_path(&parsed, flake_dir).await?;
 
     let inputs = params.inputs.unwrap_or_default();
     validate_args(&inputs).map_err(|e| e.to_string())?;
@@ -226,36 +284,46 @@ pub async fn nix_flake_update(params: NixFlakeUpdateParams) -> Result<NixFlakeUp
     args.push("--flake");
     args.push(&flake_ref);
 
+    let lock = lock_path(&parsed, flake_dir);
+    let before = lock.as_deref().and_then(read_lock);
+
     let result = run_nix_command_in_dir(&args, flake_dir)
         .await
         .map_err(|e| e.to_string())?;
 
+    let changes = lock_changes(before, lock.as_deref(), &result.stderr);
     let limits = OutputLimits {
         head: params.head,
         tail: params.tail,
         max_bytes: params.max_bytes,
         max_lines: None,
+        grep: params.grep.clone(),
+        context: params.context,
+        normalize: params.normalize,
+        ..Default::default()
     };
-
-    let limited_stdout = limit_text_output(&result.stdout, &limits);
-    let limited_stderr = limit_text_output(&result.stderr, &limits);
-
-    let truncated = limited_stdout.truncated || limited_stderr.truncated;
+    let output = command_output(&result, params.raw_output, &limits);
 
     Ok(NixFlakeUpdateResult {
         success: result.success,
-        stdout: limited_stdout.content,
-        stderr: limited_stderr.content,
-        truncated: if truncated { Some(true) } else { None },
-        truncation_info: limited_stdout.truncation_info.or(limited_stderr.truncation_info),
+        changes,
+        stdout: output.stdout,
+        stderr: output.stderr,
+        truncated: output.truncated,
+        truncation_info: output.truncation_info,
     })
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixFlakeLockResult {
     pub success: bool,
-    pub stdout: String,
-    pub stderr: String,
+    /// Inputs whose lock changed
+    pub changes: Vec<LockChange>,
+    /// Raw output, only on failure or when `raw_output` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub truncated: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -264,12 +332,11 @@ pub struct NixFlakeLockResult {
 
 pub async fn nix_flake_lock(params: NixFlakeLockParams) -> Result<NixFlakeLockResult, String> {
     let flake_ref = params.flake_ref.unwrap_or_else(|| ".".to_string());
-    validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
+    let parsed = validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
 
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
+    check_flake_ref_path(&parsed, flake_dir).await?;
 
     let mut args = vec!["flake", "lock"];
 
@@ -286,6 +353,10 @@ pub async fn nix_flake_lock(params: NixFlakeLockParams) -> Result<NixFlakeLockRe
 
     // Build override-input args
     let override_inputs = params.override_inputs.unwrap_or_default();
+    for input_ref in override_inputs.values() {
+        let parsed = validate_flake_ref(input_ref).map_err(|e| e.to_string())?;
+        check_flake_ref_path(&parsed, flake_dir).await?;
+    }
     let override_input_args: Vec<String> = override_inputs
         .iter()
         .flat_map(|(k, v)| vec!["--override-input".to_string(), k.clone(), v.clone()])
@@ -296,32 +367,37 @@ pub async fn nix_flake_lock(params: NixFlakeLockParams) -> Result<NixFlakeLockRe
 
     args.push(&flake_ref);
 
+    let lock = lock_path(&parsed, flake_dir);
+    let before = lock.as_deref().and_then(read_lock);
+
     let result = run_nix_command_in_dir(&args, flake_dir)
         .await
         .map_err(|e| e.to_string())?;
 
+    let changes = lock_changes(before, lock.as_deref(), &result.stderr);
     let limits = OutputLimits {
         head: params.head,
         tail: params.tail,
         max_bytes: params.max_bytes,
         max_lines: None,
+        grep: params.grep.clone(),
+        context: params.context,
+        normalize: params.normalize,
+        ..Default::default()
     };
-
-    let limited_stdout = limit_text_output(&result.stdout, &limits);
-    let limited_stderr = limit_text_output(&result.stderr, &limits);
-
-    let truncated = limited_stdout.truncated || limited_stderr.truncated;
+    let output = command_output(&result, params.raw_output, &limits);
 
     Ok(NixFlakeLockResult {
         success: result.success,
-        stdout: limited_stdout.content,
-        stderr: limited_stderr.content,
-        truncated: if truncated { Some(true) } else { None },
-        truncation_info: limited_stdout.truncation_info.or(limited_stderr.truncation_info),
+        changes,
+        stdout: output.stdout,
+        stderr: output.stderr,
+        truncated: output.truncated,
+        truncation_info: output.truncation_info,
     })
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixFlakeInitResult {
     pub success: bool,
     pub stdout: String,
@@ -333,16 +409,15 @@ pub struct NixFlakeInitResult {
 }
 
 pub async fn nix_flake_init(params: NixFlakeInitParams) -> Result<NixFlakeInitResult, String> {
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
 
     let mut args = vec!["flake", "init"];
 
     let template_ref;
     if let Some(ref template) = params.template {
-        validate_flake_ref(template).map_err(|e| e.to_string())?;
+        let parsed = validate_flake_ref(template).map_err(|e| e.to_string())?;
+        check_flake_ref_path(&parsed, flake_dir).await?;
         template_ref = template.clone();
         args.push("--template");
         args.push(&template_ref);
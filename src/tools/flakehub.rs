@@ -5,6 +5,8 @@ use crate::tools::{
     FhLoginParams, FhResolveParams, FhSearchParams,
 };
 use crate::validators::{validate_no_shell_metacharacters, validate_path};
+use crate::workspace::resolve_workspace_arg;
+use schemars::JsonSchema;
 use serde::Serialize;
 
 fn paginate_json_array(
@@ -38,7 +40,7 @@ fn paginate_json_array(
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct FhSearchResult {
     pub success: bool,
     pub results: serde_json::Value,
@@ -51,7 +53,7 @@ pub struct FhSearchResult {
     pub truncation_info: Option<TruncationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct FhAddResult {
     pub success: bool,
     pub output: String,
@@ -62,7 +64,7 @@ pub struct FhAddResult {
     pub truncation_info: Option<TruncationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct FhListResult {
     pub success: bool,
     pub results: serde_json::Value,
@@ -75,7 +77,7 @@ pub struct FhListResult {
     pub truncation_info: Option<TruncationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct FhResolveResult {
     pub success: bool,
     pub result: serde_json::Value,
@@ -129,7 +131,7 @@ pub async fn fh_add(params: FhAddParams) -> Result<FhAddResult, String> {
     let flake_path;
     if let Some(ref path) = params.flake_path {
         validate_no_shell_metacharacters(path).map_err(|e| e.to_string())?;
-        flake_path = path.clone();
+        flake_path = resolve_workspace_arg(path).await?;
         args.push("--flake-path");
         args.push(&flake_path);
     }
@@ -285,7 +287,7 @@ pub async fn fh_resolve(params: FhResolveParams) -> Result<FhResolveResult, Stri
     })
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct FhStatusResult {
     pub success: bool,
     pub logged_in: bool,
@@ -297,7 +299,7 @@ pub struct FhStatusResult {
     pub truncation_info: Option<TruncationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct FhFetchResult {
     pub success: bool,
     pub store_path: Option<String>,
@@ -310,7 +312,7 @@ pub struct FhFetchResult {
     pub truncation_info: Option<TruncationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct FhLoginResult {
     pub success: bool,
     pub message: String,
@@ -344,8 +346,9 @@ pub async fn fh_status() -> Result<FhStatusResult, String> {
 pub async fn fh_fetch(params: FhFetchParams) -> Result<FhFetchResult, String> {
     validate_no_shell_metacharacters(&params.flake_ref).map_err(|e| e.to_string())?;
     validate_path(&params.target_link).map_err(|e| e.to_string())?;
+    let target_link = resolve_workspace_arg(&params.target_link).await?;
 
-    let args = vec!["fetch", &params.flake_ref, &params.target_link];
+    let args = vec!["fetch", &params.flake_ref, &target_link];
     let result = run_fh_command(&args).await.map_err(|e| e.to_string())?;
 
     // Extract store path from output if present
@@ -374,7 +377,7 @@ pub async fn fh_login(params: FhLoginParams) -> Result<FhLoginResult, String> {
     let token_file;
     if let Some(ref path) = params.token_file {
         validate_path(path).map_err(|e| e.to_string())?;
-        token_file = path.clone();
+        token_file = resolve_workspace_arg(path).await?;
         args.push("--token-file");
         args.push(&token_file);
     }
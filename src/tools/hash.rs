@@ -2,9 +2,11 @@ use crate::nix_runner::run_nix_command;
 use crate::output::{limit_stderr, TruncationInfo};
 use crate::tools::{NixHashFileParams, NixHashPathParams};
 use crate::validators::validate_path;
+use crate::workspace::resolve_workspace_arg;
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixHashResult {
     pub success: bool,
     pub hash: String,
@@ -17,6 +19,7 @@ pub struct NixHashResult {
 
 pub async fn nix_hash_path(params: NixHashPathParams) -> Result<NixHashResult, String> {
     validate_path(&params.path).map_err(|e| e.to_string())?;
+    let path = resolve_workspace_arg(&params.path).await?;
 
     let hash_type = params.hash_type.unwrap_or_else(|| "sha256".to_string());
     let valid_types = ["sha256", "sha512", "sha1", "md5"];
@@ -37,7 +40,7 @@ pub async fn nix_hash_path(params: NixHashPathParams) -> Result<NixHashResult, S
 
     args.push("--type");
     args.push(&hash_type);
-    args.push(&params.path);
+    args.push(&path);
 
     let result = run_nix_command(&args).await.map_err(|e| e.to_string())?;
 
@@ -54,6 +57,7 @@ pub async fn nix_hash_path(params: NixHashPathParams) -> Result<NixHashResult, S
 
 pub async fn nix_hash_file(params: NixHashFileParams) -> Result<NixHashResult, String> {
     validate_path(&params.path).map_err(|e| e.to_string())?;
+    let path = resolve_workspace_arg(&params.path).await?;
 
     let hash_type = params.hash_type.unwrap_or_else(|| "sha256".to_string());
     let valid_types = ["sha256", "sha512", "sha1", "md5"];
@@ -74,7 +78,7 @@ pub async fn nix_hash_file(params: NixHashFileParams) -> Result<NixHashResult, S
 
     args.push("--type");
     args.push(&hash_type);
-    args.push(&params.path);
+    args.push(&path);
 
     let result = run_nix_command(&args).await.map_err(|e| e.to_string())?;
 
@@ -0,0 +1,186 @@
+use crate::nix_runner::run_nix_command;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::LazyLock;
+
+// `nix --version` output, e.g.
+//   nix (Nix) 2.24.10
+//   nix (Lix, like Nix) 2.91.1
+//   nix (Determinate Nix 3.6.2) 2.29.0
+static VERSION_LINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\S+ \(([^)]*)\) (\S+)").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NixImplementation {
+    Nix,
+    Lix,
+    Determinate,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct NixInfo {
+    pub implementation: NixImplementation,
+    /// Version number as printed by `nix --version`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Distribution version where it differs from `version` (Determinate Nix)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub implementation_version: Option<String>,
+    /// First line of `nix --version` output
+    pub version_string: String,
+    pub experimental_features: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// Whether flakes can be used, either stable or enabled as an experimental feature
+    pub flakes_enabled: bool,
+}
+
+fn parse_version(output: &str) -> (NixImplementation, Option<String>, Option<String>) {
+    let line = output.lines().next().unwrap_or("").trim();
+    let Some(caps) = VERSION_LINE.captures(line) else {
+        return (NixImplementation::Unknown, None, None);
+    };
+
+    let name = caps.get(1).map_or("", |m| m.as_str());
+    let version = caps.get(2).map(|m| m.as_str().to_string());
+
+    if let Some(distribution) = name.strip_prefix("Determinate Nix") {
+        let distribution = distribution.trim();
+        let implementation_version = (!distribution.is_empty()).then(|| distribution.to_string());
+        (
+            NixImplementation::Determinate,
+            version,
+            implementation_version,
+        )
+    } else if name.starts_with("Lix") {
+        (NixImplementation::Lix, version, None)
+    } else if name == "Nix" {
+        (NixImplementation::Nix, version, None)
+    } else {
+        (NixImplementation::Unknown, version, None)
+    }
+}
+
+/// Pull experimental features and system out of `nix config show --json`
+fn parse_config(output: &str) -> (Vec<String>, Option<String>) {
+    let Ok(config) = serde_json::from_str::<serde_json::Value>(output) else {
+        return (Vec::new(), None);
+    };
+
+    // Entries are `{"value": ..., "description": ...}`; older releases
+    // printed bare values
+    let setting = |name: &str| {
+        let entry = config.get(name)?;
+        Some(entry.get("value").unwrap_or(entry).clone())
+    };
+
+    let features = match setting("experimental-features") {
+        Some(serde_json::Value::Array(items)) => items
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|s| s.to_string())
+            .collect(),
+        Some(serde_json::Value::String(s)) => s.split_whitespace().map(|s| s.to_string()).collect(),
+        _ => Vec::new(),
+    };
+
+    let system = setting("system").and_then(|v| v.as_str().map(|s| s.to_string()));
+
+    (features, system)
+}
+
+/// Probe the installed nix for its implementation, version and features
+pub async fn nix_info() -> Result<NixInfo, String> {
+    let version_output = run_nix_command(&["--version"])
+        .await
+        .map_err(|e| e.to_string())?;
+    if !version_output.success {
+        return Err(format!(
+            "nix --version failed: {}",
+            version_output.stderr.trim()
+        ));
+    }
+
+    let (implementation, version, implementation_version) = parse_version(&version_output.stdout);
+
+    // `nix show-config` was renamed to `nix config show` in Nix 2.19; Lix and
+    // older releases only have the former
+    let mut config_output = run_nix_command(&["config", "show", "--json"])
+        .await
+        .map_err(|e| e.to_string())?;
+    if !config_output.success {
+        config_output = run_nix_command(&["show-config", "--json"])
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let (experimental_features, system) = if config_output.success {
+        parse_config(&config_output.stdout)
+    } else {
+        (Vec::new(), None)
+    };
+
+    let flakes_enabled = implementation == NixImplementation::Determinate
+        || experimental_features.iter().any(|f| f == "flakes");
+
+    Ok(NixInfo {
+        implementation,
+        version,
+        implementation_version,
+        version_string: version_output
+            .stdout
+            .lines()
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_string(),
+        experimental_features,
+        system,
+        flakes_enabled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version_variants() {
+        let (imp, ver, dist) = parse_version("nix (Nix) 2.24.10\n");
+        assert_eq!(imp, NixImplementation::Nix);
+        assert_eq!(ver.as_deref(), Some("2.24.10"));
+        assert!(dist.is_none());
+
+        let (imp, ver, _) = parse_version("nix (Lix, like Nix) 2.91.1");
+        assert_eq!(imp, NixImplementation::Lix);
+        assert_eq!(ver.as_deref(), Some("2.91.1"));
+
+        let (imp, ver, dist) = parse_version("nix (Determinate Nix 3.6.2) 2.29.0");
+        assert_eq!(imp, NixImplementation::Determinate);
+        assert_eq!(ver.as_deref(), Some("2.29.0"));
+        assert_eq!(dist.as_deref(), Some("3.6.2"));
+
+        let (imp, ver, _) = parse_version("something else");
+        assert_eq!(imp, NixImplementation::Unknown);
+        assert!(ver.is_none());
+    }
+
+    #[test]
+    fn test_parse_config_formats() {
+        let current = r#"{
+            "experimental-features": {"value": ["flakes", "nix-command"], "description": "..."},
+            "system": {"value": "x86_64-linux"}
+        }"#;
+        let (features, system) = parse_config(current);
+        assert_eq!(features, vec!["flakes", "nix-command"]);
+        assert_eq!(system.as_deref(), Some("x86_64-linux"));
+
+        let bare = r#"{"experimental-features": "nix-command flakes", "system": "aarch64-darwin"}"#;
+        let (features, system) = parse_config(bare);
+        assert_eq!(features, vec!["nix-command", "flakes"]);
+        assert_eq!(system.as_deref(), Some("aarch64-darwin"));
+    }
+}
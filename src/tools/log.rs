@@ -2,9 +2,11 @@ use crate::nix_runner::run_nix_command;
 use crate::output::{limit_stderr, limit_text_output, OutputLimits, TruncationInfo};
 use crate::tools::NixLogParams;
 use crate::validators::validate_installable;
+use crate::workspace::check_flake_ref_path;
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixLogResult {
     pub success: bool,
     pub log: String,
@@ -16,7 +18,8 @@ pub struct NixLogResult {
 }
 
 pub async fn nix_log(params: NixLogParams) -> Result<NixLogResult, String> {
-    validate_installable(&params.installable).map_err(|e| e.to_string())?;
+    let parsed = validate_installable(&params.installable).map_err(|e| e.to_string())?;
+    check_flake_ref_path(&parsed.flake_ref, None).await?;
 
     let args = vec!["log", &params.installable];
 
@@ -28,6 +31,10 @@ pub async fn nix_log(params: NixLogParams) -> Result<NixLogResult, String> {
         tail: params.tail,
         max_bytes: params.max_bytes,
         max_lines: None,
+        mode: params.mode.unwrap_or_default(),
+        grep: params.grep.clone(),
+        context: params.context,
+        normalize: params.normalize,
     };
 
     let limited = limit_text_output(&result.stdout, &limits);
@@ -1,10 +1,12 @@
 use crate::lsp_client::{create_nil_client, LspClient};
 use crate::output::PaginationInfo;
 use crate::validators::validate_no_shell_metacharacters;
+use crate::workspace::resolve_workspace_arg;
+use schemars::JsonSchema;
 use serde::Serialize;
 use std::path::Path;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct DiagnosticsResult {
     pub success: bool,
     pub file_path: String,
@@ -14,7 +16,7 @@ pub struct DiagnosticsResult {
     pub pagination: Option<PaginationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct DiagnosticInfo {
     pub line: u32,
     pub character: u32,
@@ -25,7 +27,7 @@ pub struct DiagnosticInfo {
     pub source: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct CompletionsResult {
     pub success: bool,
     pub completions: Vec<CompletionInfo>,
@@ -34,7 +36,7 @@ pub struct CompletionsResult {
     pub pagination: Option<PaginationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct CompletionInfo {
     pub label: String,
     pub kind: Option<String>,
@@ -42,7 +44,7 @@ pub struct CompletionInfo {
     pub documentation: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct HoverInfoResult {
     pub success: bool,
     pub contents: Option<String>,
@@ -50,7 +52,7 @@ pub struct HoverInfoResult {
     pub error: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct RangeInfo {
     pub start_line: u32,
     pub start_character: u32,
@@ -58,14 +60,14 @@ pub struct RangeInfo {
     pub end_character: u32,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct DefinitionResult {
     pub success: bool,
     pub locations: Vec<LocationInfo>,
     pub error: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct LocationInfo {
     pub uri: String,
     pub line: u32,
@@ -131,6 +133,7 @@ pub async fn nil_diagnostics(
     limit: Option<usize>,
 ) -> Result<DiagnosticsResult, String> {
     validate_no_shell_metacharacters(&file_path).map_err(|e| e.to_string())?;
+    let file_path = resolve_workspace_arg(&file_path).await?;
 
     let path = Path::new(&file_path);
     if !path.exists() {
@@ -212,6 +215,7 @@ pub async fn nil_completions(
     limit: Option<usize>,
 ) -> Result<CompletionsResult, String> {
     validate_no_shell_metacharacters(&file_path).map_err(|e| e.to_string())?;
+    let file_path = resolve_workspace_arg(&file_path).await?;
 
     let path = Path::new(&file_path);
     if !path.exists() {
@@ -289,6 +293,7 @@ pub async fn nil_hover(
     character: u32,
 ) -> Result<HoverInfoResult, String> {
     validate_no_shell_metacharacters(&file_path).map_err(|e| e.to_string())?;
+    let file_path = resolve_workspace_arg(&file_path).await?;
 
     let path = Path::new(&file_path);
     if !path.exists() {
@@ -344,6 +349,7 @@ pub async fn nil_definition(
     character: u32,
 ) -> Result<DefinitionResult, String> {
     validate_no_shell_metacharacters(&file_path).map_err(|e| e.to_string())?;
+    let file_path = resolve_workspace_arg(&file_path).await?;
 
     let path = Path::new(&file_path);
     if !path.exists() {
@@ -1,3 +1,4 @@
+mod audit;
 mod build;
 mod cachix;
 mod derivation;
@@ -5,12 +6,15 @@ mod eval;
 mod flake;
 mod flakehub;
 mod hash;
+mod info;
 mod log;
 mod lsp;
 mod run;
 mod search;
 mod store;
+mod task;
 
+pub use audit::audit_query;
 pub use build::nix_build;
 pub use cachix::{cachix_push, cachix_status, cachix_use};
 pub use derivation::nix_derivation_show;
@@ -24,26 +28,94 @@ pub use flakehub::{
     fh_search, fh_status,
 };
 pub use hash::{nix_hash_file, nix_hash_path};
+pub use info::{nix_info, NixInfo};
 pub use log::nix_log;
 pub use lsp::{nil_completions, nil_definition, nil_diagnostics, nil_hover};
 pub use run::{nix_develop_run, nix_run, CommandResult, NixDevelopRunResult};
 pub use search::nix_search;
 pub use store::{nix_copy, nix_store_cat, nix_store_gc, nix_store_ls, nix_store_path_info};
+pub use task::task_output;
 
+use crate::backend::Stream;
+use crate::background::TaskStarted;
+use crate::config::load_config;
+use crate::output::OutputMode;
+use crate::validators::{validate_nix_option, ALLOWED_NIX_OPTIONS};
+use schemars::generate::SchemaSettings;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 
 #[derive(Debug, Serialize)]
 pub struct ToolInfo {
     pub name: &'static str,
     pub description: &'static str,
+    pub annotations: ToolAnnotations,
     pub input_schema: serde_json::Value,
 }
 
+/// MCP hints about what a tool does, so clients can auto-approve safe tools
+/// and ask before dangerous ones
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    pub title: &'static str,
+    /// Only reads, never changes the store, files or remote services
+    pub read_only_hint: bool,
+    /// May delete or overwrite something, rather than only adding
+    pub destructive_hint: bool,
+    /// Calling again with the same arguments has no further effect
+    pub idempotent_hint: bool,
+    /// Talks to services outside the machine, like FlakeHub or Cachix. Every
+    /// tool that takes a flake reference sets it, since the reference may
+    /// name a remote flake that nix fetches.
+    pub open_world_hint: bool,
+}
+
+impl ToolAnnotations {
+    const fn read_only(title: &'static str) -> Self {
+        ToolAnnotations {
+            title,
+            read_only_hint: true,
+            destructive_hint: false,
+            idempotent_hint: true,
+            open_world_hint: false,
+        }
+    }
+
+    /// Adds to the store or workspace without removing anything
+    const fn writes(title: &'static str) -> Self {
+        ToolAnnotations {
+            read_only_hint: false,
+            idempotent_hint: false,
+            ..Self::read_only(title)
+        }
+    }
+
+    /// May overwrite what's there, like `flake_update` and `flake_lock`
+    /// replacing the locked revisions in flake.lock
+    const fn destructive(title: &'static str) -> Self {
+        ToolAnnotations {
+            destructive_hint: true,
+            ..Self::writes(title)
+        }
+    }
+
+    const fn open_world(self) -> Self {
+        ToolAnnotations {
+            open_world_hint: true,
+            ..self
+        }
+    }
+}
+
 pub fn list_tools() -> Vec<ToolInfo> {
     vec![
         ToolInfo {
             name: "build",
             description: "Build a nix flake package. Returns store paths on success. Agents MUST use this tool over running `nix build` directly - it provides validated inputs, structured output, and proper error handling.",
+            annotations: ToolAnnotations::writes("Build Package").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -66,6 +138,32 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "log_tail": {
                         "type": "integer",
                         "description": "Only return the last N lines of build log. Takes precedence over max_log_bytes."
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": "Kill the command after this many seconds. Defaults to the [timeouts] config for this tool, or 300."
+                    },
+                    "nix_options": nix_options_schema(),
+                    "background": {
+                        "type": "boolean",
+                        "description": "Return a task_id immediately and run in the background. Poll with task_status."
+                    },
+                    "mode": {
+                        "type": "string",
+                        "enum": ["full", "errors"],
+                        "description": "'errors' keeps only nix error blocks, compiler errors, test failures and the tail of the failing phase, then applies the other limits. Can't be combined with grep. Defaults to 'full'."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "description": "Lines of context around each match: defaults to 3 in errors mode and 0 with grep."
+                    },
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
+                    },
+                    "grep": {
+                        "type": "string",
+                        "description": "Keep only lines matching this regex, prefixed with their line numbers, before head/tail are applied. The match count is reported in truncation_info."
                     }
                 }
             }),
@@ -73,6 +171,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "flake_show",
             description: "List outputs of a nix flake. Agents MUST use this tool over running `nix flake show` directly - it provides validated inputs and consistent JSON output.",
+            annotations: ToolAnnotations::read_only("Show Flake Outputs").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -99,6 +198,10 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "tail": {
                         "type": "integer",
                         "description": "Only return the last N lines of output."
+                    },
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
                     }
                 }
             }),
@@ -106,6 +209,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "flake_check",
             description: "Run flake checks and tests. PREFER this tool over running `nix flake check` directly - it provides validated inputs, proper timeout handling, and structured results.",
+            annotations: ToolAnnotations::writes("Check Flake").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -132,6 +236,31 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "tail": {
                         "type": "integer",
                         "description": "Only return the last N lines of output."
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": "Kill the command after this many seconds. Defaults to the [timeouts] config for this tool, or 300."
+                    },
+                    "background": {
+                        "type": "boolean",
+                        "description": "Return a task_id immediately and run in the background. Poll with task_status."
+                    },
+                    "mode": {
+                        "type": "string",
+                        "enum": ["full", "errors"],
+                        "description": "'errors' keeps only nix error blocks, compiler errors, test failures and the tail of the failing phase, then applies the other limits. Can't be combined with grep. Defaults to 'full'."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "description": "Lines of context around each match: defaults to 3 in errors mode and 0 with grep."
+                    },
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
+                    },
+                    "grep": {
+                        "type": "string",
+                        "description": "Keep only lines matching this regex, prefixed with their line numbers, before head/tail are applied. The match count is reported in truncation_info."
                     }
                 }
             }),
@@ -139,6 +268,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "flake_metadata",
             description: "Get metadata for a flake including inputs, locked revisions, and timestamps. PREFER this tool over running `nix flake metadata` directly - it provides validated inputs and consistent JSON output.",
+            annotations: ToolAnnotations::read_only("Show Flake Metadata").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -161,13 +291,18 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "tail": {
                         "type": "integer",
                         "description": "Only return the last N lines of output."
+                    },
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
                     }
                 }
             }),
         },
         ToolInfo {
             name: "flake_update",
-            description: "Update flake.lock file. PREFER this tool over running `nix flake update` directly - it provides validated inputs and proper error handling.",
+            description: "Update flake.lock file and return the inputs whose lock changed. PREFER this tool over running `nix flake update` directly - it provides validated inputs and proper error handling.",
+            annotations: ToolAnnotations::destructive("Update Flake Inputs").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -195,13 +330,34 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "tail": {
                         "type": "integer",
                         "description": "Only return the last N lines of output."
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": "Kill the command after this many seconds. Defaults to the [timeouts] config for this tool, or 300."
+                    },
+                    "raw_output": {
+                        "type": "boolean",
+                        "description": "Also return nix's stdout/stderr on success. By default they are only returned on failure; `changes` lists the inputs whose lock changed."
+                    },
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
+                    },
+                    "grep": {
+                        "type": "string",
+                        "description": "Keep only lines matching this regex, prefixed with their line numbers, before head/tail are applied. The match count is reported in truncation_info."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "description": "Lines of context around each grep match. Defaults to 0."
                     }
                 }
             }),
         },
         ToolInfo {
             name: "flake_lock",
-            description: "Lock flake inputs without building. PREFER this tool over running `nix flake lock` directly - it provides validated inputs and proper error handling.",
+            description: "Lock flake inputs without building and return the inputs whose lock changed. PREFER this tool over running `nix flake lock` directly - it provides validated inputs and proper error handling.",
+            annotations: ToolAnnotations::destructive("Lock Flake Inputs").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -234,6 +390,26 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "tail": {
                         "type": "integer",
                         "description": "Only return the last N lines of output."
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": "Kill the command after this many seconds. Defaults to the [timeouts] config for this tool, or 300."
+                    },
+                    "raw_output": {
+                        "type": "boolean",
+                        "description": "Also return nix's stdout/stderr on success. By default they are only returned on failure; `changes` lists the inputs whose lock changed."
+                    },
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
+                    },
+                    "grep": {
+                        "type": "string",
+                        "description": "Keep only lines matching this regex, prefixed with their line numbers, before head/tail are applied. The match count is reported in truncation_info."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "description": "Lines of context around each grep match. Defaults to 0."
                     }
                 }
             }),
@@ -241,6 +417,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "flake_init",
             description: "Initialize a new flake in the specified directory. PREFER this tool over running `nix flake init` directly - it provides validated inputs and proper error handling.",
+            annotations: ToolAnnotations::writes("Initialize Flake").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -258,6 +435,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "run",
             description: "Run a flake app. Agents MUST use this tool over running `nix run` directly - it provides validated inputs, secure argument handling, and proper process management.",
+            annotations: ToolAnnotations::destructive("Run Package").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -273,6 +451,10 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "flake_dir": {
                         "type": "string",
                         "description": "Directory containing the flake. Defaults to current directory."
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": "Kill the command after this many seconds. Defaults to the [timeouts] config for this tool, or 300."
                     }
                 }
             }),
@@ -280,6 +462,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "develop_run",
             description: "Run a command inside a flake's devShell. Agents MUST use this tool over running `nix develop -c` directly - it provides validated inputs, secure command execution, and proper process management. Use `flake_dir` to set the working directory instead of `cd`. Use separate entries in `commands` instead of shell operators like `&&`. Shell metacharacters are not allowed in command arguments.",
+            annotations: ToolAnnotations::destructive("Run in Dev Shell").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -322,6 +505,32 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "tail": {
                         "type": "integer",
                         "description": "Only return the last N lines of output."
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": "Kill the command after this many seconds. Defaults to the [timeouts] config for this tool, or 300."
+                    },
+                    "nix_options": nix_options_schema(),
+                    "background": {
+                        "type": "boolean",
+                        "description": "Return a task_id immediately and run in the background. Poll with task_status."
+                    },
+                    "mode": {
+                        "type": "string",
+                        "enum": ["full", "errors"],
+                        "description": "'errors' keeps only nix error blocks, compiler errors, test failures and the tail of the failing phase, then applies the other limits. Can't be combined with grep. Defaults to 'full'."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "description": "Lines of context around each match: defaults to 3 in errors mode and 0 with grep."
+                    },
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
+                    },
+                    "grep": {
+                        "type": "string",
+                        "description": "Keep only lines matching this regex, prefixed with their line numbers, before head/tail are applied. The match count is reported in truncation_info."
                     }
                 },
                 "required": ["commands"]
@@ -330,6 +539,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "log",
             description: "Get build logs for a derivation. Agents MUST use this tool over running `nix log` directly - it provides validated inputs and optional head/tail functionality.",
+            annotations: ToolAnnotations::read_only("Show Build Log").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -348,6 +558,23 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "max_bytes": {
                         "type": "integer",
                         "description": "Maximum bytes of log output to return. Defaults to config value (100KB)."
+                    },
+                    "mode": {
+                        "type": "string",
+                        "enum": ["full", "errors"],
+                        "description": "'errors' keeps only nix error blocks, compiler errors, test failures and the tail of the failing phase, then applies the other limits. Can't be combined with grep. Defaults to 'full'."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "description": "Lines of context around each match: defaults to 3 in errors mode and 0 with grep."
+                    },
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
+                    },
+                    "grep": {
+                        "type": "string",
+                        "description": "Keep only lines matching this regex, prefixed with their line numbers, before head/tail are applied. The match count is reported in truncation_info."
                     }
                 },
                 "required": ["installable"]
@@ -356,6 +583,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "search",
             description: "Search for packages in a flake. PREFER this tool over running `nix search` directly - it provides validated inputs, structured JSON output, and pagination.",
+            annotations: ToolAnnotations::read_only("Search Packages").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -387,6 +615,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "store_path_info",
             description: "Get information about a store path or installable. PREFER this tool over running `nix path-info` directly - it provides validated inputs, structured JSON output, and closure limiting.",
+            annotations: ToolAnnotations::read_only("Show Store Path Info").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -417,6 +646,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "store_gc",
             description: "Run garbage collection on the Nix store. PREFER this tool over running `nix store gc` directly - it provides validated inputs and proper error handling.",
+            annotations: ToolAnnotations::destructive("Collect Store Garbage"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -427,6 +657,10 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "max_freed": {
                         "type": "string",
                         "description": "Stop after freeing this much space (e.g., '1G', '500M')."
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": "Kill the command after this many seconds. Defaults to the [timeouts] config for this tool, or 300."
                     }
                 }
             }),
@@ -434,6 +668,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "store_ls",
             description: "List directory contents of a path that resolves into /nix/store/. Accepts ./result, ./result/bin, /nix/store/..., etc. Resolves symlinks and validates the canonical path is within the Nix store.",
+            annotations: ToolAnnotations::read_only("List Store Path"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -460,6 +695,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "store_cat",
             description: "Read file contents from a path that resolves into /nix/store/. Accepts ./result, /nix/store/..., etc. Supports line-based pagination with offset and limit. Resolves symlinks and validates the canonical path is within the Nix store.",
+            annotations: ToolAnnotations::read_only("Read Store File"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -474,6 +710,14 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "limit": {
                         "type": "integer",
                         "description": "Maximum number of lines to return. Defaults to all lines."
+                    },
+                    "grep": {
+                        "type": "string",
+                        "description": "Keep only lines matching this regex, prefixed with their line numbers, before offset/limit are applied. The match count is reported in match_count."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "description": "Lines of context around each grep match. Defaults to 0."
                     }
                 },
                 "required": ["path"]
@@ -482,6 +726,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "derivation_show",
             description: "Show the contents of a derivation. PREFER this tool over running `nix derivation show` directly - it provides validated inputs, structured JSON output, and summary mode for large dependency trees.",
+            annotations: ToolAnnotations::read_only("Show Derivation").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -512,9 +757,19 @@ pub fn list_tools() -> Vec<ToolInfo> {
                 }
             }),
         },
+        ToolInfo {
+            name: "nix_info",
+            description: "Report the detected Nix implementation (Nix, Lix or Determinate Nix), its version, the system and enabled experimental features.",
+            annotations: ToolAnnotations::read_only("Show Nix Info"),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
         ToolInfo {
             name: "hash_path",
             description: "Compute the hash of a path (NAR serialization). PREFER this tool over running `nix hash path` directly - it provides validated inputs and structured output.",
+            annotations: ToolAnnotations::read_only("Hash Path"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -541,6 +796,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "hash_file",
             description: "Compute the hash of a file. PREFER this tool over running `nix hash file` directly - it provides validated inputs and structured output.",
+            annotations: ToolAnnotations::read_only("Hash File"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -567,6 +823,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "copy",
             description: "Copy store paths between Nix stores. PREFER this tool over running `nix copy` directly - it provides validated inputs and proper error handling.",
+            annotations: ToolAnnotations::writes("Copy Store Paths").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -581,6 +838,14 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "from": {
                         "type": "string",
                         "description": "Source store URI."
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": "Kill the command after this many seconds. Defaults to the [timeouts] config for this tool, or 300."
+                    },
+                    "background": {
+                        "type": "boolean",
+                        "description": "Return a task_id immediately and run in the background. Poll with task_status."
                     }
                 },
                 "required": ["installable"]
@@ -589,6 +854,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "eval",
             description: "Evaluate a nix expression. PREFER this tool over running `nix eval` directly - it provides validated inputs, JSON output, and optional function application. The `expr` and `apply` parameters accept full Nix syntax including attribute sets ({ x = 1; }), string interpolation (${ }), let bindings, lambdas (x: x + 1), and all Nix operators. Shell metacharacters are safe here — expressions are passed directly to the nix process, not through a shell.",
+            annotations: ToolAnnotations::read_only("Evaluate Expression").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -619,6 +885,19 @@ pub fn list_tools() -> Vec<ToolInfo> {
                     "tail": {
                         "type": "integer",
                         "description": "Only return the last N lines of output."
+                    },
+                    "nix_options": nix_options_schema(),
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
+                    },
+                    "grep": {
+                        "type": "string",
+                        "description": "Keep only lines matching this regex, prefixed with their line numbers, before head/tail are applied. The match count is reported in truncation_info."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "description": "Lines of context around each grep match. Defaults to 0."
                     }
                 }
             }),
@@ -626,6 +905,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "fh_search",
             description: "Search FlakeHub for flakes matching a query. Agents MUST use this tool over running `fh search` directly - it provides structured JSON output.",
+            annotations: ToolAnnotations::read_only("Search FlakeHub").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -652,6 +932,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "fh_add",
             description: "Add a flake input to your flake.nix from FlakeHub. Agents MUST use this tool over running `fh add` directly - it provides validated inputs and proper error handling.",
+            annotations: ToolAnnotations::destructive("Add FlakeHub Input").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -674,6 +955,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "fh_list_flakes",
             description: "List public flakes on FlakeHub. Agents MUST use this tool over running `fh list` directly - it provides structured JSON output.",
+            annotations: ToolAnnotations::read_only("List FlakeHub Flakes").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -691,6 +973,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "fh_list_releases",
             description: "List all releases for a specific flake on FlakeHub. Agents MUST use this tool over running `fh list releases` directly - it provides structured JSON output.",
+            annotations: ToolAnnotations::read_only("List FlakeHub Releases").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -713,6 +996,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "fh_list_versions",
             description: "List versions matching a constraint for a flake on FlakeHub. Agents MUST use this tool over running `fh list versions` directly - it provides structured JSON output.",
+            annotations: ToolAnnotations::read_only("List FlakeHub Versions").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -739,6 +1023,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "fh_resolve",
             description: "Resolve a FlakeHub flake reference to a store path. Agents MUST use this tool over running `fh resolve` directly - it provides validated inputs and structured output.",
+            annotations: ToolAnnotations::read_only("Resolve FlakeHub Reference").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -754,6 +1039,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "cachix_push",
             description: "Push store paths to a Cachix binary cache. Requires CACHIX_AUTH_TOKEN env var or config in ~/.config/nix-mcp-server/config.toml.",
+            annotations: ToolAnnotations::writes("Push to Cachix").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -765,6 +1051,14 @@ pub fn list_tools() -> Vec<ToolInfo> {
                         "type": "array",
                         "items": { "type": "string" },
                         "description": "Nix store paths to push (e.g., '/nix/store/...-hello')."
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": "Kill the command after this many seconds. Defaults to the [timeouts] config for this tool, or 300."
+                    },
+                    "background": {
+                        "type": "boolean",
+                        "description": "Return a task_id immediately and run in the background. Poll with task_status."
                     }
                 },
                 "required": ["store_paths"]
@@ -773,6 +1067,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "cachix_use",
             description: "Configure Nix to use a Cachix binary cache as a substituter.",
+            annotations: ToolAnnotations::writes("Use Cachix Cache").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -787,6 +1082,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "cachix_status",
             description: "Check Cachix authentication status.",
+            annotations: ToolAnnotations::read_only("Show Cachix Status").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {}
@@ -796,6 +1092,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "fh_status",
             description: "Check FlakeHub login and cache status.",
+            annotations: ToolAnnotations::read_only("Show FlakeHub Status").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {}
@@ -804,6 +1101,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "fh_fetch",
             description: "Fetch a flake output from FlakeHub cache and create a GC root symlink.",
+            annotations: ToolAnnotations::destructive("Fetch from FlakeHub").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -822,6 +1120,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "fh_login",
             description: "Initiate FlakeHub OAuth login flow. Opens browser for authentication.",
+            annotations: ToolAnnotations::destructive("Log In to FlakeHub").open_world(),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -835,7 +1134,8 @@ pub fn list_tools() -> Vec<ToolInfo> {
         // Background task tools
         ToolInfo {
             name: "task_status",
-            description: "Check status of background tasks. If no task_id provided, lists all tasks.",
+            description: "Check status of background tasks. If no task_id provided, lists all tasks. Tasks from before a server restart are kept; ones that were still running are reported as failed, since their output and result went to the old server.",
+            annotations: ToolAnnotations::read_only("Show Task Status"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -846,10 +1146,119 @@ pub fn list_tools() -> Vec<ToolInfo> {
                 }
             }),
         },
+        ToolInfo {
+            name: "task_output",
+            description: "Page through the live stdout/stderr of a background task. Pass back next_offset to follow a running task.",
+            annotations: ToolAnnotations::read_only("Read Task Output"),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "task_id": {
+                        "type": "string",
+                        "description": "Task ID returned when the task was started."
+                    },
+                    "stream": {
+                        "type": "string",
+                        "enum": ["stdout", "stderr"],
+                        "description": "Which stream to read. Defaults to 'stderr', where nix writes build logs."
+                    },
+                    "offset": {
+                        "type": "integer",
+                        "description": "Skip the first N bytes of the stream, e.g. the next_offset of the previous page. Defaults to 0."
+                    },
+                    "head": {
+                        "type": "integer",
+                        "description": "Return only the first N lines after the offset."
+                    },
+                    "tail": {
+                        "type": "integer",
+                        "description": "Return only the last N lines."
+                    },
+                    "max_bytes": {
+                        "type": "integer",
+                        "description": "Maximum output size in bytes. Defaults to the configured limit."
+                    },
+                    "max_lines": {
+                        "type": "integer",
+                        "description": "Maximum lines to return."
+                    },
+                    "normalize": {
+                        "type": "boolean",
+                        "description": "Strip ANSI codes, resolve carriage-return progress bars and collapse runs of nix progress lines before limiting. Defaults to true; set false for the exact bytes."
+                    },
+                    "grep": {
+                        "type": "string",
+                        "description": "Keep only lines matching this regex, prefixed with their line numbers, before head/tail are applied. The match count is reported in truncation_info."
+                    },
+                    "context": {
+                        "type": "integer",
+                        "description": "Lines of context around each grep match. Defaults to 0."
+                    }
+                },
+                "required": ["task_id"]
+            }),
+        },
+        ToolInfo {
+            name: "task_cancel",
+            description: "Cancel a running background task. Kills its command and marks the task failed.",
+            annotations: ToolAnnotations::destructive("Cancel Task"),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "task_id": {
+                        "type": "string",
+                        "description": "Task ID to cancel."
+                    }
+                },
+                "required": ["task_id"]
+            }),
+        },
+        ToolInfo {
+            name: "task_result",
+            description: "Get the full structured result of a finished background task, as the tool would have returned it when called directly.",
+            annotations: ToolAnnotations::read_only("Get Task Result"),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "task_id": {
+                        "type": "string",
+                        "description": "Task ID of a finished task."
+                    }
+                },
+                "required": ["task_id"]
+            }),
+        },
+        ToolInfo {
+            name: "audit_query",
+            description: "Read recent entries of the audit log, newest first. Each entry records a tool call with its redacted arguments, the exact nix/fh/cachix commands it ran, their exit codes, duration and truncation stats.",
+            annotations: ToolAnnotations::read_only("Query Audit Log"),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "tool": {
+                        "type": "string",
+                        "description": "Only return calls to this tool."
+                    },
+                    "since": {
+                        "type": "integer",
+                        "description": "Only return calls that finished at or after this unix timestamp in seconds."
+                    },
+                    "failed_only": {
+                        "type": "boolean",
+                        "description": "Only return calls that failed or returned success: false. Defaults to false."
+                    },
+                    "limit": {
+                        "type": "integer",
+                        "description": "Maximum entries to return. Defaults to 20."
+                    }
+                }
+            }),
+        },
         // nil LSP tools
         ToolInfo {
             name: "nil_diagnostics",
             description: "Get Nix language diagnostics (errors, warnings, undefined names) for a file using the nil language server.",
+            annotations: ToolAnnotations::read_only("Nix Diagnostics"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -872,6 +1281,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "nil_completions",
             description: "Get Nix code completions at a specific position using the nil language server.",
+            annotations: ToolAnnotations::read_only("Nix Completions"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -902,6 +1312,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "nil_hover",
             description: "Get hover information (documentation, type info) at a specific position using the nil language server.",
+            annotations: ToolAnnotations::read_only("Nix Hover Info"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -924,6 +1335,7 @@ pub fn list_tools() -> Vec<ToolInfo> {
         ToolInfo {
             name: "nil_definition",
             description: "Go to definition for a symbol at a specific position using the nil language server.",
+            annotations: ToolAnnotations::read_only("Go to Nix Definition"),
             input_schema: serde_json::json!({
                 "type": "object",
                 "properties": {
@@ -946,16 +1358,137 @@ pub fn list_tools() -> Vec<ToolInfo> {
     ]
 }
 
-#[derive(Debug, Deserialize, Default)]
+/// JSON schema of the structured result a tool returns, for `outputSchema`
+/// in `tools/list`.
+///
+/// With `background` the schema also allows the [`TaskStarted`] a call with
+/// `background: true` returns. Tools whose result has no fixed shape, like
+/// `task_status`, have none.
+pub fn output_schema(tool: &str, background: bool) -> Option<serde_json::Value> {
+    let schema = match tool {
+        "build" => result_schema::<build::NixBuildResult>(background),
+        "flake_show" => result_schema::<flake::NixFlakeShowResult>(background),
+        "flake_check" => result_schema::<flake::NixFlakeCheckResult>(background),
+        "flake_metadata" => result_schema::<flake::NixFlakeMetadataResult>(background),
+        "flake_update" => result_schema::<flake::NixFlakeUpdateResult>(background),
+        "flake_lock" => result_schema::<flake::NixFlakeLockResult>(background),
+        "flake_init" => result_schema::<flake::NixFlakeInitResult>(background),
+        "run" => result_schema::<run::NixRunResult>(background),
+        "develop_run" => result_schema::<run::NixDevelopRunResult>(background),
+        "log" => result_schema::<log::NixLogResult>(background),
+        "eval" => result_schema::<eval::NixEvalResult>(background),
+        "search" => result_schema::<search::NixSearchResult>(background),
+        "store_path_info" => result_schema::<store::NixStorePathInfoResult>(background),
+        "store_gc" => result_schema::<store::NixStoreGcResult>(background),
+        "store_ls" => result_schema::<store::NixStoreLsResult>(background),
+        "store_cat" => result_schema::<store::NixStoreCatResult>(background),
+        "copy" => result_schema::<store::NixCopyResult>(background),
+        "derivation_show" => result_schema::<derivation::NixDerivationShowResult>(background),
+        "hash_path" | "hash_file" => result_schema::<hash::NixHashResult>(background),
+        "cachix_push" => result_schema::<cachix::CachixPushResult>(background),
+        "cachix_use" => result_schema::<cachix::CachixUseResult>(background),
+        "cachix_status" => result_schema::<cachix::CachixStatusResult>(background),
+        "fh_search" => result_schema::<flakehub::FhSearchResult>(background),
+        "fh_add" => result_schema::<flakehub::FhAddResult>(background),
+        "fh_list_flakes" | "fh_list_releases" | "fh_list_versions" => {
+            result_schema::<flakehub::FhListResult>(background)
+        }
+        "fh_resolve" => result_schema::<flakehub::FhResolveResult>(background),
+        "fh_status" => result_schema::<flakehub::FhStatusResult>(background),
+        "fh_fetch" => result_schema::<flakehub::FhFetchResult>(background),
+        "fh_login" => result_schema::<flakehub::FhLoginResult>(background),
+        "nix_info" => result_schema::<NixInfo>(background),
+        "task_output" => result_schema::<task::TaskOutputResult>(background),
+        "audit_query" => result_schema::<audit::AuditQueryResult>(background),
+        "nil_diagnostics" => result_schema::<lsp::DiagnosticsResult>(background),
+        "nil_completions" => result_schema::<lsp::CompletionsResult>(background),
+        "nil_hover" => result_schema::<lsp::HoverInfoResult>(background),
+        "nil_definition" => result_schema::<lsp::DefinitionResult>(background),
+        _ => return None,
+    };
+    Some(schema)
+}
+
+fn result_schema<T: JsonSchema>(background: bool) -> serde_json::Value {
+    // Draft 7 is what most MCP clients validate against
+    let mut generator = SchemaSettings::draft07().into_generator();
+    if !background {
+        return generator.into_root_schema_for::<T>().to_value();
+    }
+    let result = generator.subschema_for::<T>();
+    let started = generator.subschema_for::<TaskStarted>();
+    serde_json::json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "type": "object",
+        "anyOf": [result, started],
+        "definitions": generator.take_definitions(true),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixBuildParams {
     pub installable: Option<String>,
     pub print_build_logs: Option<bool>,
     pub flake_dir: Option<String>,
     pub max_log_bytes: Option<usize>,
     pub log_tail: Option<usize>,
+    pub mode: Option<OutputMode>,
+    pub grep: Option<String>,
+    pub context: Option<usize>,
+    pub normalize: Option<bool>,
+    pub nix_options: Option<BTreeMap<String, NixOptionValue>>,
+}
+
+/// A `nix_options` value; nix takes every setting as a string
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum NixOptionValue {
+    Bool(bool),
+    Int(u64),
+    String(String),
+}
+
+impl fmt::Display for NixOptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NixOptionValue::Bool(b) => write!(f, "{}", b),
+            NixOptionValue::Int(n) => write!(f, "{}", n),
+            NixOptionValue::String(s) => f.write_str(s),
+        }
+    }
+}
+
+/// Input schema for the `nix_options` parameter, listing the built-in allow-list
+fn nix_options_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "description": format!(
+            "Nix settings passed as `--option <name> <value>`, e.g. {{\"max-jobs\": 4, \"keep-going\": true}}. Allowed: {}, plus any in the [nix_options] config.",
+            ALLOWED_NIX_OPTIONS.join(", ")
+        ),
+        "additionalProperties": {"type": ["string", "integer", "boolean"]}
+    })
+}
+
+/// Validate `nix_options` and turn them into `--option <name> <value>` args
+pub(crate) fn nix_option_args(
+    options: Option<&BTreeMap<String, NixOptionValue>>,
+) -> Result<Vec<String>, String> {
+    let Some(options) = options.filter(|o| !o.is_empty()) else {
+        return Ok(Vec::new());
+    };
+
+    let extra_allowed = load_config().nix_options.allowed;
+    let mut args = Vec::with_capacity(options.len() * 3);
+    for (name, value) in options {
+        let value = value.to_string();
+        validate_nix_option(name, &value, &extra_allowed).map_err(|e| e.to_string())?;
+        args.extend(["--option".to_string(), name.clone(), value]);
+    }
+    Ok(args)
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixFlakeShowParams {
     pub flake_ref: Option<String>,
     pub all_systems: Option<bool>,
@@ -963,9 +1496,10 @@ pub struct NixFlakeShowParams {
     pub max_bytes: Option<usize>,
     pub head: Option<usize>,
     pub tail: Option<usize>,
+    pub normalize: Option<bool>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixFlakeCheckParams {
     pub flake_ref: Option<String>,
     pub keep_going: Option<bool>,
@@ -973,18 +1507,23 @@ pub struct NixFlakeCheckParams {
     pub max_bytes: Option<usize>,
     pub head: Option<usize>,
     pub tail: Option<usize>,
+    pub mode: Option<OutputMode>,
+    pub grep: Option<String>,
+    pub context: Option<usize>,
+    pub normalize: Option<bool>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixFlakeMetadataParams {
     pub flake_ref: Option<String>,
     pub flake_dir: Option<String>,
     pub max_bytes: Option<usize>,
     pub head: Option<usize>,
     pub tail: Option<usize>,
+    pub normalize: Option<bool>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixFlakeUpdateParams {
     pub flake_ref: Option<String>,
     pub inputs: Option<Vec<String>>,
@@ -992,9 +1531,13 @@ pub struct NixFlakeUpdateParams {
     pub max_bytes: Option<usize>,
     pub head: Option<usize>,
     pub tail: Option<usize>,
+    pub grep: Option<String>,
+    pub context: Option<usize>,
+    pub normalize: Option<bool>,
+    pub raw_output: Option<bool>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixFlakeLockParams {
     pub flake_ref: Option<String>,
     pub update_inputs: Option<Vec<String>>,
@@ -1003,28 +1546,32 @@ pub struct NixFlakeLockParams {
     pub max_bytes: Option<usize>,
     pub head: Option<usize>,
     pub tail: Option<usize>,
+    pub grep: Option<String>,
+    pub context: Option<usize>,
+    pub normalize: Option<bool>,
+    pub raw_output: Option<bool>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixFlakeInitParams {
     pub template: Option<String>,
     pub flake_dir: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixRunParams {
     pub installable: Option<String>,
     pub args: Option<Vec<String>>,
     pub flake_dir: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CommandEntry {
     pub command: String,
     pub args: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NixDevelopRunParams {
     pub flake_ref: Option<String>,
     pub commands: Vec<CommandEntry>,
@@ -1032,17 +1579,26 @@ pub struct NixDevelopRunParams {
     pub max_bytes: Option<usize>,
     pub head: Option<usize>,
     pub tail: Option<usize>,
+    pub mode: Option<OutputMode>,
+    pub grep: Option<String>,
+    pub context: Option<usize>,
+    pub normalize: Option<bool>,
+    pub nix_options: Option<BTreeMap<String, NixOptionValue>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NixLogParams {
     pub installable: String,
     pub head: Option<usize>,
     pub tail: Option<usize>,
     pub max_bytes: Option<usize>,
+    pub mode: Option<OutputMode>,
+    pub grep: Option<String>,
+    pub context: Option<usize>,
+    pub normalize: Option<bool>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixEvalParams {
     pub installable: Option<String>,
     pub expr: Option<String>,
@@ -1051,9 +1607,13 @@ pub struct NixEvalParams {
     pub max_bytes: Option<usize>,
     pub head: Option<usize>,
     pub tail: Option<usize>,
+    pub grep: Option<String>,
+    pub context: Option<usize>,
+    pub normalize: Option<bool>,
+    pub nix_options: Option<BTreeMap<String, NixOptionValue>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NixSearchParams {
     pub query: String,
     pub flake_ref: Option<String>,
@@ -1062,7 +1622,7 @@ pub struct NixSearchParams {
     pub offset: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NixStorePathInfoParams {
     pub path: String,
     pub closure: Option<bool>,
@@ -1071,13 +1631,13 @@ pub struct NixStorePathInfoParams {
     pub closure_offset: Option<usize>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixStoreGcParams {
     pub dry_run: Option<bool>,
     pub max_freed: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NixStoreLsParams {
     pub path: String,
     pub long: Option<bool>,
@@ -1085,14 +1645,16 @@ pub struct NixStoreLsParams {
     pub limit: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NixStoreCatParams {
     pub path: String,
     pub offset: Option<usize>,
     pub limit: Option<usize>,
+    pub grep: Option<String>,
+    pub context: Option<usize>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NixDerivationShowParams {
     pub installable: Option<String>,
     pub recursive: Option<bool>,
@@ -1102,7 +1664,7 @@ pub struct NixDerivationShowParams {
     pub inputs_offset: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NixHashPathParams {
     pub path: String,
     pub hash_type: Option<String>,
@@ -1110,7 +1672,7 @@ pub struct NixHashPathParams {
     pub sri: Option<bool>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NixHashFileParams {
     pub path: String,
     pub hash_type: Option<String>,
@@ -1118,14 +1680,14 @@ pub struct NixHashFileParams {
     pub sri: Option<bool>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NixCopyParams {
     pub installable: String,
     pub to: Option<String>,
     pub from: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FhSearchParams {
     pub query: String,
     pub max_results: Option<usize>,
@@ -1133,27 +1695,27 @@ pub struct FhSearchParams {
     pub limit: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FhAddParams {
     pub input_ref: String,
     pub flake_path: Option<String>,
     pub input_name: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct FhListFlakesParams {
     pub limit: Option<usize>,
     pub offset: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FhListReleasesParams {
     pub flake: String,
     pub limit: Option<usize>,
     pub offset: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FhListVersionsParams {
     pub flake: String,
     pub version_constraint: String,
@@ -1161,56 +1723,83 @@ pub struct FhListVersionsParams {
     pub offset: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FhResolveParams {
     pub flake_ref: String,
 }
 
 // Cachix params
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct CachixPushParams {
     pub cache_name: Option<String>,
     pub store_paths: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CachixUseParams {
     pub cache_name: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct CachixStatusParams {}
 
 // FlakeHub cache params
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct FhStatusParams {}
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FhFetchParams {
     pub flake_ref: String,
     pub target_link: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct FhLoginParams {
     pub token_file: Option<String>,
 }
 
 // Background task params
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct TaskStatusParams {
     pub task_id: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct TaskOutputParams {
+    pub task_id: String,
+    pub stream: Option<Stream>,
+    pub offset: Option<usize>,
+    pub head: Option<usize>,
+    pub tail: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub max_lines: Option<usize>,
+    pub grep: Option<String>,
+    pub context: Option<usize>,
+    pub normalize: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskIdParams {
+    pub task_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AuditQueryParams {
+    pub tool: Option<String>,
+    pub since: Option<u64>,
+    pub failed_only: Option<bool>,
+    pub limit: Option<usize>,
+}
+
 // nil LSP params
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NilDiagnosticsParams {
     pub file_path: String,
     pub offset: Option<usize>,
     pub limit: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NilCompletionsParams {
     pub file_path: String,
     pub line: u32,
@@ -1219,14 +1808,14 @@ pub struct NilCompletionsParams {
     pub limit: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NilHoverParams {
     pub file_path: String,
     pub line: u32,
     pub character: u32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NilDefinitionParams {
     pub file_path: String,
     pub line: u32,
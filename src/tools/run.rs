@@ -1,13 +1,14 @@
 use crate::nix_runner::run_nix_command_in_dir;
 use crate::output::{limit_stderr, limit_text_output, OutputLimits, TruncationInfo};
-use crate::tools::{NixDevelopRunParams, NixRunParams};
+use crate::tools::{nix_option_args, NixDevelopRunParams, NixRunParams};
 use crate::validators::{
     validate_args, validate_flake_ref, validate_installable, validate_no_shell_metacharacters,
-    validate_path,
 };
+use crate::workspace::{check_flake_ref_path, resolve_flake_dir};
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixRunResult {
     pub success: bool,
     pub stdout: String,
@@ -19,7 +20,7 @@ pub struct NixRunResult {
     pub truncation_info: Option<TruncationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct CommandResult {
     pub command: String,
     pub success: bool,
@@ -32,7 +33,7 @@ pub struct CommandResult {
     pub truncation_info: Option<TruncationInfo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixDevelopRunResult {
     pub success: bool,
     pub results: Vec<CommandResult>,
@@ -46,12 +47,11 @@ pub async fn nix_run(params: NixRunParams) -> Result<NixRunResult, String> {
     let installable = params
         .installable
         .unwrap_or_else(|| ".#default".to_string());
-    validate_installable(&installable).map_err(|e| e.to_string())?;
+    let parsed = validate_installable(&installable).map_err(|e| e.to_string())?;
 
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
+    check_flake_ref_path(&parsed.flake_ref, flake_dir).await?;
 
     if let Some(ref args) = params.args {
         validate_args(args).map_err(|e| e.to_string())?;
@@ -85,22 +85,27 @@ pub async fn nix_run(params: NixRunParams) -> Result<NixRunResult, String> {
 
 pub async fn nix_develop_run(params: NixDevelopRunParams) -> Result<NixDevelopRunResult, String> {
     let flake_ref = params.flake_ref.unwrap_or_else(|| ".".to_string());
-    validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
+    let parsed = validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
 
-    let flake_dir = params.flake_dir.as_deref();
-    if let Some(dir) = flake_dir {
-        validate_path(dir).map_err(|e| e.to_string())?;
-    }
+    let flake_dir = resolve_flake_dir(params.flake_dir.as_deref()).await?;
+    let flake_dir = flake_dir.as_deref();
+    check_flake_ref_path(&parsed, flake_dir).await?;
 
     if params.commands.is_empty() {
         return Err("commands array must not be empty".to_string());
     }
 
+    let option_args = nix_option_args(params.nix_options.as_ref())?;
+
     let limits = OutputLimits {
         head: params.head,
         tail: params.tail,
         max_bytes: params.max_bytes,
         max_lines: None,
+        mode: params.mode.unwrap_or_default(),
+        grep: params.grep.clone(),
+        context: params.context,
+        normalize: params.normalize,
     };
 
     let mut results = Vec::new();
@@ -114,7 +119,9 @@ pub async fn nix_develop_run(params: NixDevelopRunParams) -> Result<NixDevelopRu
             validate_args(args).map_err(|e| e.to_string())?;
         }
 
-        let mut nix_args: Vec<&str> = vec!["develop", &flake_ref, "-c", &entry.command];
+        let mut nix_args: Vec<&str> = vec!["develop"];
+        nix_args.extend(option_args.iter().map(String::as_str));
+        nix_args.extend([flake_ref.as_str(), "-c", &entry.command]);
 
         let user_args: Vec<&str> = entry
             .args
@@ -177,7 +184,8 @@ pub async fn nix_develop_run(params: NixDevelopRunParams) -> Result<NixDevelopRu
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tools::{CommandEntry, NixDevelopRunParams};
+    use crate::tools::{CommandEntry, NixDevelopRunParams, NixOptionValue};
+    use std::collections::BTreeMap;
 
     #[tokio::test]
     async fn test_develop_run_rejects_empty_commands() {
@@ -188,6 +196,11 @@ mod tests {
             max_bytes: None,
             head: None,
             tail: None,
+            mode: None,
+            grep: None,
+            context: None,
+            normalize: None,
+            nix_options: None,
         };
         let result = nix_develop_run(params).await;
         assert!(result.is_err());
@@ -206,6 +219,11 @@ mod tests {
             max_bytes: None,
             head: None,
             tail: None,
+            mode: None,
+            grep: None,
+            context: None,
+            normalize: None,
+            nix_options: None,
         };
         let result = nix_develop_run(params).await;
         assert!(result.is_err());
@@ -224,6 +242,11 @@ mod tests {
             max_bytes: None,
             head: None,
             tail: None,
+            mode: None,
+            grep: None,
+            context: None,
+            normalize: None,
+            nix_options: None,
         };
         let result = nix_develop_run(params).await;
         assert!(result.is_err());
@@ -242,6 +265,11 @@ mod tests {
             max_bytes: None,
             head: None,
             tail: None,
+            mode: None,
+            grep: None,
+            context: None,
+            normalize: None,
+            nix_options: None,
         };
         let result = nix_develop_run(params).await;
         assert!(result.is_err());
@@ -260,9 +288,40 @@ mod tests {
             max_bytes: None,
             head: None,
             tail: None,
+            mode: None,
+            grep: None,
+            context: None,
+            normalize: None,
+            nix_options: None,
         };
         let result = nix_develop_run(params).await;
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("invalid path"));
     }
+
+    #[tokio::test]
+    async fn test_develop_run_rejects_disallowed_nix_option() {
+        let params = NixDevelopRunParams {
+            flake_ref: None,
+            commands: vec![CommandEntry {
+                command: "echo".to_string(),
+                args: None,
+            }],
+            flake_dir: None,
+            max_bytes: None,
+            head: None,
+            tail: None,
+            mode: None,
+            grep: None,
+            context: None,
+            normalize: None,
+            nix_options: Some(BTreeMap::from([(
+                "post-build-hook".to_string(),
+                NixOptionValue::String("/tmp/hook".to_string()),
+            )])),
+        };
+        let result = nix_develop_run(params).await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("nix option not allowed"));
+    }
 }
@@ -2,9 +2,11 @@ use crate::nix_runner::run_nix_command;
 use crate::output::{limit_stderr, OutputLimitsConfig, PaginationInfo, TruncationInfo};
 use crate::tools::NixSearchParams;
 use crate::validators::{validate_flake_ref, validate_no_shell_metacharacters};
+use crate::workspace::check_flake_ref_path;
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixSearchResult {
     pub success: bool,
     pub packages: serde_json::Value,
@@ -19,7 +21,8 @@ pub struct NixSearchResult {
 
 pub async fn nix_search(params: NixSearchParams) -> Result<NixSearchResult, String> {
     let flake_ref = params.flake_ref.unwrap_or_else(|| "nixpkgs".to_string());
-    validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
+    let parsed = validate_flake_ref(&flake_ref).map_err(|e| e.to_string())?;
+    check_flake_ref_path(&parsed, None).await?;
 
     validate_no_shell_metacharacters(&params.query).map_err(|e| e.to_string())?;
 
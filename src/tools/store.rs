@@ -1,10 +1,13 @@
-use crate::nix_runner::run_nix_command;
-use crate::output::{limit_stderr, PaginationInfo, TruncationInfo};
+use crate::nix_runner::{normalize_path_info, run_nix_command};
+use crate::output::{grep_lines, limit_stderr, PaginationInfo, TruncationInfo};
 use crate::tools::{NixCopyParams, NixStoreCatParams, NixStoreLsParams, NixStoreGcParams, NixStorePathInfoParams};
 use crate::validators::{validate_flake_ref, validate_no_shell_metacharacters, validate_store_path, validate_store_subpath};
+use crate::workspace::check_flake_ref_path;
+use regex::Regex;
+use schemars::JsonSchema;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixStorePathInfoResult {
     pub success: bool,
     pub path_info: serde_json::Value,
@@ -27,7 +30,8 @@ pub async fn nix_store_path_info(
     if path.starts_with("/nix/store/") {
         validate_store_path(path).map_err(|e| e.to_string())?;
     } else {
-        validate_flake_ref(path).map_err(|e| e.to_string())?;
+        let parsed = validate_flake_ref(path).map_err(|e| e.to_string())?;
+        check_flake_ref_path(&parsed, None).await?;
     }
 
     let use_closure = params.closure.unwrap_or(false);
@@ -58,6 +62,7 @@ pub async fn nix_store_path_info(
 
     let parsed: serde_json::Value =
         serde_json::from_str(&result.stdout).unwrap_or(serde_json::Value::Null);
+    let parsed = normalize_path_info(parsed);
 
     // Apply pagination if closure was requested and we have an array
     let (path_info, pagination) = if use_closure {
@@ -102,7 +107,7 @@ pub async fn nix_store_path_info(
     })
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixStoreGcResult {
     pub success: bool,
     pub stdout: String,
@@ -141,7 +146,7 @@ pub async fn nix_store_gc(params: NixStoreGcParams) -> Result<NixStoreGcResult,
     })
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixCopyResult {
     pub success: bool,
     pub stdout: String,
@@ -176,7 +181,8 @@ pub async fn nix_copy(params: NixCopyParams) -> Result<NixCopyResult, String> {
     if path.starts_with("/nix/store/") {
         validate_store_path(path).map_err(|e| e.to_string())?;
     } else {
-        validate_flake_ref(path).map_err(|e| e.to_string())?;
+        let parsed = validate_flake_ref(path).map_err(|e| e.to_string())?;
+        check_flake_ref_path(&parsed, None).await?;
     }
 
     args.push(path);
@@ -208,14 +214,14 @@ async fn resolve_and_validate_store_path(path: &str) -> Result<std::path::PathBu
     Ok(canonical)
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixStoreLsEntry {
     pub name: String,
     pub entry_type: String,
     pub size: Option<u64>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixStoreLsResult {
     pub path: String,
     pub entries: Vec<NixStoreLsEntry>,
@@ -302,12 +308,15 @@ pub async fn nix_store_ls(params: NixStoreLsParams) -> Result<NixStoreLsResult,
     })
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct NixStoreCatResult {
     pub path: String,
     pub content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pagination: Option<PaginationInfo>,
+    /// Lines matching `grep`, before pagination
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_count: Option<usize>,
 }
 
 pub async fn nix_store_cat(params: NixStoreCatParams) -> Result<NixStoreCatResult, String> {
@@ -317,12 +326,24 @@ pub async fn nix_store_cat(params: NixStoreCatParams) -> Result<NixStoreCatResul
         .await
         .map_err(|e| format!("Failed to read file '{}': {}", canonical.display(), e))?;
 
-    let lines: Vec<&str> = content.lines().collect();
+    let (lines, match_count): (Vec<String>, _) = match &params.grep {
+        Some(pattern) => {
+            let re = Regex::new(pattern).map_err(|e| format!("Invalid grep pattern: {}", e))?;
+            let matches = grep_lines(&content, &re, params.context.unwrap_or(0));
+            (matches.lines, Some(matches.match_count))
+        }
+        None => (content.lines().map(str::to_string).collect(), None),
+    };
     let total = lines.len();
     let offset = params.offset.unwrap_or(0);
     let limit = params.limit.unwrap_or(total);
 
-    let paginated: Vec<&str> = lines.iter().skip(offset).take(limit).copied().collect();
+    let paginated: Vec<&str> = lines
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(String::as_str)
+        .collect();
     let kept_count = paginated.len();
     let has_more = offset + kept_count < total;
 
@@ -341,5 +362,6 @@ pub async fn nix_store_cat(params: NixStoreCatParams) -> Result<NixStoreCatResul
         path: canonical.to_string_lossy().to_string(),
         content: paginated.join("\n"),
         pagination,
+        match_count,
     })
 }
@@ -0,0 +1,244 @@
+use crate::backend::Stream;
+use crate::background::{self, TaskStatus};
+use crate::config::load_config;
+use crate::output::{limit_text_output, OutputLimits, TruncationInfo};
+use crate::tools::TaskOutputParams;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::ops::Range;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TaskOutputResult {
+    pub task_id: String,
+    pub status: TaskStatus,
+    pub stream: Stream,
+    pub output: String,
+    /// Lines the stream holds so far
+    pub total_lines: usize,
+    /// Bytes the stream holds so far
+    pub total_bytes: usize,
+    /// Byte offset to pass next time to continue after this page
+    pub next_offset: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncation_info: Option<TruncationInfo>,
+}
+
+/// Page through the live output of a background task.
+///
+/// `offset` skips that many bytes from the start of the stream. The page is
+/// cut from the raw output before grep and normalization, so `next_offset`
+/// is exactly where it ended even when `max_bytes` splits a line, and a
+/// client can follow a running task by passing it back.
+pub fn task_output(params: TaskOutputParams) -> Result<TaskOutputResult, String> {
+    let stream = params.stream.unwrap_or(Stream::Stderr);
+    let max_bytes = params
+        .max_bytes
+        .unwrap_or_else(|| load_config().output_limits.default_max_bytes());
+    let page = background::task_output(
+        &params.task_id,
+        stream,
+        params.offset.unwrap_or(0),
+        max_bytes,
+        params.tail.is_some(),
+    )
+    .ok_or_else(|| format!("Task not found: {}", params.task_id))?;
+
+    let lines = match (params.head, params.max_lines) {
+        (Some(head), Some(max)) => Some(head.min(max)),
+        (head, max) => head.or(max),
+    };
+    let range = page_range(&page.text, params.tail, lines, max_bytes);
+    let raw = &page.text[range.clone()];
+
+    let limits = OutputLimits {
+        grep: params.grep.clone(),
+        context: params.context,
+        normalize: params.normalize,
+        ..Default::default()
+    };
+    let limited = limit_text_output(raw.strip_suffix('\n').unwrap_or(raw), &limits);
+
+    // A tail page read back from the log starts after the requested offset
+    let offset = params.offset.unwrap_or(0);
+    let skipped = page.first_byte > offset;
+    let paged = skipped || range.start > 0 || range.end < page.text.len();
+    let truncation_info = limited.truncation_info.or_else(|| {
+        paged.then(|| TruncationInfo {
+            original_bytes: page.total_bytes.saturating_sub(offset),
+            original_lines: (!skipped).then(|| page.text.lines().count()),
+            original_items: None,
+            kept_bytes: limited.content.len(),
+            kept_lines: Some(limited.content.lines().count()),
+            kept_items: None,
+            position: Some(
+                if skipped || range.start > 0 {
+                    "tail"
+                } else {
+                    "head"
+                }
+                .to_string(),
+            ),
+            kept_sections: None,
+            match_count: None,
+        })
+    });
+
+    Ok(TaskOutputResult {
+        task_id: params.task_id,
+        status: page.status,
+        stream,
+        output: limited.content,
+        total_lines: page.total_lines,
+        total_bytes: page.total_bytes,
+        next_offset: page.first_byte + range.end,
+        truncated: (limited.truncated || paged).then_some(true),
+        truncation_info,
+    })
+}
+
+/// Byte range of `text` to return: its last `tail` lines or its first
+/// `lines` lines, cut to `max_bytes` at a line break if there is one
+fn page_range(
+    text: &str,
+    tail: Option<usize>,
+    lines: Option<usize>,
+    max_bytes: usize,
+) -> Range<usize> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .filter(|&i| i < text.len())
+        .collect();
+
+    let mut range = 0..text.len();
+    if let Some(tail) = tail {
+        if tail < line_starts.len() {
+            range.start = line_starts[line_starts.len() - tail];
+        }
+    } else if let Some(lines) = lines {
+        if lines < line_starts.len() {
+            range.end = line_starts[lines];
+        }
+    }
+
+    if range.len() > max_bytes {
+        let cut = range.start + max_bytes;
+        range.end = match text[range.start..cut].rfind('\n') {
+            Some(newline) => range.start + newline + 1,
+            None => (range.start..=cut)
+                .rev()
+                .find(|&i| text.is_char_boundary(i))
+                .unwrap_or(range.start),
+        };
+    }
+    range
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::{append_output, generate_task_id, register_task, remove_task};
+    use crate::context::CancelToken;
+
+    fn params(task_id: &str) -> TaskOutputParams {
+        TaskOutputParams {
+            task_id: task_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_task_output_pages_with_offset() {
+        let id = generate_task_id();
+        register_task(id.clone(), "build .#hello".to_string(), CancelToken::new());
+        for i in 0..5 {
+            append_output(&id, Stream::Stderr, &format!("line {}", i));
+        }
+        append_output(&id, Stream::Stdout, "/nix/store/abc-hello");
+
+        let first = task_output(TaskOutputParams {
+            max_lines: Some(2),
+            ..params(&id)
+        })
+        .unwrap();
+        assert_eq!(first.output, "line 0\nline 1");
+        assert_eq!(first.total_lines, 5);
+        assert_eq!(first.total_bytes, 35);
+        assert_eq!(first.next_offset, 14);
+
+        let rest = task_output(TaskOutputParams {
+            offset: Some(first.next_offset),
+            ..params(&id)
+        })
+        .unwrap();
+        assert_eq!(rest.output, "line 2\nline 3\nline 4");
+        assert_eq!(rest.next_offset, 35);
+        assert!(rest.truncated.is_none());
+
+        let tail = task_output(TaskOutputParams {
+            tail: Some(1),
+            ..params(&id)
+        })
+        .unwrap();
+        assert_eq!(tail.output, "line 4");
+        assert_eq!(tail.next_offset, 35);
+
+        let stdout = task_output(TaskOutputParams {
+            stream: Some(Stream::Stdout),
+            ..params(&id)
+        })
+        .unwrap();
+        assert_eq!(stdout.output, "/nix/store/abc-hello");
+
+        remove_task(&id);
+        assert!(task_output(params(&id)).is_err());
+    }
+
+    #[test]
+    fn test_task_output_tail_reads_only_max_bytes() {
+        let id = generate_task_id();
+        register_task(id.clone(), "build .#hello".to_string(), CancelToken::new());
+        for i in 0..5 {
+            append_output(&id, Stream::Stderr, &format!("line {}", i));
+        }
+
+        let tail = task_output(TaskOutputParams {
+            tail: Some(3),
+            max_bytes: Some(10),
+            ..params(&id)
+        })
+        .unwrap();
+        assert_eq!(tail.output, "line 4");
+        assert_eq!(tail.next_offset, 35);
+        assert_eq!(tail.truncated, Some(true));
+
+        remove_task(&id);
+    }
+
+    #[test]
+    fn test_task_output_resumes_inside_a_long_line() {
+        let id = generate_task_id();
+        register_task(id.clone(), "build .#hello".to_string(), CancelToken::new());
+        append_output(&id, Stream::Stderr, "abcdefghij");
+        append_output(&id, Stream::Stderr, "done");
+
+        let mut output = String::new();
+        let mut offset = 0;
+        while offset < 16 {
+            let page = task_output(TaskOutputParams {
+                offset: Some(offset),
+                max_bytes: Some(4),
+                normalize: Some(false),
+                ..params(&id)
+            })
+            .unwrap();
+            assert!(page.next_offset > offset);
+            output.push_str(&page.output);
+            offset = page.next_offset;
+        }
+        assert_eq!(output, "abcdefghijdone");
+
+        remove_task(&id);
+    }
+}
@@ -1,11 +1,12 @@
+use crate::flake_ref::{FlakeRef, Installable};
 use regex::Regex;
 use std::sync::LazyLock;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ValidationError {
-    #[error("invalid flake reference: `{0}`")]
-    InvalidFlakeRef(String),
+    #[error("invalid flake reference: `{0}`: {1}")]
+    InvalidFlakeRef(String, String),
 
     #[error("shell metacharacters not allowed: `{0}`. Retry with the metacharacters removed — use separate array entries or tool parameters instead of shell operators")]
     ShellMetacharacters(String),
@@ -27,10 +28,34 @@ pub enum ValidationError {
 
     #[error("invalid path: `{0}`")]
     InvalidPath(String),
+
+    #[error("nix option not allowed: `{0}`. Allowed options: {1}")]
+    DisallowedNixOption(String, String),
+
+    #[error("invalid value for nix option `{0}`: `{1}`")]
+    InvalidNixOptionValue(String, String),
 }
 
-static FLAKE_REF_PATTERN: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9._\-/:#+]+$").unwrap());
+/// `nix --option` settings that tools accept in `nix_options`.
+///
+/// These only tune scheduling, caching and sandboxing. Settings that run
+/// arbitrary programs (`post-build-hook`, `build-hook`, `pre-build-hook`) or
+/// widen what evaluation may access are left out, as are the
+/// `trusted-public-keys` settings, which with `substituters` would let a
+/// caller make nix accept paths from a cache it signs. Deployments that need
+/// more can extend the list with `[nix_options] allowed` in config.toml.
+pub const ALLOWED_NIX_OPTIONS: &[&str] = &[
+    "max-jobs",
+    "cores",
+    "substituters",
+    "extra-substituters",
+    "sandbox",
+    "keep-going",
+    "keep-failed",
+    "fallback",
+    "connect-timeout",
+    "show-trace",
+];
 
 static ATTR_PATH_PATTERN: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9._\-]+$").unwrap());
@@ -52,22 +77,24 @@ static STORE_SUBPATH_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r"^/nix/store/[a-z0-9]{32}-[a-zA-Z0-9._\-]+(/[a-zA-Z0-9._\-]+)*$").unwrap()
 });
 
+// Nix option values: URLs, keys (name:base64=), numbers, booleans, space separated lists
+static NIX_OPTION_VALUE_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9._\-/:=+@, ]*$").unwrap());
+
 // File paths: no shell metacharacters, reasonable characters
 static PATH_PATTERN: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9._\-/~]+$").unwrap());
 
-pub fn validate_installable(installable: &str) -> Result<&str, ValidationError> {
-    if !FLAKE_REF_PATTERN.is_match(installable) {
-        return Err(ValidationError::InvalidFlakeRef(installable.to_string()));
-    }
-    Ok(installable)
+/// Parse an installable such as `nixpkgs#hello^out` into its parts
+pub fn validate_installable(installable: &str) -> Result<Installable, ValidationError> {
+    Installable::parse(installable)
+        .map_err(|reason| ValidationError::InvalidFlakeRef(installable.to_string(), reason))
 }
 
-pub fn validate_flake_ref(flake_ref: &str) -> Result<&str, ValidationError> {
-    if !FLAKE_REF_PATTERN.is_match(flake_ref) {
-        return Err(ValidationError::InvalidFlakeRef(flake_ref.to_string()));
-    }
-    Ok(flake_ref)
+/// Parse a flake reference. A trailing `#attr` is accepted, as several tools
+/// take either, but only the reference itself is returned.
+pub fn validate_flake_ref(flake_ref: &str) -> Result<FlakeRef, ValidationError> {
+    validate_installable(flake_ref).map(|installable| installable.flake_ref)
 }
 
 pub fn validate_attr_path(attr_path: &str) -> Result<&str, ValidationError> {
@@ -142,6 +169,31 @@ pub fn validate_path(path: &str) -> Result<&str, ValidationError> {
     Ok(path)
 }
 
+/// Validate a `--option <name> <value>` pair against [`ALLOWED_NIX_OPTIONS`]
+/// plus any options allowed in config.
+pub fn validate_nix_option<'a>(
+    name: &'a str,
+    value: &str,
+    extra_allowed: &[String],
+) -> Result<&'a str, ValidationError> {
+    let allowed = ALLOWED_NIX_OPTIONS.contains(&name) || extra_allowed.iter().any(|o| o == name);
+    if !allowed {
+        let mut all: Vec<&str> = ALLOWED_NIX_OPTIONS.to_vec();
+        all.extend(extra_allowed.iter().map(String::as_str));
+        return Err(ValidationError::DisallowedNixOption(
+            name.to_string(),
+            all.join(", "),
+        ));
+    }
+    if !NIX_OPTION_VALUE_PATTERN.is_match(value) {
+        return Err(ValidationError::InvalidNixOptionValue(
+            name.to_string(),
+            value.to_string(),
+        ));
+    }
+    Ok(name)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -152,6 +204,10 @@ mod tests {
         assert!(validate_installable("nixpkgs#hello").is_ok());
         assert!(validate_installable("github:NixOS/nixpkgs#hello").is_ok());
         assert!(validate_installable(".#packages.x86_64-linux.default").is_ok());
+        assert!(validate_installable("github:owner/repo?ref=v1.2&dir=sub").is_ok());
+        assert!(validate_installable("git+https://host/repo.git?rev=abc").is_ok());
+        assert!(validate_installable("tarball+https://host/main.tar.gz").is_ok());
+        assert!(validate_installable("path:./foo?narHash=sha256-abc=").is_ok());
     }
 
     #[test]
@@ -274,4 +330,47 @@ mod tests {
         assert!(validate_path("/path;injection").is_err());
         assert!(validate_path("/path$(cmd)").is_err());
     }
+
+    #[test]
+    fn test_nix_option_allow_list() {
+        assert!(validate_nix_option("max-jobs", "4", &[]).is_ok());
+        assert!(validate_nix_option("sandbox", "false", &[]).is_ok());
+        assert!(validate_nix_option(
+            "extra-substituters",
+            "https://cache.example.org https://nix-community.cachix.org",
+            &[]
+        )
+        .is_ok());
+        assert!(validate_nix_option(
+            "extra-trusted-public-keys",
+            "nix-community.cachix.org-1:mB9FSh9qf2dCimDSUo8Zy7bkq5CX+/rkCWyvRCYg3Fs=",
+            &[]
+        )
+        .is_err());
+
+        let err = validate_nix_option("post-build-hook", "/tmp/x", &[]).unwrap_err();
+        assert!(err.to_string().contains("max-jobs"));
+    }
+
+    #[test]
+    fn test_nix_option_extra_allowed() {
+        let extra = vec!["builders".to_string()];
+        assert!(validate_nix_option("builders", "ssh://builder x86_64-linux", &extra).is_ok());
+        assert!(validate_nix_option("builders", "ssh://builder", &[]).is_err());
+
+        let extra = vec!["extra-trusted-public-keys".to_string()];
+        assert!(validate_nix_option(
+            "extra-trusted-public-keys",
+            "nix-community.cachix.org-1:mB9FSh9qf2dCimDSUo8Zy7bkq5CX+/rkCWyvRCYg3Fs=",
+            &extra
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_nix_option_rejects_bad_values() {
+        assert!(validate_nix_option("cores", "4; rm -rf /", &[]).is_err());
+        assert!(validate_nix_option("substituters", "$(curl evil)", &[]).is_err());
+        assert!(validate_nix_option("cores", "4\n--option", &[]).is_err());
+    }
 }
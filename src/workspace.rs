@@ -0,0 +1,365 @@
+use crate::config::load_config;
+use crate::flake_ref::{percent_decode_path, FlakeRef};
+use crate::validators::validate_path;
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+/// Directories that path parameters must stay inside, from the `[workspace]`
+/// section of config.toml, e.g.
+///
+/// ```toml
+/// [workspace]
+/// roots = ["~/src", "/nix/store"]
+/// use_client_roots = true
+/// ```
+///
+/// With no roots configured and none from the client, the directory the
+/// server was started in is the only root.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorkspaceConfig {
+    #[serde(default)]
+    pub roots: Vec<PathBuf>,
+    /// Also allow the `file://` roots the client reports through `roots/list`
+    /// (default: true)
+    #[serde(default = "default_use_client_roots")]
+    pub use_client_roots: bool,
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        WorkspaceConfig {
+            roots: Vec::new(),
+            use_client_roots: default_use_client_roots(),
+        }
+    }
+}
+
+fn default_use_client_roots() -> bool {
+    true
+}
+
+impl WorkspaceConfig {
+    /// Configured roots with `~` expanded, plus the client's if enabled
+    fn roots(&self) -> Vec<PathBuf> {
+        let mut roots: Vec<PathBuf> = self.roots.iter().map(|root| expand_home(root)).collect();
+        if self.use_client_roots {
+            roots.extend(CLIENT_ROOTS.lock().unwrap().iter().cloned());
+        }
+        if roots.is_empty() {
+            roots.extend(std::env::current_dir().ok());
+        }
+        roots
+    }
+}
+
+/// Roots from the client's last `roots/list` response
+static CLIENT_ROOTS: LazyLock<Mutex<Vec<PathBuf>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Replace the client's roots with those in a `roots/list` result
+pub fn set_client_roots(result: &serde_json::Value) {
+    *CLIENT_ROOTS.lock().unwrap() = parse_roots(result);
+}
+
+/// Directories in a `roots/list` result.
+///
+/// Only `file://` URIs on this host name local directories; anything else
+/// is ignored.
+fn parse_roots(result: &serde_json::Value) -> Vec<PathBuf> {
+    result
+        .get("roots")
+        .and_then(|roots| roots.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|root| file_uri_path(root.get("uri")?.as_str()?))
+        .collect()
+}
+
+/// The path of a `file:///...` or `file://localhost/...` URI, with `%XX`
+/// escapes decoded
+fn file_uri_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let path = rest.strip_prefix("localhost").unwrap_or(rest);
+    if !path.starts_with('/') {
+        return None;
+    }
+    percent_decode_path(path)
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Resolve a path parameter and check that it lies inside a workspace root.
+///
+/// Symlinks are resolved before the check, so a link inside a root can't
+/// point the tool outside it. Paths that don't exist yet, like the link
+/// `fh_fetch` creates, are resolved through their nearest existing parent.
+pub async fn resolve_workspace_path(path: &str) -> Result<PathBuf, String> {
+    let roots = load_config().workspace.roots();
+    check_in_roots(path, &roots).await
+}
+
+/// [`resolve_workspace_path`] as a string to hand to a command, so the
+/// command gets the path that was checked
+pub async fn resolve_workspace_arg(path: &str) -> Result<String, String> {
+    resolve_workspace_path(path)
+        .await?
+        .into_os_string()
+        .into_string()
+        .map_err(|resolved| {
+            format!(
+                "Path '{}' resolves to '{}', which isn't valid UTF-8",
+                path,
+                resolved.to_string_lossy()
+            )
+        })
+}
+
+/// Validate and resolve an optional `flake_dir` parameter, giving the
+/// directory the command should run in
+pub async fn resolve_flake_dir(dir: Option<&str>) -> Result<Option<String>, String> {
+    let Some(dir) = dir else {
+        return Ok(None);
+    };
+    validate_path(dir).map_err(|e| e.to_string())?;
+    resolve_workspace_arg(dir).await.map(Some)
+}
+
+/// Check that a local flake reference, like `.`, `path:../other?dir=sub` or
+/// `/home/user/flake`, stays inside a workspace root.
+///
+/// Relative paths are taken from `flake_dir`, where nix runs. Remote
+/// references and store paths aren't workspace files and always pass.
+pub async fn check_flake_ref_path(
+    flake_ref: &FlakeRef,
+    flake_dir: Option<&str>,
+) -> Result<(), String> {
+    let roots = load_config().workspace.roots();
+    check_flake_ref_in_roots(flake_ref, flake_dir, &roots).await
+}
+
+async fn check_flake_ref_in_roots(
+    flake_ref: &FlakeRef,
+    flake_dir: Option<&str>,
+    roots: &[PathBuf],
+) -> Result<(), String> {
+    let Some(path) = flake_ref.local_path() else {
+        return Ok(());
+    };
+    let mut full = Path::new(flake_dir.unwrap_or(".")).join(path);
+    if let Some(dir) = flake_ref.query.get("dir") {
+        full.push(dir);
+    }
+    if in_store(&full).await {
+        return Ok(());
+    }
+    check_in_roots(&full.to_string_lossy(), roots)
+        .await
+        .map(|_| ())
+}
+
+async fn check_in_roots(path: &str, roots: &[PathBuf]) -> Result<PathBuf, String> {
+    let resolved = resolve_path(Path::new(path))
+        .await
+        .map_err(|e| format!("Failed to resolve path '{}': {}", path, e))?;
+
+    for root in roots {
+        // Roots that don't exist can't contain anything
+        let Ok(root) = tokio::fs::canonicalize(root).await else {
+            continue;
+        };
+        if resolved.starts_with(&root) {
+            return Ok(resolved);
+        }
+    }
+
+    let roots: Vec<String> = roots.iter().map(|r| r.display().to_string()).collect();
+    Err(format!(
+        "Path '{}' resolves to '{}', outside the workspace roots: {}",
+        path,
+        resolved.display(),
+        roots.join(", ")
+    ))
+}
+
+/// Whether `path` is under /nix/store without `..` leading out of it, and
+/// if it exists, without a symlink leading out either.
+///
+/// Store paths that aren't there yet are fine, nix substitutes them.
+async fn in_store(path: &Path) -> bool {
+    if !is_store_path(path) {
+        return false;
+    }
+    match tokio::fs::canonicalize(path).await {
+        Ok(resolved) => resolved.starts_with("/nix/store"),
+        Err(_) => true,
+    }
+}
+
+/// Whether `path` names something under /nix/store, with no `..` in it
+pub fn is_store_path(path: &Path) -> bool {
+    path.starts_with("/nix/store") && !path.components().any(|c| c == Component::ParentDir)
+}
+
+/// Canonicalize `path`, or its nearest existing ancestor with the missing
+/// components appended
+async fn resolve_path(path: &Path) -> std::io::Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match tokio::fs::canonicalize(existing).await {
+            Ok(canonical) => {
+                return Ok(missing.iter().rev().fold(canonical, |p, c| p.join(c)));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        // `..` after a missing directory can't be resolved without it
+        let (Some(parent), Some(Component::Normal(name))) =
+            (existing.parent(), existing.components().next_back())
+        else {
+            return Err(std::io::ErrorKind::NotFound.into());
+        };
+        missing.push(name);
+        existing = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chix-{}-{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_paths_inside_roots() {
+        let root = temp_dir("root");
+        std::fs::create_dir(root.join("flake")).unwrap();
+        let roots = vec![root.clone()];
+
+        let dir = root.join("flake");
+        assert_eq!(
+            check_in_roots(dir.to_str().unwrap(), &roots).await.unwrap(),
+            dir
+        );
+        // A link that doesn't exist yet resolves through its parent
+        let link = root.join("flake/result");
+        assert_eq!(
+            check_in_roots(link.to_str().unwrap(), &roots)
+                .await
+                .unwrap(),
+            link
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_paths_outside_roots() {
+        let root = temp_dir("root");
+        let outside = temp_dir("outside");
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        let roots = vec![root.clone()];
+
+        let err = check_in_roots(outside.to_str().unwrap(), &roots)
+            .await
+            .unwrap_err();
+        assert!(err.contains("outside the workspace roots"), "{}", err);
+
+        // Symlinks and `..` are resolved before the check
+        let escape = root.join("escape");
+        assert!(check_in_roots(escape.to_str().unwrap(), &roots)
+            .await
+            .is_err());
+        let dotdot = format!(
+            "{}/../{}",
+            root.display(),
+            outside.file_name().unwrap().to_str().unwrap()
+        );
+        assert!(check_in_roots(&dotdot, &roots).await.is_err());
+        let missing = format!("{}/missing/../../etc", root.display());
+        assert!(check_in_roots(&missing, &roots).await.is_err());
+
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_dir_all(&outside);
+    }
+
+    #[tokio::test]
+    async fn test_local_flake_refs() {
+        let root = temp_dir("root");
+        let outside = temp_dir("outside");
+        let roots = vec![root.clone()];
+        let dir = root.to_str().unwrap();
+        let check = |flake_ref: String| {
+            let roots = roots.clone();
+            async move {
+                let flake_ref = FlakeRef::parse(&flake_ref).unwrap();
+                check_flake_ref_in_roots(&flake_ref, Some(dir), &roots).await
+            }
+        };
+
+        assert!(check(".".to_string()).await.is_ok());
+        assert!(check("path:./sub?dir=flake".to_string()).await.is_ok());
+        assert!(check("github:NixOS/nixpkgs".to_string()).await.is_ok());
+        assert!(check("/nix/store/abc-source".to_string()).await.is_ok());
+        assert!(check(format!("/nix/store/../..{}", outside.display()))
+            .await
+            .is_err());
+        assert!(check("/nix/store/abc-source?dir=../../..".to_string())
+            .await
+            .is_err());
+
+        let err = check(format!("path:{}", outside.display()))
+            .await
+            .unwrap_err();
+        assert!(err.contains("outside the workspace roots"), "{}", err);
+        assert!(check(outside.display().to_string()).await.is_err());
+        assert!(check(".?dir=../..".to_string()).await.is_err());
+
+        for scheme in ["file", "git+file", "hg+file", "tarball+file"] {
+            let inside = format!("{}://{}", scheme, root.display());
+            assert!(check(inside.clone()).await.is_ok(), "{}", inside);
+            let escape = format!("{}://{}", scheme, outside.display());
+            assert!(check(escape.clone()).await.is_err(), "{}", escape);
+        }
+        let encoded = format!("git+file://{}/%2E%2E", root.display());
+        assert!(check(encoded).await.is_err());
+
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_dir_all(&outside);
+    }
+
+    #[test]
+    fn test_parse_roots() {
+        let roots = parse_roots(&serde_json::json!({
+            "roots": [
+                {"uri": "file:///home/user/project", "name": "project"},
+                {"uri": "https://example.com/repo"}
+            ]
+        }));
+        assert_eq!(roots, vec![PathBuf::from("/home/user/project")]);
+
+        assert_eq!(
+            file_uri_path("file:///home/user/My%20Project"),
+            Some(PathBuf::from("/home/user/My Project"))
+        );
+        assert_eq!(
+            file_uri_path("file://localhost/srv/caf%C3%A9"),
+            Some(PathBuf::from("/srv/café"))
+        );
+        assert_eq!(file_uri_path("file://otherhost/srv"), None);
+        assert_eq!(file_uri_path("file:///bad%2"), None);
+    }
+}